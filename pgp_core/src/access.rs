@@ -0,0 +1,766 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    AssignmentTarget, Expr, FromTable, Ident, ObjectName, Query, SelectItem, SetExpr, Statement,
+    TableFactor, TableWithJoins, Visit, Visitor,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+
+use crate::config::Access;
+use crate::schema::{Catalog, Table};
+
+/// Whether the rules let the assistant use an object, named by its schema,
+/// table and column, as far as they apply. Deny patterns cover everything
+/// under them; allow patterns also make the schema and table they are in
+/// visible.
+pub fn is_allowed(access: &Access, names: &[&str]) -> bool {
+    let parts = |pattern: &str| pattern.split('.').map(str::to_string).collect::<Vec<_>>();
+    let matches = |parts: &[String]| {
+        parts
+            .iter()
+            .zip(names)
+            .all(|(part, name)| part == "*" || part == name)
+    };
+
+    let denied = access.deny.iter().any(|pattern| {
+        let parts = parts(pattern);
+        parts.len() <= names.len() && matches(&parts)
+    });
+
+    !denied && (access.allow.is_empty() || access.allow.iter().any(|p| matches(&parts(p))))
+}
+
+/// The catalog without what the rules hide. Tables keep the names of their
+/// hidden columns, so queries selecting them with `*` can be told apart.
+pub fn filter(catalog: Catalog, access: &Access) -> Catalog {
+    if is_open(access) {
+        return catalog;
+    }
+
+    let mut catalog = Catalog {
+        schemas: catalog
+            .schemas
+            .into_iter()
+            .filter(|schema| is_allowed(access, &[&schema.name]))
+            .map(|mut schema| {
+                schema
+                    .tables
+                    .retain(|t| is_allowed(access, &[&t.schema, &t.name]));
+                schema
+                    .functions
+                    .retain(|f| is_allowed(access, &[&f.schema, &f.name]));
+                for table in &mut schema.tables {
+                    hide_columns(table, access);
+                }
+                schema
+            })
+            .collect(),
+        enums: catalog.enums,
+    };
+
+    // Foreign keys would still name the tables and columns they point to.
+    let visible: BTreeSet<(String, String)> = catalog
+        .tables()
+        .map(|t| (t.schema.clone(), t.name.clone()))
+        .collect();
+
+    for table in catalog.schemas.iter_mut().flat_map(|s| s.tables.iter_mut()) {
+        table.foreign_keys.retain(|fk| {
+            visible.contains(&(fk.schema.clone(), fk.table.clone()))
+                && fk
+                    .referenced
+                    .iter()
+                    .all(|c| is_allowed(access, &[&fk.schema, &fk.table, c]))
+        });
+    }
+
+    catalog
+}
+
+/// Rejects SQL that uses a hidden schema, table or column, going by the
+/// parsed statements rather than the text. Names are looked up in
+/// `namespace` as Postgres would find them, and hidden columns in the
+/// filtered `catalog`.
+pub fn check(
+    sql: &str,
+    access: &Access,
+    namespace: &Namespace,
+    catalog: &Catalog,
+) -> Result<(), String> {
+    if is_open(access) {
+        return Ok(());
+    }
+
+    let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql).map_err(|e| {
+        format!(
+            "the statement cannot be checked against the access rules: {}",
+            e
+        )
+    })?;
+
+    for statement in &statements {
+        if let Statement::SetVariable { variables, .. } = statement {
+            if variables.to_string().to_lowercase().contains("search_path") {
+                return Err("changing the search path is not allowed".to_string());
+            }
+        }
+
+        let mut references = References::default();
+        let _ = statement.visit(&mut references);
+        references.check(access, namespace, catalog)?;
+    }
+
+    Ok(())
+}
+
+fn is_open(access: &Access) -> bool {
+    access.allow.is_empty() && access.deny.is_empty()
+}
+
+/// Whether an allow pattern names the schema itself rather than matching it
+/// with `*`, as system catalogs need.
+fn is_named(access: &Access, names: &[&str]) -> bool {
+    is_allowed(access, names)
+        && access.allow.iter().any(|pattern| {
+            pattern.split('.').next() == Some(names[0])
+                && pattern
+                    .split('.')
+                    .zip(names)
+                    .all(|(part, name)| part == "*" || part == *name)
+        })
+}
+
+/// Schemas that belong to Postgres itself.
+pub(crate) fn is_system(schema: &str) -> bool {
+    schema == "information_schema" || schema.starts_with("pg_")
+}
+
+/// Functions that run SQL they are given as text, which cannot be checked,
+/// and `set_config`, which can change the search path.
+const UNCHECKABLE: [&str; 24] = [
+    "crosstab",
+    "cursor_to_xml",
+    "cursor_to_xmlschema",
+    "database_to_xml",
+    "database_to_xml_and_xmlschema",
+    "database_to_xmlschema",
+    "dblink",
+    "dblink_exec",
+    "dblink_fetch",
+    "dblink_get_result",
+    "dblink_open",
+    "dblink_send_query",
+    "query_to_xml",
+    "query_to_xml_and_xmlschema",
+    "query_to_xmlschema",
+    "schema_to_xml",
+    "schema_to_xml_and_xmlschema",
+    "schema_to_xmlschema",
+    "set_config",
+    "table_to_xml",
+    "table_to_xml_and_xmlschema",
+    "table_to_xmlschema",
+    "ts_stat",
+    "xmlexists_sql",
+];
+
+/// Why a function cannot be allowed whatever the rules say, if it cannot.
+pub(crate) fn uncheckable(function: &[String]) -> Option<String> {
+    let name = function.last()?;
+
+    UNCHECKABLE
+        .contains(&name.as_str())
+        .then(|| match name.as_str() {
+            "set_config" => "function set_config is not allowed".to_string(),
+            _ => format!(
+                "function {} runs SQL given as text, which cannot be checked",
+                name
+            ),
+        })
+}
+
+/// Where names lead in the database: every table, those the access rules
+/// hide included, the schemas Postgres searches for unqualified names and
+/// the tables behind each view.
+#[derive(Debug, Clone, Default)]
+pub struct Namespace {
+    tables: BTreeSet<(String, String)>,
+    search_path: Vec<String>,
+    views: BTreeMap<(String, String), BTreeSet<(String, String)>>,
+}
+
+impl Namespace {
+    /// `catalog` as read from the database, before the access rules,
+    /// `current_schemas(true)` of the connection and the tables each view
+    /// reads.
+    pub fn new(
+        catalog: &Catalog,
+        search_path: Vec<String>,
+        view_tables: Vec<((String, String), (String, String))>,
+    ) -> Self {
+        let mut views: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        for (view, table) in view_tables {
+            views.entry(view).or_default().insert(table);
+        }
+
+        Self {
+            tables: catalog
+                .tables()
+                .map(|t| (t.schema.clone(), t.name.clone()))
+                .collect(),
+            search_path,
+            views,
+        }
+    }
+
+    /// The tables a view reads, none for tables.
+    pub(crate) fn view_tables(&self, view: &(String, String)) -> Vec<&(String, String)> {
+        self.views
+            .get(view)
+            .map(|tables| tables.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Schema and name of a relation. Unqualified names are looked up in
+    /// the schemas of the search path in order, `pg_catalog` first unless
+    /// the path places it, and otherwise taken to be in the first schema
+    /// of the path that is not a system one.
+    pub(crate) fn resolve(&self, relation: &[String]) -> Option<(String, String)> {
+        match relation {
+            [name] => {
+                let implicit = (!self.search_path.iter().any(|s| s == "pg_catalog"))
+                    .then(|| "pg_catalog".to_string());
+                let mut searched = implicit.iter().chain(&self.search_path);
+
+                let schema = searched
+                    .find(|schema| match schema.as_str() {
+                        // Only system relations start with `pg_` there.
+                        "pg_catalog" => name.starts_with("pg_"),
+                        schema => self.tables.contains(&(schema.to_string(), name.clone())),
+                    })
+                    .or_else(|| self.search_path.iter().find(|s| !is_system(s)))
+                    .cloned()
+                    .unwrap_or_else(|| "public".to_string());
+
+                Some((schema, name.clone()))
+            }
+            [.., schema, name] => Some((schema.clone(), name.clone())),
+            [] => None,
+        }
+    }
+
+    /// Why an unqualified name cannot be told apart: it names tables in
+    /// more than one schema, and which one Postgres reads depends on a
+    /// search path that can change.
+    pub(crate) fn ambiguous(&self, relation: &[String]) -> Option<String> {
+        let [name] = relation else {
+            return None;
+        };
+
+        let schemas = self.tables.iter().filter(|(_, t)| t == name).count();
+
+        (schemas > 1).then(|| {
+            format!(
+                "{} is a table in more than one schema, qualify it with its schema",
+                name
+            )
+        })
+    }
+}
+
+fn hide_columns(table: &mut Table, access: &Access) {
+    let (schema, name) = (table.schema.clone(), table.name.clone());
+    let allowed = |column: &str| is_allowed(access, &[&schema, &name, column]);
+
+    table.hidden = table
+        .columns
+        .iter()
+        .filter(|c| !allowed(&c.name))
+        .map(|c| c.name.clone())
+        .collect();
+
+    if table.hidden.is_empty() {
+        return;
+    }
+
+    let hidden = table.hidden.clone();
+    let mentions_hidden = |text: &str| {
+        text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| hidden.iter().any(|h| h == word))
+    };
+
+    table.columns.retain(|c| allowed(&c.name));
+    table.primary_key = table
+        .primary_key
+        .take()
+        .filter(|key| key.columns.iter().all(|c| allowed(c)));
+    table
+        .unique
+        .retain(|key| key.columns.iter().all(|c| allowed(c)));
+    table
+        .foreign_keys
+        .retain(|fk| fk.columns.iter().all(|c| allowed(c)));
+    table.indexes.retain(|index| !mentions_hidden(index));
+}
+
+/// `*`, or `name.*` with the qualifier, and the relations the select it
+/// appears in reads from.
+type Wildcard = (Option<Vec<String>>, Vec<Vec<String>>);
+
+/// Names a statement refers to, collected from its syntax tree.
+#[derive(Default)]
+struct References {
+    relations: Vec<Vec<String>>,
+    /// Table aliases and the relation each stands for.
+    aliases: Vec<(String, Vec<String>)>,
+    ctes: BTreeSet<String>,
+    columns: Vec<Vec<String>>,
+    wildcards: Vec<Wildcard>,
+    /// Function calls, schema-qualified or not.
+    functions: Vec<Vec<String>>,
+}
+
+impl Visitor for References {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.ctes.insert(normalize(&cte.alias.name));
+            }
+        }
+
+        self.set_expr(&query.body);
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<()> {
+        self.relations.push(names(relation));
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        match table_factor {
+            // Functions in `FROM` parse as tables with arguments.
+            TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            }
+            | TableFactor::Function { name, .. } => self.functions.push(names(name)),
+            _ => {}
+        }
+
+        if let TableFactor::Table {
+            name,
+            alias: Some(alias),
+            ..
+        } = table_factor
+        {
+            self.aliases.push((normalize(&alias.name), names(name)));
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) => self.columns.push(vec![normalize(ident)]),
+            Expr::CompoundIdentifier(idents) => {
+                self.columns.push(idents.iter().map(normalize).collect())
+            }
+            Expr::Function(function) => self.functions.push(names(&function.name)),
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<()> {
+        match statement {
+            Statement::Insert(insert) => {
+                let table = names(&insert.table_name);
+                for column in &insert.columns {
+                    self.columns
+                        .push([table.clone(), vec![normalize(column)]].concat());
+                }
+                self.returning(insert.returning.as_deref(), vec![table]);
+            }
+            Statement::Update {
+                table,
+                assignments,
+                returning,
+                ..
+            } => {
+                for assignment in assignments {
+                    let targets = match &assignment.target {
+                        AssignmentTarget::ColumnName(name) => vec![name],
+                        AssignmentTarget::Tuple(names) => names.iter().collect(),
+                    };
+                    self.columns.extend(targets.into_iter().map(names));
+                }
+                let from = relations(std::slice::from_ref(table));
+                self.returning(returning.as_deref(), from);
+            }
+            Statement::Delete(delete) => {
+                let from = match &delete.from {
+                    FromTable::WithFromKeyword(tables) | FromTable::WithoutKeyword(tables) => {
+                        relations(tables)
+                    }
+                };
+                self.returning(delete.returning.as_deref(), from);
+            }
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+impl References {
+    fn set_expr(&mut self, body: &SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                let from = relations(&select.from);
+                self.projection(&select.projection, &from);
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.set_expr(left);
+                self.set_expr(right);
+            }
+            SetExpr::Table(table) => {
+                let relation: Vec<String> = [&table.schema_name, &table.table_name]
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                self.relations.push(relation.clone());
+                self.wildcards.push((Some(relation), vec![]));
+            }
+            _ => {}
+        }
+    }
+
+    fn projection(&mut self, items: &[SelectItem], from: &[Vec<String>]) {
+        for item in items {
+            let qualifier = match item {
+                SelectItem::Wildcard(_) => None,
+                SelectItem::QualifiedWildcard(name, _) => Some(names(name)),
+                _ => continue,
+            };
+            self.wildcards.push((qualifier, from.to_vec()));
+        }
+    }
+
+    fn returning(&mut self, items: Option<&[SelectItem]>, from: Vec<Vec<String>>) {
+        self.projection(items.unwrap_or_default(), &from);
+    }
+
+    /// Tables the statement reads or writes, as schema and name.
+    fn tables(&self, namespace: &Namespace) -> Vec<(String, String)> {
+        self.relations
+            .iter()
+            .filter(|r| !(r.len() == 1 && self.ctes.contains(&r[0])))
+            .filter(|r| !self.functions.contains(r))
+            .filter_map(|r| namespace.resolve(r))
+            .collect()
+    }
+
+    /// The table a qualifier such as an alias or a table name stands for.
+    fn qualifier(&self, qualifier: &[String], namespace: &Namespace) -> Option<(String, String)> {
+        if let [alias] = qualifier {
+            if let Some((_, relation)) = self.aliases.iter().find(|(a, _)| a == alias) {
+                return namespace.resolve(relation);
+            }
+            if self.ctes.contains(alias) {
+                return None;
+            }
+        }
+
+        namespace.resolve(qualifier)
+    }
+
+    fn check(
+        &self,
+        access: &Access,
+        namespace: &Namespace,
+        catalog: &Catalog,
+    ) -> Result<(), String> {
+        if let Some(problem) = self
+            .relations
+            .iter()
+            .filter(|r| !(r.len() == 1 && self.ctes.contains(&r[0])))
+            .find_map(|r| namespace.ambiguous(r))
+        {
+            return Err(problem);
+        }
+
+        let tables = self.tables(namespace);
+
+        for (schema, table) in &tables {
+            if !is_allowed(access, &[schema, table]) {
+                return Err(format!("{}.{} is not accessible", schema, table));
+            }
+            // Statistics and the like show values of every column.
+            if is_system(schema) && !is_named(access, &[schema, table]) {
+                return Err(format!(
+                    "{}.{} is a system catalog, which the access rules do not allow",
+                    schema, table
+                ));
+            }
+        }
+
+        let hidden = |schema: &str, table: &str| -> Vec<String> {
+            catalog
+                .table(schema, table)
+                .map(|t| t.hidden.clone())
+                .unwrap_or_default()
+        };
+
+        for column in &self.columns {
+            let (qualifier, name) = column.split_at(column.len() - 1);
+            let name = &name[0];
+
+            // A bare table name or alias refers to the whole row.
+            let whole_row = qualifier.is_empty()
+                && (self.aliases.iter().any(|(a, _)| a == name)
+                    || tables.iter().any(|(_, t)| t == name));
+
+            if whole_row {
+                if let Some((schema, table)) = self.qualifier(std::slice::from_ref(name), namespace)
+                {
+                    if !hidden(&schema, &table).is_empty() {
+                        return Err(format!(
+                            "{} refers to the whole row of {}.{}, which has hidden columns",
+                            name, schema, table
+                        ));
+                    }
+                }
+                continue;
+            }
+
+            let candidates = match qualifier {
+                [] => tables.clone(),
+                qualifier => self.qualifier(qualifier, namespace).into_iter().collect(),
+            };
+
+            // Only columns the table has count, so output aliases and the
+            // like are not mistaken for hidden columns.
+            for (schema, table) in candidates {
+                if hidden(&schema, &table).contains(name) {
+                    return Err(format!(
+                        "column {} of {}.{} is not accessible",
+                        name, schema, table
+                    ));
+                }
+            }
+        }
+
+        for function in &self.functions {
+            if let Some(problem) = uncheckable(function) {
+                return Err(problem);
+            }
+
+            if let [.., schema, name] = function.as_slice() {
+                if !is_allowed(access, &[schema, name]) {
+                    return Err(format!("function {}.{} is not accessible", schema, name));
+                }
+            }
+        }
+
+        for (qualifier, from) in &self.wildcards {
+            let covered: Vec<(String, String)> = match qualifier {
+                None => from
+                    .iter()
+                    .filter(|r| !(r.len() == 1 && self.ctes.contains(&r[0])))
+                    .filter_map(|r| namespace.resolve(r))
+                    .collect(),
+                Some(qualifier) => self.qualifier(qualifier, namespace).into_iter().collect(),
+            };
+
+            for (schema, table) in covered {
+                if !hidden(&schema, &table).is_empty() {
+                    return Err(format!(
+                        "* would include hidden columns of {}.{}, list the columns instead",
+                        schema, table
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of the tables a `FROM` list reads, joins included.
+fn relations(from: &[TableWithJoins]) -> Vec<Vec<String>> {
+    from.iter()
+        .flat_map(|t| std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation)))
+        .filter_map(|factor| match factor {
+            TableFactor::Table { name, .. } => Some(names(name)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn names(name: &ObjectName) -> Vec<String> {
+    name.0.iter().map(normalize).collect()
+}
+
+/// Identifier as Postgres stores it: folded to lower case unless quoted.
+pub(crate) fn normalize(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::catalog;
+
+    fn access(allow: &[&str], deny: &[&str]) -> Access {
+        Access {
+            allow: allow.iter().map(|p| p.to_string()).collect(),
+            deny: deny.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// Checks `sql` with `users.ssn` hidden and the `secret` schema and
+    /// table denied, on a server searching `secret` before `public`.
+    fn checked(sql: &str) -> Result<(), String> {
+        checked_with(
+            &access(&[], &["public.users.ssn", "public.secret", "secret"]),
+            sql,
+        )
+    }
+
+    fn checked_with(access: &Access, sql: &str) -> Result<(), String> {
+        let (namespace, catalog) = users(access);
+        check(sql, access, &namespace, &catalog)
+    }
+
+    fn users(access: &Access) -> (Namespace, Catalog) {
+        let catalog = catalog(&[
+            ("public.users", &["id", "name", "ssn"]),
+            ("public.secret", &["id"]),
+            ("secret.salaries", &["id", "amount"]),
+        ]);
+        let search_path = vec![
+            "pg_catalog".to_string(),
+            "secret".to_string(),
+            "public".to_string(),
+        ];
+
+        (
+            Namespace::new(&catalog, search_path, vec![]),
+            filter(catalog, access),
+        )
+    }
+
+    #[test]
+    fn hides_denied_tables_and_columns() {
+        let (_, catalog) = users(&access(&[], &["public.users.ssn", "public.secret"]));
+        let users = catalog.table("public", "users").unwrap();
+
+        assert!(catalog.table("public", "secret").is_none());
+        assert_eq!(users.hidden, ["ssn"]);
+        assert!(users.columns.iter().all(|c| c.name != "ssn"));
+    }
+
+    #[test]
+    fn refuses_hidden_columns_through_aliases() {
+        assert!(checked("SELECT u.name FROM users u").is_ok());
+        assert!(checked("SELECT u.ssn FROM users u").is_err());
+        assert!(checked("SELECT x.ssn FROM users AS x").is_err());
+        assert!(checked("SELECT ssn AS id FROM users").is_err());
+    }
+
+    #[test]
+    fn refuses_wildcards_and_whole_rows_with_hidden_columns() {
+        assert!(checked("SELECT * FROM users").is_err());
+        assert!(checked("SELECT u.* FROM users u").is_err());
+        assert!(checked("SELECT row_to_json(u) FROM users u").is_err());
+    }
+
+    #[test]
+    fn refuses_table_commands_on_hidden_tables() {
+        assert!(checked("SELECT id FROM users UNION TABLE secret").is_err());
+        assert!(checked("SELECT id FROM users UNION TABLE users").is_err());
+    }
+
+    #[test]
+    fn refuses_names_in_more_than_one_schema() {
+        let access = access(&[], &["public.secret"]);
+        let catalog = catalog(&[("analytics.orders", &["id"]), ("public.orders", &["id"])]);
+        let namespace = Namespace::new(&catalog, vec!["public".to_string()], vec![]);
+        let check = |sql| check(sql, &access, &namespace, &catalog);
+
+        assert!(check("SELECT id FROM orders").is_err());
+        assert!(check("SELECT id FROM public.orders").is_ok());
+        assert!(check("WITH orders AS (SELECT 1 AS id) SELECT id FROM orders").is_ok());
+    }
+
+    #[test]
+    fn resolves_names_along_the_search_path() {
+        // Only `secret` has the table, and the server searches it.
+        assert!(checked("SELECT amount FROM salaries").is_err());
+        assert!(checked("SELECT amount FROM secret.salaries").is_err());
+    }
+
+    #[test]
+    fn refuses_system_catalogs_unless_named() {
+        assert!(checked("SELECT most_common_vals FROM pg_stats").is_err());
+        assert!(checked("SELECT * FROM pg_catalog.pg_stats").is_err());
+        assert!(checked("SELECT * FROM information_schema.columns").is_err());
+
+        let open = access(&["public.*"], &[]);
+        assert!(checked_with(&open, "SELECT * FROM pg_stats").is_err());
+        assert!(checked_with(&open, "SELECT * FROM public.users").is_ok());
+
+        let named = access(&["public.*", "pg_catalog.pg_settings"], &[]);
+        assert!(checked_with(&named, "SELECT name FROM pg_settings").is_ok());
+        assert!(checked_with(&named, "SELECT * FROM pg_stats").is_err());
+    }
+
+    #[test]
+    fn refuses_functions_that_run_sql_text() {
+        for sql in [
+            "SELECT query_to_xml('SELECT ssn FROM users', true, true, '')",
+            "SELECT table_to_xml('users', true, true, '')",
+            "SELECT cursor_to_xml('c', 10, true, true, '')",
+            "SELECT pg_catalog.query_to_xml('SELECT 1', true, true, '')",
+            "SELECT * FROM dblink('dbname=postgres', 'SELECT ssn FROM users') AS t(ssn text)",
+            "SELECT dblink_exec('dbname=postgres', 'DROP TABLE users')",
+            "SELECT * FROM ts_stat('SELECT to_tsvector(ssn) FROM users')",
+        ] {
+            assert!(checked(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn refuses_search_path_changes() {
+        assert!(checked("SET search_path TO secret").is_err());
+        assert!(checked("SELECT set_config('search_path', 'secret', false)").is_err());
+    }
+
+    #[test]
+    fn allows_other_functions() {
+        assert!(checked("SELECT lower(name), count(*) FROM users GROUP BY 1").is_ok());
+        assert!(checked("SELECT * FROM generate_series(1, 3)").is_ok());
+    }
+
+    #[test]
+    fn matches_patterns_by_part() {
+        let access = access(&["public.*"], &["public.users.ssn"]);
+
+        assert!(is_allowed(&access, &["public", "users", "name"]));
+        assert!(!is_allowed(&access, &["public", "users", "ssn"]));
+        assert!(!is_allowed(&access, &["analytics", "daily"]));
+    }
+}
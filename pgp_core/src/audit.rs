@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, KeyInit, Mac};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::Audit;
+use crate::connection::Connection;
+use crate::errors::Error;
+
+/// `prev` of the first entry of a log.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Sequence number and hash of the last entry of each open log, so
+/// appending does not have to read the file again.
+static HEADS: Mutex<BTreeMap<PathBuf, (u64, String)>> = Mutex::new(BTreeMap::new());
+
+/// One executed statement. `hash` covers every other field, `prev` included,
+/// which chains each entry to the one before it. With a key it is an HMAC.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub connection: String,
+    pub os_user: String,
+    /// The question the statement was generated for; none for SQL typed by
+    /// the user.
+    pub question: Option<String>,
+    pub model: Option<String>,
+    pub statement: String,
+    pub rows: Option<u64>,
+    pub duration_ms: u64,
+    pub outcome: String,
+    pub prev: String,
+    pub hash: String,
+}
+
+/// What a statement did, as recorded in the log.
+#[derive(Debug, Clone)]
+pub struct Execution<'a> {
+    pub statement: &'a str,
+    pub rows: Option<u64>,
+    pub duration: Duration,
+    pub outcome: String,
+}
+
+/// The audit log of a session.
+#[derive(Debug, Clone)]
+pub struct Log {
+    enabled: bool,
+    path: PathBuf,
+    connection: String,
+    os_user: String,
+    key: Option<String>,
+    /// Set once an entry could not be written, for every copy of the log.
+    failed: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The line is not an entry.
+    Unreadable(usize),
+    /// Entries are missing before this one, or it is out of order.
+    Sequence {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+    /// The entry does not follow the hash of the one before it.
+    Broken(usize),
+    /// The entry was changed after it was written.
+    Altered(usize),
+    /// The log ends before the last entry written to it.
+    Truncated { expected: u64, found: u64 },
+    /// The head recording how far the log went is gone.
+    MissingHead,
+    /// The head was not written with the key.
+    ForgedHead,
+}
+
+/// What checking a log found.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub entries: u64,
+    pub last_hash: Option<String>,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Unreadable(line) => write!(f, "line {}: not an audit entry", line),
+            Problem::Sequence {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected entry {}, found {}",
+                line, expected, found
+            ),
+            Problem::Broken(line) => {
+                write!(f, "line {}: does not follow the previous entry", line)
+            }
+            Problem::Altered(line) => write!(f, "line {}: entry was modified", line),
+            Problem::Truncated { expected, found } => write!(
+                f,
+                "log ends at entry {} but {} were written",
+                found, expected
+            ),
+            Problem::MissingHead => write!(f, "the head file is missing"),
+            Problem::ForgedHead => write!(f, "the head file was not written with the key"),
+        }
+    }
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Log {
+    /// The log for a connection. Fails when an enabled log cannot be
+    /// written, so nothing runs unrecorded.
+    pub fn new(settings: &Audit, connection: &Connection) -> Result<Self, Error> {
+        if settings.enabled {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&settings.path)
+                .map_err(|_| Error::AuditError)?;
+        }
+
+        Ok(Self {
+            enabled: settings.enabled,
+            path: settings.path.clone(),
+            connection: format!(
+                "{}@{}:{}/{}",
+                connection.username, connection.host, connection.port, connection.database
+            ),
+            os_user: whoami::username(),
+            key: settings.key.clone(),
+            failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Fails once an entry could not be written, so nothing more runs
+    /// unrecorded.
+    pub fn ready(&self) -> Result<(), Error> {
+        match self.failed.load(Ordering::SeqCst) {
+            true => Err(Error::AuditError),
+            false => Ok(()),
+        }
+    }
+
+    /// Appends an entry for an executed statement. After a failed write the
+    /// log stays failed.
+    pub fn record(
+        &self,
+        question: Option<&str>,
+        model: Option<&str>,
+        execution: Execution,
+    ) -> Result<(), Error> {
+        self.ready()?;
+
+        let written = self.write(question, model, execution);
+        if written.is_err() {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+
+        written
+    }
+
+    fn write(
+        &self,
+        question: Option<&str>,
+        model: Option<&str>,
+        execution: Execution,
+    ) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let mut heads = HEADS.lock();
+
+        let (seq, prev) = match heads.get(&self.path) {
+            Some(head) => head.clone(),
+            None => read_head(&self.path)?,
+        };
+
+        let mut entry = Entry {
+            seq: seq + 1,
+            timestamp: Utc::now().to_rfc3339(),
+            connection: self.connection.clone(),
+            os_user: self.os_user.clone(),
+            question: question.map(str::to_string),
+            model: model.map(str::to_string),
+            statement: execution.statement.to_string(),
+            rows: execution.rows,
+            duration_ms: execution.duration.as_millis() as u64,
+            outcome: execution.outcome,
+            prev,
+            hash: String::new(),
+        };
+        let key = self.key.as_deref();
+        entry.hash = digest(&entry, key)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|_| Error::AuditError)?;
+
+        writeln!(file, "{}", serde_json::to_string(&entry)?).map_err(|_| Error::AuditError)?;
+        file.sync_data().map_err(|_| Error::AuditError)?;
+
+        // The head records how far the log went, which the chain alone
+        // cannot tell when entries are cut from its end.
+        fs::write(head_path(&self.path), head(entry.seq, &entry.hash, key))
+            .map_err(|_| Error::AuditError)?;
+
+        heads.insert(self.path.clone(), (entry.seq, entry.hash));
+
+        Ok(())
+    }
+}
+
+/// Checks every entry of a log against the one before it and against its
+/// own hash, and the end of the log against the recorded head. `key` is the
+/// one the log was written with, if any.
+pub fn verify(path: &Path, key: Option<&str>) -> Result<Report, Error> {
+    let contents = fs::read_to_string(path)?;
+    let mut report = Report::default();
+    let mut expected = (1, GENESIS.to_string());
+
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let Ok(entry) = serde_json::from_str::<Entry>(line) else {
+            report.problems.push(Problem::Unreadable(line_number));
+            continue;
+        };
+
+        if entry.seq != expected.0 {
+            report.problems.push(Problem::Sequence {
+                line: line_number,
+                expected: expected.0,
+                found: entry.seq,
+            });
+        }
+        if entry.prev != expected.1 {
+            report.problems.push(Problem::Broken(line_number));
+        }
+        if digest(&entry, key)? != entry.hash {
+            report.problems.push(Problem::Altered(line_number));
+        }
+
+        report.entries += 1;
+        report.last_hash = Some(entry.hash.clone());
+        expected = (entry.seq + 1, entry.hash);
+    }
+
+    let found = expected.0 - 1;
+
+    match fs::read_to_string(head_path(path)) {
+        Ok(contents) => {
+            let mut parts = contents.split_whitespace();
+            let seq: u64 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+            let hash = parts.next().unwrap_or_default();
+
+            if contents != head(seq, hash, key) {
+                report.problems.push(Problem::ForgedHead);
+            }
+            if seq != found || Some(hash) != report.last_hash.as_deref() {
+                report.problems.push(Problem::Truncated {
+                    expected: seq,
+                    found,
+                });
+            }
+        }
+        Err(_) if found > 0 => report.problems.push(Problem::MissingHead),
+        Err(_) => {}
+    }
+
+    Ok(report)
+}
+
+/// Hash of an entry with every field but `hash` itself.
+fn digest(entry: &Entry, key: Option<&str>) -> Result<String, Error> {
+    let unsigned = Entry {
+        hash: String::new(),
+        ..entry.clone()
+    };
+
+    Ok(sign(serde_json::to_string(&unsigned)?.as_bytes(), key))
+}
+
+/// Contents of the head file for the last entry. With a key, the head is
+/// signed so it cannot be rewritten to match a cut log.
+fn head(seq: u64, hash: &str, key: Option<&str>) -> String {
+    let head = format!("{} {}", seq, hash);
+
+    match key {
+        Some(_) => format!("{} {}\n", head, sign(head.as_bytes(), key)),
+        None => format!("{}\n", head),
+    }
+}
+
+/// SHA-256 of the data, or its HMAC with the key, in hex.
+fn sign(data: &[u8], key: Option<&str>) -> String {
+    let digest: Vec<u8> = match key {
+        Some(key) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => Sha256::digest(data).to_vec(),
+    };
+
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Sequence number and hash of the last entry in the file.
+fn read_head(path: &Path) -> Result<(u64, String), Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(_) => return Err(Error::AuditError),
+    };
+
+    match contents.lines().rev().find(|line| !line.trim().is_empty()) {
+        Some(line) => {
+            let entry: Entry = serde_json::from_str(line).map_err(|_| Error::AuditError)?;
+            Ok((entry.seq, entry.hash))
+        }
+        None => Ok((0, GENESIS.to_string())),
+    }
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".head");
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A log of `statements` in a file of its own, written with `key`.
+    fn written(name: &str, key: Option<&str>, statements: &[&str]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pgp_audit_{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(head_path(&path));
+        HEADS.lock().remove(&path);
+
+        let log = Log {
+            enabled: true,
+            path: path.clone(),
+            connection: "postgres@localhost:5432/postgres".to_string(),
+            os_user: "test".to_string(),
+            key: key.map(str::to_string),
+            failed: Arc::new(AtomicBool::new(false)),
+        };
+
+        for statement in statements {
+            let execution = Execution {
+                statement,
+                rows: Some(1),
+                duration: Duration::from_millis(1),
+                outcome: "ok".to_string(),
+            };
+            log.record(Some("question"), Some("model"), execution)
+                .unwrap();
+        }
+
+        path
+    }
+
+    fn remove(path: &Path) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(head_path(path));
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn verifies_an_intact_log() {
+        let path = written("intact", None, &["SELECT 1", "SELECT 2", "SELECT 3"]);
+        let report = verify(&path, None).unwrap();
+        remove(&path);
+
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert_eq!(report.entries, 3);
+    }
+
+    #[test]
+    fn finds_altered_entries() {
+        let path = written("altered", None, &["SELECT 1", "SELECT 2"]);
+        let contents = fs::read_to_string(&path)
+            .unwrap()
+            .replace("SELECT 1", "SELECT 9");
+        fs::write(&path, contents).unwrap();
+        let report = verify(&path, None).unwrap();
+        remove(&path);
+
+        assert_eq!(report.problems, [Problem::Altered(1)]);
+    }
+
+    #[test]
+    fn finds_removed_entries() {
+        let path = written("removed", None, &["SELECT 1", "SELECT 2", "SELECT 3"]);
+        let lines = lines(&path);
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let report = verify(&path, None).unwrap();
+        remove(&path);
+
+        assert_eq!(
+            report.problems,
+            [
+                Problem::Sequence {
+                    line: 2,
+                    expected: 2,
+                    found: 3
+                },
+                Problem::Broken(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_a_cut_log() {
+        let path = written("cut", None, &["SELECT 1", "SELECT 2", "SELECT 3"]);
+        let lines = lines(&path);
+        fs::write(&path, format!("{}\n", lines[0])).unwrap();
+        let report = verify(&path, None).unwrap();
+        remove(&path);
+
+        assert_eq!(
+            report.problems,
+            [Problem::Truncated {
+                expected: 3,
+                found: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_a_cut_log_without_its_head() {
+        let path = written("headless", None, &["SELECT 1", "SELECT 2"]);
+        let lines = lines(&path);
+        fs::write(&path, format!("{}\n", lines[0])).unwrap();
+        fs::remove_file(head_path(&path)).unwrap();
+        let report = verify(&path, None).unwrap();
+        remove(&path);
+
+        assert_eq!(report.problems, [Problem::MissingHead]);
+    }
+
+    #[test]
+    fn finds_a_log_rewritten_without_the_key() {
+        let path = written("keyed", Some("secret"), &["SELECT 1", "SELECT 2"]);
+        assert!(verify(&path, Some("secret")).unwrap().is_valid());
+        assert!(!verify(&path, Some("other")).unwrap().is_valid());
+
+        // Cutting the log and writing a matching head needs the key.
+        let lines = lines(&path);
+        let first: Entry = serde_json::from_str(&lines[0]).unwrap();
+        fs::write(&path, format!("{}\n", lines[0])).unwrap();
+        fs::write(head_path(&path), head(first.seq, &first.hash, None)).unwrap();
+        let report = verify(&path, Some("secret")).unwrap();
+        remove(&path);
+
+        assert_eq!(report.problems, [Problem::ForgedHead]);
+    }
+}
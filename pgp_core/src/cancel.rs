@@ -0,0 +1,78 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tokio_postgres::{CancelToken, NoTls};
+
+use crate::errors::Error;
+
+/// Aborts the in-flight turn of a session.
+///
+/// The model call is interrupted locally, while a statement that is already
+/// running on the server is stopped with a Postgres cancel request, so the
+/// connection stays open and the session can be used for the next turn.
+#[derive(Clone)]
+pub struct Canceller {
+    token: CancelToken,
+    ssl: bool,
+    requested: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Canceller {
+    pub fn new(token: CancelToken, ssl: bool) -> Self {
+        Self {
+            token,
+            ssl,
+            requested: Arc::new(AtomicBool::new(false)),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub async fn cancel(&self) -> Result<(), Error> {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+
+        self.cancel_query().await
+    }
+
+    /// Stops the statement running on the server without ending the turn.
+    pub async fn cancel_query(&self) -> Result<(), Error> {
+        if self.ssl {
+            self.token.cancel_query(crate::tls_connector()?).await?;
+        } else {
+            self.token.cancel_query(NoTls).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reset(&self) {
+        self.requested.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` is called.
+    pub async fn cancelled(&self) {
+        let notified = self.notify.notified();
+
+        if self.is_cancelled() {
+            return;
+        }
+
+        notified.await
+    }
+}
+
+impl fmt::Debug for Canceller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Canceller")
+            .field("ssl", &self.ssl)
+            .field("requested", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,859 @@
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{self, fontdb, TreeParsing, TreePostProc};
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::errors::Error;
+use crate::results::{ResultSet, Value};
+
+/// Size used when a chart is saved to a file.
+pub const EXPORT_WIDTH: f32 = 800.0;
+pub const EXPORT_HEIGHT: f32 = 400.0;
+
+const MARGIN_LEFT: f32 = 64.0;
+const MARGIN_RIGHT: f32 = 16.0;
+const MARGIN_TOP: f32 = 16.0;
+const MARGIN_BOTTOM: f32 = 40.0;
+const TICKS: usize = 5;
+const MAX_CATEGORIES: usize = 50;
+const MAX_SLICES: usize = 12;
+const LABEL_WIDTH: f32 = 60.0;
+
+pub type Color = [u8; 3];
+
+pub const AXIS: Color = [128, 128, 128];
+pub const PALETTE: [Color; 8] = [
+    [76, 120, 168],
+    [245, 133, 24],
+    [228, 87, 86],
+    [114, 183, 178],
+    [84, 162, 75],
+    [238, 202, 59],
+    [178, 121, 162],
+    [255, 157, 166],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartKind {
+    Bar,
+    Line,
+    Scatter,
+    Pie,
+}
+
+impl ChartKind {
+    pub const ALL: [ChartKind; 4] = [
+        ChartKind::Bar,
+        ChartKind::Line,
+        ChartKind::Scatter,
+        ChartKind::Pie,
+    ];
+}
+
+impl fmt::Display for ChartKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChartKind::Bar => write!(f, "Bar"),
+            ChartKind::Line => write!(f, "Line"),
+            ChartKind::Scatter => write!(f, "Scatter"),
+            ChartKind::Pie => write!(f, "Pie"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartFormat {
+    Png,
+    Svg,
+}
+
+impl ChartFormat {
+    pub const ALL: [ChartFormat; 2] = [ChartFormat::Png, ChartFormat::Svg];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChartFormat::Png => "png",
+            ChartFormat::Svg => "svg",
+        }
+    }
+}
+
+impl fmt::Display for ChartFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChartFormat::Png => write!(f, "PNG"),
+            ChartFormat::Svg => write!(f, "SVG"),
+        }
+    }
+}
+
+/// Which chart to draw and which columns go on the axes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChartSpec {
+    pub kind: ChartKind,
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Temporal,
+    Numeric,
+    Category,
+}
+
+impl ColumnKind {
+    pub fn of(type_name: &str) -> Self {
+        match type_name {
+            "date" | "time" | "timestamp" | "timestamptz" => ColumnKind::Temporal,
+            "int2" | "int4" | "int8" | "oid" | "float4" | "float8" | "numeric" => {
+                ColumnKind::Numeric
+            }
+            _ => ColumnKind::Category,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Drawing primitives in pixel coordinates with the origin at the top left.
+/// The same shapes back the canvas in the UI and the exported files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+        color: Color,
+    },
+    Polyline {
+        points: Vec<(f32, f32)>,
+        color: Color,
+    },
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+    },
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+        color: Color,
+    },
+    /// A pie slice, angles in radians clockwise from the positive x axis.
+    Wedge {
+        center: (f32, f32),
+        radius: f32,
+        start: f32,
+        end: f32,
+        color: Color,
+    },
+    Text {
+        position: (f32, f32),
+        content: String,
+        anchor: Anchor,
+        color: Color,
+    },
+}
+
+struct Point {
+    x: f64,
+    y: f64,
+    label: String,
+}
+
+/// Picks a chart from the column types: time and a number make a line,
+/// a category and a number make a bar chart, two numbers a scatter plot.
+pub fn suggest(result: &ResultSet) -> Option<ChartSpec> {
+    let kinds: Vec<_> = result
+        .columns
+        .iter()
+        .map(|c| ColumnKind::of(&c.type_name))
+        .collect();
+
+    let find = |kind, skip: Option<usize>| {
+        kinds
+            .iter()
+            .enumerate()
+            .position(|(i, k)| *k == kind && Some(i) != skip)
+    };
+
+    let y = find(ColumnKind::Numeric, None)?;
+
+    if let Some(x) = find(ColumnKind::Temporal, None) {
+        return Some(ChartSpec {
+            kind: ChartKind::Line,
+            x,
+            y,
+        });
+    }
+
+    if let Some(x) = find(ColumnKind::Category, None) {
+        return Some(ChartSpec {
+            kind: ChartKind::Bar,
+            x,
+            y,
+        });
+    }
+
+    let y2 = find(ColumnKind::Numeric, Some(y))?;
+
+    Some(ChartSpec {
+        kind: ChartKind::Scatter,
+        x: y,
+        y: y2,
+    })
+}
+
+pub fn render(result: &ResultSet, spec: ChartSpec, width: f32, height: f32) -> Vec<Shape> {
+    if spec.x >= result.columns.len() || spec.y >= result.columns.len() {
+        return vec![];
+    }
+
+    match spec.kind {
+        ChartKind::Bar => bar(&categories(result, spec, MAX_CATEGORIES), width, height),
+        ChartKind::Pie => pie(&categories(result, spec, MAX_SLICES), width, height),
+        ChartKind::Line | ChartKind::Scatter => xy(result, spec, width, height),
+    }
+}
+
+pub fn to_svg(shapes: &[Shape], width: f32, height: f32) -> String {
+    let rgb = |c: &Color| format!("rgb({},{},{})", c[0], c[1], c[2]);
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    );
+    svg.push_str(r#"<rect width="100%" height="100%" fill="white"/>"#);
+
+    for shape in shapes {
+        let element = match shape {
+            Shape::Line { from, to, color } => format!(
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}"/>"#,
+                from.0,
+                from.1,
+                to.0,
+                to.1,
+                rgb(color)
+            ),
+            Shape::Polyline { points, color } => format!(
+                r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                points
+                    .iter()
+                    .map(|(x, y)| format!("{},{}", x, y))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                rgb(color)
+            ),
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                x,
+                y,
+                width,
+                height,
+                rgb(color)
+            ),
+            Shape::Circle {
+                center,
+                radius,
+                color,
+            } => format!(
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                center.0,
+                center.1,
+                radius,
+                rgb(color)
+            ),
+            // An arc that ends where it starts draws nothing, so a full
+            // sweep is drawn as a circle.
+            Shape::Wedge {
+                center,
+                radius,
+                start,
+                end,
+                color,
+            } if end - start >= TAU - 1e-4 => format!(
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                center.0,
+                center.1,
+                radius,
+                rgb(color)
+            ),
+            Shape::Wedge {
+                center,
+                radius,
+                start,
+                end,
+                color,
+            } => {
+                let (x1, y1) = polar(*center, *radius, *start);
+                let (x2, y2) = polar(*center, *radius, *end);
+                let large = if end - start > std::f32::consts::PI {
+                    1
+                } else {
+                    0
+                };
+
+                format!(
+                    r#"<path d="M {} {} L {} {} A {} {} 0 {} 1 {} {} Z" fill="{}"/>"#,
+                    center.0,
+                    center.1,
+                    x1,
+                    y1,
+                    radius,
+                    radius,
+                    large,
+                    x2,
+                    y2,
+                    rgb(color)
+                )
+            }
+            Shape::Text {
+                position,
+                content,
+                anchor,
+                color,
+            } => format!(
+                r#"<text x="{}" y="{}" text-anchor="{}" dominant-baseline="middle" font-family="Helvetica, Arial, DejaVu Sans, sans-serif" font-size="11" fill="{}">{}</text>"#,
+                position.0,
+                position.1,
+                match anchor {
+                    Anchor::Start => "start",
+                    Anchor::Middle => "middle",
+                    Anchor::End => "end",
+                },
+                rgb(color),
+                escape_xml(content)
+            ),
+        };
+
+        svg.push_str(&element);
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+pub async fn save(
+    result: ResultSet,
+    spec: ChartSpec,
+    format: ChartFormat,
+    path: PathBuf,
+) -> Result<PathBuf, Error> {
+    tokio::task::spawn_blocking(move || {
+        let shapes = render(&result, spec, EXPORT_WIDTH, EXPORT_HEIGHT);
+        let svg = to_svg(&shapes, EXPORT_WIDTH, EXPORT_HEIGHT);
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|_| Error::ExportError)?;
+        }
+
+        match format {
+            ChartFormat::Svg => std::fs::write(&path, svg).map_err(|_| Error::ExportError)?,
+            ChartFormat::Png => rasterize(&svg)?
+                .save_png(&path)
+                .map_err(|_| Error::ExportError)?,
+        }
+
+        Ok(path)
+    })
+    .await
+    .map_err(|_| Error::ExportError)?
+}
+
+fn rasterize(svg: &str) -> Result<Pixmap, Error> {
+    let mut tree =
+        usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|_| Error::ExportError)?;
+
+    let mut fonts = fontdb::Database::new();
+    fonts.load_system_fonts();
+    tree.postprocess(usvg::PostProcessingSteps::default(), &fonts);
+
+    let mut pixmap =
+        Pixmap::new(EXPORT_WIDTH as u32, EXPORT_HEIGHT as u32).ok_or(Error::ExportError)?;
+    resvg::render(&tree, Transform::default(), &mut pixmap.as_mut());
+
+    Ok(pixmap)
+}
+
+/// Labels of the x column with the y values summed per label, in the order
+/// they first appear. Anything past `limit` is folded into "Other".
+fn categories(result: &ResultSet, spec: ChartSpec, limit: usize) -> Vec<Point> {
+    let mut points: Vec<Point> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for row in &result.rows {
+        let Some(y) = row[spec.y].as_f64() else {
+            continue;
+        };
+
+        let label = row[spec.x].to_string();
+
+        match index.get(&label) {
+            Some(&i) => points[i].y += y,
+            None => {
+                index.insert(label.clone(), points.len());
+                points.push(Point {
+                    x: points.len() as f64,
+                    y,
+                    label,
+                });
+            }
+        }
+    }
+
+    if points.len() > limit {
+        let other: f64 = points[limit - 1..].iter().map(|p| p.y).sum();
+        points.truncate(limit - 1);
+        points.push(Point {
+            x: (limit - 1) as f64,
+            y: other,
+            label: "Other".to_string(),
+        });
+    }
+
+    points
+}
+
+fn xy_points(result: &ResultSet, spec: ChartSpec) -> Vec<Point> {
+    let kind = ColumnKind::of(&result.columns[spec.x].type_name);
+
+    let mut points: Vec<Point> = result
+        .rows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, row)| {
+            let x = match kind {
+                ColumnKind::Temporal => seconds(&row[spec.x])?,
+                ColumnKind::Numeric => row[spec.x].as_f64()?,
+                ColumnKind::Category => i as f64,
+            };
+
+            Some(Point {
+                x,
+                y: row[spec.y].as_f64()?,
+                label: row[spec.x].to_string(),
+            })
+        })
+        .collect();
+
+    if spec.kind == ChartKind::Line {
+        points.sort_by(|a, b| a.x.total_cmp(&b.x));
+    }
+
+    points
+}
+
+fn seconds(value: &Value) -> Option<f64> {
+    match value {
+        Value::Date(d) => Some(d.and_hms_opt(0, 0, 0)?.and_utc().timestamp() as f64),
+        Value::Timestamp(t) => Some(t.and_utc().timestamp() as f64),
+        Value::TimestampTz(t) => Some(t.timestamp() as f64),
+        Value::Time(t) => {
+            use chrono::Timelike;
+            Some(t.num_seconds_from_midnight() as f64)
+        }
+        _ => None,
+    }
+}
+
+struct Plot {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Plot {
+    fn new(width: f32, height: f32, y_min: f64, y_max: f64) -> Self {
+        let (y_min, y_max) = if y_min == y_max {
+            (y_min - 1.0, y_max + 1.0)
+        } else {
+            (y_min, y_max)
+        };
+
+        Self {
+            left: MARGIN_LEFT,
+            right: (width - MARGIN_RIGHT).max(MARGIN_LEFT + 1.0),
+            top: MARGIN_TOP,
+            bottom: (height - MARGIN_BOTTOM).max(MARGIN_TOP + 1.0),
+            y_min,
+            y_max,
+        }
+    }
+
+    fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    fn y(&self, value: f64) -> f32 {
+        let ratio = (value - self.y_min) / (self.y_max - self.y_min);
+        self.bottom - ratio as f32 * (self.bottom - self.top)
+    }
+
+    /// Horizontal grid lines with value labels and the two axes.
+    fn axes(&self, shapes: &mut Vec<Shape>) {
+        for tick in ticks(self.y_min, self.y_max) {
+            let y = self.y(tick);
+
+            shapes.push(Shape::Line {
+                from: (self.left, y),
+                to: (self.right, y),
+                color: [224, 224, 224],
+            });
+            shapes.push(Shape::Text {
+                position: (self.left - 6.0, y),
+                content: format_number(tick),
+                anchor: Anchor::End,
+                color: AXIS,
+            });
+        }
+
+        shapes.push(Shape::Line {
+            from: (self.left, self.top),
+            to: (self.left, self.bottom),
+            color: AXIS,
+        });
+        shapes.push(Shape::Line {
+            from: (self.left, self.bottom),
+            to: (self.right, self.bottom),
+            color: AXIS,
+        });
+    }
+
+    fn x_label(&self, shapes: &mut Vec<Shape>, x: f32, label: &str) {
+        shapes.push(Shape::Text {
+            position: (x, self.bottom + 14.0),
+            content: shorten(label, 12),
+            anchor: Anchor::Middle,
+            color: AXIS,
+        });
+    }
+}
+
+fn bar(points: &[Point], width: f32, height: f32) -> Vec<Shape> {
+    let mut shapes = vec![];
+
+    if points.is_empty() {
+        return shapes;
+    }
+
+    let y_min = points.iter().map(|p| p.y).fold(0.0, f64::min);
+    let y_max = points.iter().map(|p| p.y).fold(0.0, f64::max);
+    let plot = Plot::new(width, height, y_min, y_max);
+    plot.axes(&mut shapes);
+
+    let band = plot.width() / points.len() as f32;
+    let every = (LABEL_WIDTH / band).ceil().max(1.0) as usize;
+    let zero = plot.y(0.0);
+
+    for (i, point) in points.iter().enumerate() {
+        let x = plot.left + band * i as f32;
+        let y = plot.y(point.y);
+
+        shapes.push(Shape::Rect {
+            x: x + band * 0.1,
+            y: y.min(zero),
+            width: band * 0.8,
+            height: (y - zero).abs(),
+            color: PALETTE[0],
+        });
+
+        if i % every == 0 {
+            plot.x_label(&mut shapes, x + band / 2.0, &point.label);
+        }
+    }
+
+    shapes
+}
+
+fn xy(result: &ResultSet, spec: ChartSpec, width: f32, height: f32) -> Vec<Shape> {
+    let points = xy_points(result, spec);
+    let mut shapes = vec![];
+
+    if points.is_empty() {
+        return shapes;
+    }
+
+    let y_min = points.iter().map(|p| p.y).fold(f64::INFINITY, f64::min);
+    let y_max = points.iter().map(|p| p.y).fold(f64::NEG_INFINITY, f64::max);
+    let x_min = points.iter().map(|p| p.x).fold(f64::INFINITY, f64::min);
+    let x_max = points.iter().map(|p| p.x).fold(f64::NEG_INFINITY, f64::max);
+    let x_span = if x_max > x_min { x_max - x_min } else { 1.0 };
+
+    let plot = Plot::new(width, height, y_min, y_max);
+    plot.axes(&mut shapes);
+
+    let x = |value: f64| plot.left + ((value - x_min) / x_span) as f32 * plot.width();
+
+    // Temporal and categorical axes are labelled with the values themselves.
+    if ColumnKind::of(&result.columns[spec.x].type_name) == ColumnKind::Numeric {
+        for tick in ticks(x_min, x_max) {
+            plot.x_label(&mut shapes, x(tick), &format_number(tick));
+        }
+    } else {
+        let step = (points.len() / TICKS).max(1);
+        for point in points.iter().step_by(step) {
+            plot.x_label(&mut shapes, x(point.x), &point.label);
+        }
+    }
+
+    let positions: Vec<_> = points.iter().map(|p| (x(p.x), plot.y(p.y))).collect();
+
+    match spec.kind {
+        ChartKind::Line => shapes.push(Shape::Polyline {
+            points: positions,
+            color: PALETTE[0],
+        }),
+        _ => shapes.extend(positions.into_iter().map(|center| Shape::Circle {
+            center,
+            radius: 3.0,
+            color: PALETTE[0],
+        })),
+    }
+
+    shapes
+}
+
+fn pie(points: &[Point], width: f32, height: f32) -> Vec<Shape> {
+    let points: Vec<_> = points.iter().filter(|p| p.y > 0.0).collect();
+    let total: f64 = points.iter().map(|p| p.y).sum();
+    let mut shapes = vec![];
+
+    if total <= 0.0 {
+        return shapes;
+    }
+
+    let radius = ((height - MARGIN_TOP * 2.0) / 2.0)
+        .min(width / 4.0)
+        .max(1.0);
+    let center = (MARGIN_TOP + radius, height / 2.0);
+    let legend_x = center.0 + radius + 24.0;
+    let mut start = -FRAC_PI_2;
+
+    for (i, point) in points.iter().enumerate() {
+        let share = point.y / total;
+        let end = start + share as f32 * TAU;
+        let color = PALETTE[i % PALETTE.len()];
+
+        shapes.push(Shape::Wedge {
+            center,
+            radius,
+            start,
+            end,
+            color,
+        });
+
+        let y = MARGIN_TOP + 8.0 + i as f32 * 18.0;
+        shapes.push(Shape::Rect {
+            x: legend_x,
+            y: y - 5.0,
+            width: 10.0,
+            height: 10.0,
+            color,
+        });
+        shapes.push(Shape::Text {
+            position: (legend_x + 16.0, y),
+            content: format!("{} ({:.1}%)", shorten(&point.label, 24), share * 100.0),
+            anchor: Anchor::Start,
+            color: AXIS,
+        });
+
+        start = end;
+    }
+
+    shapes
+}
+
+/// Round tick values covering `min..=max`.
+fn ticks(min: f64, max: f64) -> Vec<f64> {
+    let raw = (max - min) / TICKS as f64;
+
+    if !raw.is_finite() || raw <= 0.0 {
+        return vec![min];
+    }
+
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = match raw / magnitude {
+        n if n < 1.5 => 1.0,
+        n if n < 3.0 => 2.0,
+        n if n < 7.0 => 5.0,
+        _ => 10.0,
+    } * magnitude;
+
+    let first = (min / step).ceil();
+    let last = (max / step + 1e-9).floor();
+
+    // Adding 0.0 turns a negative zero into a plain one.
+    (first as i64..=last as i64)
+        .map(|i| i as f64 * step + 0.0)
+        .collect()
+}
+
+fn format_number(value: f64) -> String {
+    let abs = value.abs();
+
+    if abs >= 1e9 {
+        format!("{:.1}B", value / 1e9)
+    } else if abs >= 1e6 {
+        format!("{:.1}M", value / 1e6)
+    } else if abs >= 1e4 {
+        format!("{:.1}K", value / 1e3)
+    } else if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn shorten(label: &str, max: usize) -> String {
+    if label.chars().count() > max {
+        label.chars().take(max - 1).chain(['…']).collect()
+    } else {
+        label.to_string()
+    }
+}
+
+fn polar(center: (f32, f32), radius: f32, angle: f32) -> (f32, f32) {
+    (
+        center.0 + radius * angle.cos(),
+        center.1 + radius * angle.sin(),
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::Column;
+
+    fn result(columns: &[(&str, &str)], rows: Vec<Vec<Value>>) -> ResultSet {
+        ResultSet {
+            columns: columns
+                .iter()
+                .map(|(name, type_name)| Column {
+                    name: name.to_string(),
+                    type_name: type_name.to_string(),
+                    source: None,
+                })
+                .collect(),
+            rows,
+            ..ResultSet::default()
+        }
+    }
+
+    #[test]
+    fn suggests_a_chart_from_column_types() {
+        let spec = |columns: &[(&str, &str)]| suggest(&result(columns, vec![]));
+
+        assert_eq!(
+            spec(&[("n", "int8"), ("day", "date")]),
+            Some(ChartSpec {
+                kind: ChartKind::Line,
+                x: 1,
+                y: 0
+            })
+        );
+        assert_eq!(
+            spec(&[("city", "text"), ("total", "numeric")]),
+            Some(ChartSpec {
+                kind: ChartKind::Bar,
+                x: 0,
+                y: 1
+            })
+        );
+        assert_eq!(
+            spec(&[("a", "float8"), ("b", "int4")]),
+            Some(ChartSpec {
+                kind: ChartKind::Scatter,
+                x: 0,
+                y: 1
+            })
+        );
+        assert_eq!(spec(&[("a", "text"), ("b", "date")]), None);
+    }
+
+    #[test]
+    fn sums_categories_and_folds_the_rest_into_other() {
+        let rows = ["a", "b", "a", "c", "d"]
+            .iter()
+            .map(|label| vec![Value::Text(label.to_string()), Value::Int(1)])
+            .collect();
+        let result = result(&[("k", "text"), ("n", "int4")], rows);
+        let spec = ChartSpec {
+            kind: ChartKind::Bar,
+            x: 0,
+            y: 1,
+        };
+        let points = categories(&result, spec, 3);
+
+        let sums: Vec<(&str, f64)> = points.iter().map(|p| (p.label.as_str(), p.y)).collect();
+        assert_eq!(sums, [("a", 2.0), ("b", 1.0), ("Other", 2.0)]);
+    }
+
+    #[test]
+    fn rendering_ignores_missing_columns() {
+        let result = result(&[("n", "int4")], vec![vec![Value::Int(1)]]);
+        let spec = ChartSpec {
+            kind: ChartKind::Bar,
+            x: 0,
+            y: 3,
+        };
+
+        assert!(render(&result, spec, 100.0, 100.0).is_empty());
+    }
+
+    #[test]
+    fn ticks_are_round() {
+        assert_eq!(ticks(0.0, 10.0), [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+        assert_eq!(ticks(-1.0, 1.0), [-1.0, -0.5, 0.0, 0.5, 1.0]);
+        assert_eq!(ticks(3.0, 3.0), [3.0]);
+    }
+
+    #[test]
+    fn formats_numbers_and_labels() {
+        assert_eq!(format_number(2_500_000.0), "2.5M");
+        assert_eq!(format_number(12_000.0), "12.0K");
+        assert_eq!(format_number(42.0), "42");
+        assert_eq!(format_number(0.126), "0.13");
+        assert_eq!(shorten("abcdef", 4), "abc…");
+        assert_eq!(escape_xml("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+
+    #[test]
+    fn svg_escapes_text() {
+        let shapes = [Shape::Text {
+            position: (1.0, 2.0),
+            content: "<b>".into(),
+            anchor: Anchor::Start,
+            color: AXIS,
+        }];
+        let svg = to_svg(&shapes, 10.0, 10.0);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("&lt;b&gt;"));
+        assert!(!svg.contains("<b>"));
+    }
+}
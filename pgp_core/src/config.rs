@@ -2,18 +2,418 @@ use crate::connection::Connection;
 use crate::errors::Error;
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct OpenAI {
     pub token: String,
+    #[serde(default = "default_model")]
+    pub model: String,
+}
+
+fn default_model() -> String {
+    "gpt-3.5-turbo-0613".to_string()
+}
+
+/// What happens to the rest of a script when one statement fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    #[default]
+    Stop,
+    Continue,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Scripts {
+    #[serde(default)]
+    pub transaction: bool,
+    #[serde(default)]
+    pub on_error: OnError,
+}
+
+/// How tables are picked for the system message. Schemas up to `min_tables`
+/// tables are sent whole; larger ones only send the `top_k` tables that best
+/// match the question, plus the tables they join to when `neighbours` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Retrieval {
+    pub min_tables: usize,
+    pub top_k: usize,
+    pub neighbours: bool,
+}
+
+impl Default for Retrieval {
+    fn default() -> Self {
+        Self {
+            min_tables: 30,
+            top_k: 8,
+            neighbours: true,
+        }
+    }
+}
+
+/// How the context window is shared out. `response` tokens are kept for the
+/// reply, the system message with the schema may take the `schema` fraction
+/// of the rest and a single tool result the `result` fraction; the history
+/// gets whatever is left. `context_window` overrides the model's own size.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    pub context_window: Option<usize>,
+    pub response: u16,
+    pub schema: f32,
+    pub result: f32,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            context_window: None,
+            response: 512,
+            schema: 0.4,
+            result: 0.25,
+        }
+    }
+}
+
+/// How the schema is written into the system message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaFormat {
+    /// `CREATE` statements with constraints, defaults and indexes.
+    #[default]
+    Ddl,
+    /// One `table(column type, ...)` line per table.
+    Compact,
+    Json,
+}
+
+impl SchemaFormat {
+    pub const ALL: [SchemaFormat; 3] = [
+        SchemaFormat::Ddl,
+        SchemaFormat::Compact,
+        SchemaFormat::Json,
+    ];
+}
+
+impl fmt::Display for SchemaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaFormat::Ddl => write!(f, "DDL"),
+            SchemaFormat::Compact => write!(f, "Compact"),
+            SchemaFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+/// What goes into the schema part of the prompt besides tables and columns.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchemaOptions {
+    pub format: SchemaFormat,
+    pub comments: bool,
+    pub foreign_keys: bool,
+    pub enum_values: bool,
+    pub row_estimates: bool,
+}
+
+impl Default for SchemaOptions {
+    fn default() -> Self {
+        Self {
+            format: SchemaFormat::Ddl,
+            comments: true,
+            foreign_keys: true,
+            enum_values: true,
+            row_estimates: false,
+        }
+    }
+}
+
+/// Column statistics the model can ask for. Tables and columns matching an
+/// `exclude` pattern (`schema.table` or `schema.table.column`, parts may be
+/// `*`) are never sampled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Hints {
+    pub enabled: bool,
+    pub exclude: Vec<String>,
+    /// Most common values reported per column.
+    pub max_values: usize,
+}
+
+impl Default for Hints {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            exclude: vec![],
+            max_values: 10,
+        }
+    }
+}
+
+/// Self-consistency for questions asked with voting on: the model writes
+/// `candidates` queries, sampled at `temperature`, which are run read-only
+/// and grouped by result, and the answer uses the result most agree on.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Voting {
+    pub candidates: u8,
+    pub temperature: f32,
+}
+
+impl Default for Voting {
+    fn default() -> Self {
+        Self {
+            candidates: 3,
+            temperature: 0.8,
+        }
+    }
+}
+
+/// What happens to a value found to be personal data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskAction {
+    /// Replaced by the kind of data it is, such as `[email]`.
+    #[default]
+    Mask,
+    /// Replaced by a salted hash, so equal values still compare equal.
+    Hash,
+    /// Left out: the column for a column rule, the value otherwise.
+    Drop,
+}
+
+/// Columns whose names match `pattern` (case insensitive, `*` matches any
+/// run of characters) are treated as personal data.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColumnRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub action: MaskAction,
+}
+
+/// Personal data kept out of what is sent to the model. Columns are matched
+/// by name with `columns`; with `detect_values` every other value is also
+/// checked for emails, phone numbers, IP addresses, card numbers and geo
+/// points, which get `action`. Results shown locally are left untouched.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Masking {
+    pub enabled: bool,
+    pub action: MaskAction,
+    pub detect_values: bool,
+    pub columns: Vec<ColumnRule>,
+    /// Salt for hashed values; a random one per session when not set.
+    pub salt: Option<String>,
+}
+
+impl Default for Masking {
+    fn default() -> Self {
+        let rule = |pattern: &str, action| ColumnRule {
+            pattern: pattern.to_string(),
+            action,
+        };
+
+        Self {
+            enabled: true,
+            action: MaskAction::Mask,
+            detect_values: true,
+            columns: vec![
+                rule("*email*", MaskAction::Hash),
+                rule("*phone*", MaskAction::Mask),
+                rule("*push*token*", MaskAction::Drop),
+                rule("*password*", MaskAction::Drop),
+                rule("*secret*", MaskAction::Drop),
+                rule("*location*", MaskAction::Mask),
+                rule("*residence*", MaskAction::Mask),
+                rule("*address*", MaskAction::Mask),
+                rule("ip", MaskAction::Mask),
+                rule("*_ip", MaskAction::Mask),
+                rule("latitude", MaskAction::Mask),
+                rule("longitude", MaskAction::Mask),
+                rule("*card*number*", MaskAction::Drop),
+            ],
+            salt: None,
+        }
+    }
+}
+
+/// Defence against instructions hidden in database contents. With
+/// `enabled`, text values that read like instructions to the model (as in
+/// "ignore previous instructions") are withheld from it; `patterns` adds
+/// case insensitive regexes to the built-in ones. With `approval` as well,
+/// a tool call the model makes while such values are in what it was sent
+/// waits for the user to approve it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Injection {
+    pub enabled: bool,
+    pub approval: bool,
+    pub patterns: Vec<String>,
+}
+
+impl Default for Injection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            approval: true,
+            patterns: vec![],
+        }
+    }
+}
+
+/// Which schemas, tables and columns the assistant may use on a connection.
+/// Patterns are `schema`, `schema.table` or `schema.table.column`, where any
+/// part may be `*`. With an `allow` list only what it covers is visible;
+/// `deny` wins over `allow`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Access {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+/// A predicate every query must apply to `table` (`schema.table`, or a
+/// table in `public`), such as `tenant_id = $tenant`. `$name` stands for the
+/// connection variable of that name and is inserted as a quoted literal.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RowFilter {
+    pub table: String,
+    pub predicate: String,
+}
+
+/// Caps on the result of a single query. Queries get a `LIMIT` of
+/// `max_rows`, and reading stops once `max_rows` rows or `max_bytes` bytes
+/// were read or `max_duration` seconds have passed. Zero turns a cap off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_rows: usize,
+    pub max_bytes: usize,
+    pub max_duration: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_rows: 1000,
+            max_bytes: 10 * 1024 * 1024,
+            max_duration: 60,
+        }
+    }
+}
+
+/// What happens to a model-generated query found to be expensive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostAction {
+    /// The query runs and the model is told why it is expensive.
+    #[default]
+    Warn,
+    /// The query does not run and the reason goes back to the model.
+    Block,
+}
+
+/// Thresholds for the plan of a model-generated query, checked with
+/// `EXPLAIN` before it runs: the total cost, the rows returned, the rows of
+/// an unlimited sequential scan, and joins without a join condition.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Cost {
+    pub enabled: bool,
+    pub action: CostAction,
+    pub max_cost: f64,
+    pub max_rows: f64,
+    pub max_scan_rows: f64,
+    pub cartesian: bool,
+}
+
+impl Default for Cost {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            action: CostAction::Warn,
+            max_cost: 1_000_000.0,
+            max_rows: 10_000_000.0,
+            max_scan_rows: 1_000_000.0,
+            cartesian: true,
+        }
+    }
+}
+
+/// Whether model-generated SQL is checked against the cached schema before
+/// it runs. Table and column names that match a single name but for case or
+/// quoting are corrected; other unknown names go back to the model.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Validation {
+    pub enabled: bool,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// How query plans are shown. With `analyze`, which is off unless the
+/// connection turns it on, a query is also run, under `EXPLAIN ANALYZE` in a
+/// read-only transaction that is rolled back, for actual timings and row
+/// counts; otherwise statements are only planned.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Explain {
+    pub analyze: bool,
+}
+
+/// Where executed statements are recorded. The log is append only and its
+/// entries are hash chained, so edits and deletions can be detected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Audit {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Secret the entries and the head are signed with (HMAC-SHA256).
+    /// Without it, whoever can write the log can also rebuild its chain.
+    pub key: Option<String>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from("audit.log"),
+            key: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub connections: Option<Vec<Connection>>,
     pub openai: OpenAI,
+    #[serde(default)]
+    pub scripts: Scripts,
+    #[serde(default)]
+    pub retrieval: Retrieval,
+    #[serde(default)]
+    pub budget: Budget,
+    #[serde(default)]
+    pub schema: SchemaOptions,
+    #[serde(default)]
+    pub hints: Hints,
+    #[serde(default)]
+    pub masking: Masking,
+    #[serde(default)]
+    pub injection: Injection,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub voting: Voting,
 }
 
 impl Config {
@@ -2,7 +2,9 @@ use serde::Deserialize;
 use serde::Deserializer;
 use url::Url;
 
-use std::collections::HashMap;
+use crate::config::{Access, Cost, Explain, Limits, RowFilter, Validation};
+
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicU8, Ordering};
 
 const PG_DEFAULT_PORT: u16 = 5432;
@@ -20,6 +22,23 @@ pub struct Connection {
     pub sslmode: Option<String>,
     pub cert: Option<String>,
     pub timeout: u16,
+    /// Keep row data on this machine: the model only sees the schema and
+    /// the shape of results.
+    pub schema_only: bool,
+    /// Schemas, tables and columns the assistant may use.
+    pub access: Access,
+    /// Predicates added to every query on the tables they name.
+    pub row_filters: Vec<RowFilter>,
+    /// Values the row filters refer to as `$name`.
+    pub variables: BTreeMap<String, String>,
+    /// How much a single query may return.
+    pub limits: Limits,
+    /// When a model-generated query is too expensive to run.
+    pub cost: Cost,
+    /// Whether plans may be analyzed by running the query.
+    pub explain: Explain,
+    /// Whether model-generated SQL is checked against the schema.
+    pub validation: Validation,
     // pub client: Arc<Mutex<Option<Client>>>,
 }
 
@@ -48,6 +67,44 @@ impl<'de> Deserialize<'de> for Connection {
         let mut map: HashMap<String, toml::Value> = HashMap::deserialize(deserializer)?;
         let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
         let active = false;
+        let schema_only = map
+            .remove("schema_only")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let access: Access = match map.remove("access") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => Access::default(),
+        };
+        let row_filters: Vec<RowFilter> = match map.remove("row_filters") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => vec![],
+        };
+        let variables: BTreeMap<String, String> = map
+            .remove("variables")
+            .and_then(|v| v.as_table().cloned())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| match value {
+                toml::Value::String(value) => (name, value),
+                value => (name, value.to_string()),
+            })
+            .collect();
+        let limits: Limits = match map.remove("limits") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => Limits::default(),
+        };
+        let cost: Cost = match map.remove("cost") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => Cost::default(),
+        };
+        let explain: Explain = match map.remove("explain") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => Explain::default(),
+        };
+        let validation: Validation = match map.remove("validation") {
+            Some(value) => value.try_into().map_err(serde::de::Error::custom)?,
+            None => Validation::default(),
+        };
 
         if map.contains_key("url") {
             let url = map.remove("url").unwrap().as_str().unwrap().to_string();
@@ -72,6 +129,14 @@ impl<'de> Deserialize<'de> for Connection {
                 sslmode,
                 cert: None,
                 timeout: DEFAULT_CONNECT_TIMEOUT,
+                schema_only,
+                access,
+                row_filters,
+                variables,
+                limits,
+                cost,
+                explain,
+                validation,
                 // client: Arc::new(Mutex::new(None)),
             })
         } else {
@@ -119,6 +184,14 @@ impl<'de> Deserialize<'de> for Connection {
                 sslmode,
                 cert: None,
                 timeout,
+                schema_only,
+                access,
+                row_filters,
+                variables,
+                limits,
+                cost,
+                explain,
+                validation,
                 // client: Arc::new(Mutex::new(None)),
             })
         }
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use serde_json::Value as Json;
+use tokio_postgres::Client;
+
+use crate::config::Cost;
+
+/// Nodes that read all of their input before returning a row, so a `Limit`
+/// above them does not stop the scans below.
+const BLOCKING: [&str; 6] = [
+    "Aggregate",
+    "Hash",
+    "HashAggregate",
+    "Materialize",
+    "Sort",
+    "WindowAgg",
+];
+
+/// Why a plan is considered expensive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// Estimated total cost over `max_cost`.
+    Cost(f64),
+    /// Estimated rows returned over `max_rows`.
+    Rows(f64),
+    /// A sequential scan reading more than `max_scan_rows` rows.
+    SeqScan { table: String, rows: f64 },
+    /// A join without a join condition, producing every pair of rows.
+    Cartesian { rows: f64 },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Cost(cost) => write!(f, "estimated cost {:.0}", cost),
+            Finding::Rows(rows) => write!(f, "about {:.0} rows returned", rows),
+            Finding::SeqScan { table, rows } => {
+                write!(
+                    f,
+                    "sequential scan of {} reading about {:.0} rows",
+                    table, rows
+                )
+            }
+            Finding::Cartesian { rows } => write!(
+                f,
+                "join without a join condition producing about {:.0} rows",
+                rows
+            ),
+        }
+    }
+}
+
+/// The `EXPLAIN (FORMAT JSON)` plan of a statement, or none for statements
+/// that cannot be explained.
+pub async fn explain(client: &Client, statement: &str) -> Option<Json> {
+    let rows = client
+        .query(&format!("EXPLAIN (FORMAT JSON) {}", statement), &[])
+        .await
+        .ok()?;
+    let plan: Json = rows.first()?.try_get(0).ok()?;
+
+    plan.get(0)?.get("Plan").cloned()
+}
+
+/// What makes a plan exceed the thresholds.
+pub fn assess(plan: &Json, settings: &Cost) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    let cost = number(plan, "Total Cost");
+    if cost > settings.max_cost {
+        findings.push(Finding::Cost(cost));
+    }
+
+    let rows = number(plan, "Plan Rows");
+    if rows > settings.max_rows {
+        findings.push(Finding::Rows(rows));
+    }
+
+    walk(plan, settings, false, &mut findings);
+
+    findings
+}
+
+/// Looks for expensive scans and joins below `node`. Scans under a `Limit`
+/// stop early, so they are not held to `max_scan_rows`, unless a blocking
+/// node in between reads them whole.
+fn walk(node: &Json, settings: &Cost, limited: bool, findings: &mut Vec<Finding>) {
+    let node_type = node["Node Type"].as_str().unwrap_or_default();
+    let rows = number(node, "Plan Rows");
+    let children: &[Json] = node["Plans"].as_array().map_or(&[], |c| c.as_slice());
+
+    match node_type {
+        "Seq Scan" if !limited && rows > settings.max_scan_rows => {
+            let table = match (node["Schema"].as_str(), node["Relation Name"].as_str()) {
+                (Some(schema), Some(table)) => format!("{}.{}", schema, table),
+                (None, Some(table)) => table.to_string(),
+                _ => "a table".to_string(),
+            };
+            findings.push(Finding::SeqScan { table, rows });
+        }
+        "Nested Loop" if settings.cartesian && is_cartesian(node, children) => {
+            findings.push(Finding::Cartesian { rows });
+        }
+        _ => {}
+    }
+
+    let limited = match node_type {
+        "Limit" => true,
+        blocking if BLOCKING.contains(&blocking) => false,
+        _ => limited,
+    };
+
+    for child in children {
+        walk(child, settings, limited, findings);
+    }
+}
+
+/// Whether a nested loop returns every pair of rows of its inputs, which
+/// happens when it has no join condition.
+fn is_cartesian(node: &Json, children: &[Json]) -> bool {
+    let [outer, inner] = children else {
+        return false;
+    };
+    let outer_rows = number(outer, "Plan Rows");
+    let inner_rows = number(inner, "Plan Rows");
+
+    node.get("Join Filter").is_none()
+        && outer_rows > 1.0
+        && inner_rows > 1.0
+        && !is_parameterized(inner)
+        && number(node, "Plan Rows") >= outer_rows * inner_rows
+}
+
+/// Whether a plan reads rows using values from the outer side of a join.
+fn is_parameterized(node: &Json) -> bool {
+    ["Index Cond", "Recheck Cond", "TID Cond", "Cache Key"]
+        .iter()
+        .any(|key| node.get(key).is_some())
+        || node["Plans"]
+            .as_array()
+            .is_some_and(|children| children.iter().any(is_parameterized))
+}
+
+fn number(node: &Json, key: &str) -> f64 {
+    node[key].as_f64().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scan(rows: f64) -> Json {
+        json!({
+            "Node Type": "Seq Scan",
+            "Schema": "public",
+            "Relation Name": "events",
+            "Plan Rows": rows,
+        })
+    }
+
+    fn node(node_type: &str, children: Vec<Json>) -> Json {
+        json!({ "Node Type": node_type, "Plan Rows": 10.0, "Plans": children })
+    }
+
+    fn scans(plan: &Json) -> usize {
+        assess(plan, &Cost::default())
+            .iter()
+            .filter(|f| matches!(f, Finding::SeqScan { .. }))
+            .count()
+    }
+
+    #[test]
+    fn flags_large_scans() {
+        assert_eq!(scans(&scan(5_000_000.0)), 1);
+        assert_eq!(scans(&scan(5_000.0)), 0);
+    }
+
+    #[test]
+    fn lets_limited_scans_pass() {
+        let plan = node("Limit", vec![scan(5_000_000.0)]);
+
+        assert_eq!(scans(&plan), 0);
+    }
+
+    #[test]
+    fn flags_scans_read_whole_under_a_limit() {
+        for blocking in BLOCKING {
+            let plan = node("Limit", vec![node(blocking, vec![scan(5_000_000.0)])]);
+
+            assert_eq!(scans(&plan), 1, "{}", blocking);
+        }
+    }
+
+    #[test]
+    fn flags_joins_without_a_condition() {
+        let plan = json!({
+            "Node Type": "Nested Loop",
+            "Plan Rows": 1_000_000.0,
+            "Plans": [
+                { "Node Type": "Seq Scan", "Plan Rows": 1000.0 },
+                { "Node Type": "Seq Scan", "Plan Rows": 1000.0 },
+            ],
+        });
+
+        assert!(assess(&plan, &Cost::default()).contains(&Finding::Cartesian { rows: 1_000_000.0 }));
+    }
+}
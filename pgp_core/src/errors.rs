@@ -8,6 +8,13 @@ pub enum Error {
     ParseError,
     ConnectionError,
     QueryError,
+    Cancelled,
+    ExportError,
+    AuditError,
+    /// The statement did not pass the checks, for this reason.
+    Rejected(String),
+    /// The database failed the statement with this message.
+    Failed(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -26,6 +33,14 @@ impl From<toml::de::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Error {
+        dbg!(error);
+
+        Error::ParseError
+    }
+}
+
 impl From<tokio_postgres::Error> for Error {
     fn from(error: tokio_postgres::Error) -> Error {
         dbg!(error);
@@ -50,6 +65,30 @@ impl From<async_openai::error::OpenAIError> for Error {
     }
 }
 
+impl From<csv::Error> for Error {
+    fn from(error: csv::Error) -> Error {
+        dbg!(error);
+
+        Error::ExportError
+    }
+}
+
+impl From<rust_xlsxwriter::XlsxError> for Error {
+    fn from(error: rust_xlsxwriter::XlsxError) -> Error {
+        dbg!(error);
+
+        Error::ExportError
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(error: parquet::errors::ParquetError) -> Error {
+        dbg!(error);
+
+        Error::ExportError
+    }
+}
+
 impl From<PoisonError<std::sync::MutexGuard<'_, tokio_postgres::Client>>> for Error {
     fn from(error: PoisonError<std::sync::MutexGuard<'_, tokio_postgres::Client>>) -> Error {
         dbg!(error);
@@ -67,6 +106,11 @@ impl fmt::Display for Error {
             Error::ParseError => write!(f, "Wrong config"),
             Error::ConnectionError => write!(f, "Connection error"),
             Error::QueryError => write!(f, "Query error"),
+            Error::Cancelled => write!(f, "Query cancelled"),
+            Error::ExportError => write!(f, "Export error"),
+            Error::AuditError => write!(f, "Audit log error"),
+            Error::Rejected(reason) => write!(f, "Rejected: {}", reason),
+            Error::Failed(message) => write!(f, "Query failed: {}", message),
         }
     }
 }
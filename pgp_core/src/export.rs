@@ -0,0 +1,769 @@
+use chrono::{Local, NaiveDate};
+use futures_util::StreamExt;
+use parquet::basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{
+    BoolType, ByteArray, ByteArrayType, DataType, DoubleType, FixedLenByteArray,
+    FixedLenByteArrayType, FloatType, Int32Type, Int64Type,
+};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::format::MicroSeconds;
+use parquet::schema::types::Type as ParquetType;
+use rust_xlsxwriter::{Format as XlsxFormat, Workbook};
+use sqlparser::ast::Statement;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client as DbClient;
+
+use crate::audit;
+use crate::cancel::Canceller;
+use crate::errors::Error;
+use crate::results::{ResultSet, Value};
+use crate::script;
+use crate::Session;
+
+const EXPORT_DIR: &str = "exports";
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    JsonLines,
+    Markdown,
+    Xlsx,
+    Parquet,
+}
+
+impl Format {
+    pub const ALL: [Format; 6] = [
+        Format::Csv,
+        Format::Tsv,
+        Format::JsonLines,
+        Format::Markdown,
+        Format::Xlsx,
+        Format::Parquet,
+    ];
+
+    /// Formats that can be put on the clipboard as text.
+    pub const TEXT: [Format; 4] = [
+        Format::Csv,
+        Format::Tsv,
+        Format::JsonLines,
+        Format::Markdown,
+    ];
+
+    /// Formats that can be produced by `COPY ... TO STDOUT` without
+    /// materialising the rows.
+    pub const STREAM: [Format; 3] = [Format::Csv, Format::Tsv, Format::JsonLines];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::JsonLines => "jsonl",
+            Format::Markdown => "md",
+            Format::Xlsx => "xlsx",
+            Format::Parquet => "parquet",
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        Format::TEXT.contains(self)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Csv => write!(f, "CSV"),
+            Format::Tsv => write!(f, "TSV"),
+            Format::JsonLines => write!(f, "JSON lines"),
+            Format::Markdown => write!(f, "Markdown"),
+            Format::Xlsx => write!(f, "Excel"),
+            Format::Parquet => write!(f, "Parquet"),
+        }
+    }
+}
+
+/// Path in the exports directory for a new file with the given extension.
+pub fn default_path(name: &str, extension: &str) -> PathBuf {
+    let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+
+    Path::new(EXPORT_DIR).join(format!("{}-{}.{}", name, timestamp, extension))
+}
+
+/// Renders a result set in one of the text formats.
+pub fn to_string(result: &ResultSet, format: Format) -> Result<String, Error> {
+    match format {
+        Format::Csv => delimited(result, b','),
+        Format::Tsv => delimited(result, b'\t'),
+        Format::JsonLines => Ok(json_lines(result)),
+        Format::Markdown => Ok(markdown(result)),
+        Format::Xlsx | Format::Parquet => Err(Error::ExportError),
+    }
+}
+
+pub async fn save(result: ResultSet, format: Format, path: PathBuf) -> Result<PathBuf, Error> {
+    tokio::task::spawn_blocking(move || write(&result, format, &path).map(|_| path))
+        .await
+        .map_err(|_| Error::ExportError)?
+}
+
+pub fn write(result: &ResultSet, format: Format, path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|_| Error::ExportError)?;
+    }
+
+    match format {
+        Format::Xlsx => xlsx(result, path),
+        Format::Parquet => parquet(result, path),
+        _ => fs::write(path, to_string(result, format)?).map_err(|_| Error::ExportError),
+    }
+}
+
+/// Runs `query` as `COPY (query) TO STDOUT` and writes the output straight
+/// to `path`, reporting the number of bytes written so far to `progress`.
+/// Only queries are copied, and in a read-only transaction, so streaming a
+/// result never runs a write again. Cancelling the session stops the copy
+/// and removes the partial file.
+pub async fn copy_to_file(
+    session: Session,
+    query: String,
+    format: Format,
+    path: PathBuf,
+    mut progress: impl FnMut(u64) + Send,
+) -> Result<u64, Error> {
+    let query = crate::check_statement(&session, &query).map_err(Error::Rejected)?;
+
+    let statement = copy_statement(&query, format)?;
+
+    session.audit.ready()?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|_| Error::ExportError)?;
+    }
+
+    let client = session
+        .db_client
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(Error::ConnectionError)?;
+
+    let started = Instant::now();
+    let result = match script::begin_read_only(&client).await {
+        Ok(savepoint) => {
+            let copied = copy_out(
+                &client,
+                &statement,
+                &path,
+                &session.canceller,
+                &mut progress,
+            )
+            .await;
+            let ended = script::end_read_only(&client, savepoint).await;
+            copied.and_then(|bytes| ended.map(|()| bytes).map_err(copy_error))
+        }
+        Err(e) => Err(copy_error(e)),
+    };
+
+    *session.db_client.lock().unwrap() = Some(client);
+
+    let outcome = match &result {
+        Ok(bytes) => format!("ok: {} bytes to {}", bytes, path.display()),
+        Err(Error::Cancelled) => "cancelled".to_string(),
+        Err(e) => format!("error: {:?}", e),
+    };
+    let execution = audit::Execution {
+        statement: &statement,
+        rows: None,
+        duration: started.elapsed(),
+        outcome,
+    };
+    // An export that was not recorded is not kept.
+    let result = session.audit.record(None, None, execution).and(result);
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    result
+}
+
+fn copy_statement(query: &str, format: Format) -> Result<String, Error> {
+    let query = query.trim().trim_end_matches(';');
+
+    // Results of `INSERT ... RETURNING` and the like would run the write
+    // once more.
+    match Parser::parse_sql(&PostgreSqlDialect {}, query).as_deref() {
+        Ok([Statement::Query(_)]) => {}
+        _ => return Err(Error::ExportError),
+    }
+
+    match format {
+        Format::Csv => Ok(format!(
+            "COPY ({}) TO STDOUT WITH (FORMAT csv, HEADER)",
+            query
+        )),
+        Format::Tsv => Ok(format!(
+            "COPY ({}) TO STDOUT WITH (FORMAT csv, HEADER, DELIMITER E'\\t')",
+            query
+        )),
+        // JSON never contains raw control characters, so using them as quote
+        // and delimiter leaves each `row_to_json` line untouched.
+        Format::JsonLines => Ok(format!(
+            "COPY (SELECT row_to_json(q) FROM ({}) q) TO STDOUT WITH (FORMAT csv, QUOTE E'\\x01', DELIMITER E'\\x02')",
+            query
+        )),
+        _ => Err(Error::ExportError),
+    }
+}
+
+async fn copy_out(
+    client: &DbClient,
+    statement: &str,
+    path: &Path,
+    canceller: &Canceller,
+    progress: &mut (impl FnMut(u64) + Send),
+) -> Result<u64, Error> {
+    let stream = client.copy_out(statement).await.map_err(copy_error)?;
+    tokio::pin!(stream);
+
+    let file = tokio::fs::File::create(path)
+        .await
+        .map_err(|_| Error::ExportError)?;
+    let mut file = BufWriter::new(file);
+    let mut bytes = 0;
+    let mut reported = Instant::now();
+
+    while let Some(chunk) = stream.next().await {
+        if canceller.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let chunk = chunk.map_err(copy_error)?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|_| Error::ExportError)?;
+        bytes += chunk.len() as u64;
+
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            progress(bytes);
+            reported = Instant::now();
+        }
+    }
+
+    file.flush().await.map_err(|_| Error::ExportError)?;
+    progress(bytes);
+
+    Ok(bytes)
+}
+
+fn copy_error(error: tokio_postgres::Error) -> Error {
+    if error.code() == Some(&SqlState::QUERY_CANCELED) {
+        Error::Cancelled
+    } else {
+        Error::Failed(match error.as_db_error() {
+            Some(db_error) => db_error.to_string(),
+            None => error.to_string(),
+        })
+    }
+}
+
+fn delimited(result: &ResultSet, delimiter: u8) -> Result<String, Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+
+    writer.write_record(result.columns.iter().map(|c| c.name.as_str()))?;
+
+    for row in &result.rows {
+        writer.write_record(row.iter().map(|v| match v {
+            Value::Null => String::new(),
+            _ => v.to_string(),
+        }))?;
+    }
+
+    let bytes = writer.into_inner().map_err(|_| Error::ExportError)?;
+
+    String::from_utf8(bytes).map_err(|_| Error::ExportError)
+}
+
+fn json_lines(result: &ResultSet) -> String {
+    let names = unique_names(result);
+
+    result
+        .rows
+        .iter()
+        .map(|row| {
+            let object: serde_json::Map<_, _> = names
+                .iter()
+                .zip(row)
+                .map(|(name, v)| (name.clone(), v.to_json()))
+                .collect();
+
+            serde_json::Value::Object(object).to_string() + "\n"
+        })
+        .collect()
+}
+
+fn markdown(result: &ResultSet) -> String {
+    let escape = |s: &str| s.replace('|', "\\|").replace(['\n', '\r'], " ");
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+
+    let mut out = line(result.columns.iter().map(|c| escape(&c.name)).collect());
+    out.push_str(&line(
+        result.columns.iter().map(|_| "---".to_string()).collect(),
+    ));
+
+    for row in &result.rows {
+        out.push_str(&line(
+            row.iter()
+                .map(|v| match v {
+                    Value::Null => String::new(),
+                    _ => escape(&v.to_string()),
+                })
+                .collect(),
+        ));
+    }
+
+    out
+}
+
+fn xlsx(result: &ResultSet, path: &Path) -> Result<(), Error> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+
+    let header = XlsxFormat::new().set_bold();
+    let date = XlsxFormat::new().set_num_format("yyyy-mm-dd");
+    let time = XlsxFormat::new().set_num_format("hh:mm:ss");
+    let datetime = XlsxFormat::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+
+    for (c, column) in result.columns.iter().enumerate() {
+        worksheet.write_string_with_format(0, c as u16, &column.name, &header)?;
+    }
+
+    for (r, row) in result.rows.iter().enumerate() {
+        let r = r as u32 + 1;
+
+        for (c, value) in row.iter().enumerate() {
+            let c = c as u16;
+
+            match value {
+                Value::Null => continue,
+                Value::Bool(v) => worksheet.write_boolean(r, c, *v)?,
+                Value::Int(v) => worksheet.write_number(r, c, *v as f64)?,
+                Value::Float(v) => worksheet.write_number(r, c, *v)?,
+                Value::Numeric(v) => match v.parse::<f64>() {
+                    Ok(n) => worksheet.write_number(r, c, n)?,
+                    Err(_) => worksheet.write_string(r, c, v)?,
+                },
+                Value::Date(v) => worksheet.write_datetime_with_format(r, c, v, &date)?,
+                Value::Time(v) => worksheet.write_datetime_with_format(r, c, v, &time)?,
+                Value::Timestamp(v) => worksheet.write_datetime_with_format(r, c, v, &datetime)?,
+                Value::TimestampTz(v) => {
+                    worksheet.write_datetime_with_format(r, c, v.naive_utc(), &datetime)?
+                }
+                _ => worksheet.write_string(r, c, value.to_string())?,
+            };
+        }
+    }
+
+    worksheet.set_freeze_panes(1, 0)?;
+    worksheet.autofit();
+    workbook.save(path)?;
+
+    Ok(())
+}
+
+/// Column names for formats that key values by name, with a repeated name
+/// numbered from its second use on (`id`, `id_2`).
+fn unique_names(result: &ResultSet) -> Vec<String> {
+    let mut taken: HashSet<String> = result.columns.iter().map(|c| c.name.clone()).collect();
+    let mut seen = HashSet::new();
+
+    result
+        .columns
+        .iter()
+        .map(|c| {
+            if seen.insert(&c.name) {
+                return c.name.clone();
+            }
+
+            let name = (2..)
+                .map(|n| format!("{}_{}", c.name, n))
+                .find(|name| !taken.contains(name))
+                .unwrap();
+            taken.insert(name.clone());
+            name
+        })
+        .collect()
+}
+
+fn parquet(result: &ResultSet, path: &Path) -> Result<(), Error> {
+    let kinds: Vec<ParquetKind> = (0..result.columns.len())
+        .map(|index| parquet_kind(result, index))
+        .collect();
+
+    let fields = unique_names(result)
+        .iter()
+        .zip(&result.columns)
+        .zip(&kinds)
+        .map(|((name, c), kind)| parquet_field(name, &c.type_name, kind).map(Arc::new))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let schema = ParquetType::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?;
+
+    let file = File::create(path).map_err(|_| Error::ExportError)?;
+    let properties = WriterProperties::builder().build();
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+
+    while let Some(mut column) = row_group.next_column()? {
+        let rows = &result.rows;
+
+        match kinds[index] {
+            ParquetKind::Bool => write_column::<BoolType>(&mut column, rows, index, |v| match v {
+                Value::Bool(b) => Some(*b),
+                _ => None,
+            })?,
+            ParquetKind::Int32 => {
+                write_column::<Int32Type>(&mut column, rows, index, |v| match v {
+                    Value::Int(i) => Some(*i as i32),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Int64 => {
+                write_column::<Int64Type>(&mut column, rows, index, |v| match v {
+                    Value::Int(i) => Some(*i),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Float => {
+                write_column::<FloatType>(&mut column, rows, index, |v| match v {
+                    Value::Float(f) => Some(*f as f32),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Double => {
+                write_column::<DoubleType>(&mut column, rows, index, |v| match v {
+                    Value::Float(f) => Some(*f),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Date => {
+                let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+                write_column::<Int32Type>(&mut column, rows, index, |v| match v {
+                    Value::Date(d) => Some((*d - epoch).num_days() as i32),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Timestamp(_) => {
+                write_column::<Int64Type>(&mut column, rows, index, |v| match v {
+                    Value::Timestamp(t) => Some(t.and_utc().timestamp_micros()),
+                    Value::TimestampTz(t) => Some(t.timestamp_micros()),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Decimal { precision, scale } if precision <= INT64_DIGITS => {
+                write_column::<Int64Type>(&mut column, rows, index, |v| match v {
+                    Value::Numeric(n) => unscaled(n, scale).map(|n| n as i64),
+                    _ => None,
+                })?
+            }
+            ParquetKind::Decimal { scale, .. } => {
+                write_column::<FixedLenByteArrayType>(&mut column, rows, index, |v| match v {
+                    Value::Numeric(n) => unscaled(n, scale)
+                        .map(|n| FixedLenByteArray::from(n.to_be_bytes().to_vec())),
+                    _ => None,
+                })?
+            }
+            ParquetKind::String => {
+                write_column::<ByteArrayType>(&mut column, rows, index, |v| match v {
+                    Value::Null => None,
+                    Value::Json(json) => Some(ByteArray::from(json.to_string().into_bytes())),
+                    _ => Some(ByteArray::from(v.to_string().into_bytes())),
+                })?
+            }
+        }
+
+        column.close()?;
+        index += 1;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Digits of the decimals stored as `INT64`; longer ones take 16 bytes.
+const INT64_DIGITS: i32 = 18;
+
+#[derive(Clone, Copy)]
+enum ParquetKind {
+    Bool,
+    Int32,
+    Int64,
+    Float,
+    Double,
+    Date,
+    Timestamp(bool),
+    Decimal { precision: i32, scale: i32 },
+    String,
+}
+
+fn parquet_kind(result: &ResultSet, index: usize) -> ParquetKind {
+    match result.columns[index].type_name.as_str() {
+        "bool" => ParquetKind::Bool,
+        "int2" | "int4" => ParquetKind::Int32,
+        "int8" | "oid" => ParquetKind::Int64,
+        "float4" => ParquetKind::Float,
+        "float8" => ParquetKind::Double,
+        "date" => ParquetKind::Date,
+        "timestamp" => ParquetKind::Timestamp(false),
+        "timestamptz" => ParquetKind::Timestamp(true),
+        "numeric" => decimal(&result.rows, index).unwrap_or(ParquetKind::String),
+        _ => ParquetKind::String,
+    }
+}
+
+/// The smallest decimal type that holds every value of a `numeric` column,
+/// which has no fixed precision. None when a value is not a finite number
+/// or needs more than the 38 digits a 16 byte decimal has.
+fn decimal(rows: &[Vec<Value>], index: usize) -> Option<ParquetKind> {
+    let mut integer = 1;
+    let mut scale = 0;
+
+    for row in rows {
+        let number = match &row[index] {
+            Value::Null => continue,
+            Value::Numeric(n) => n.trim_start_matches('-'),
+            _ => return None,
+        };
+        let (digits, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+
+        if !is_digits(digits) || !is_digits(fraction) {
+            return None;
+        }
+
+        integer = integer.max(digits.trim_start_matches('0').len());
+        scale = scale.max(fraction.len());
+    }
+
+    let precision = integer + scale;
+
+    (precision <= 38).then_some(ParquetKind::Decimal {
+        precision: precision as i32,
+        scale: scale as i32,
+    })
+}
+
+/// The digits of a decimal as an integer, `scale` of them after the point.
+fn unscaled(number: &str, scale: i32) -> Option<i128> {
+    let (digits, fraction) = number.split_once('.').unwrap_or((number, ""));
+
+    format!("{}{:0<2$}", digits, fraction, scale as usize)
+        .parse()
+        .ok()
+}
+
+fn parquet_field(
+    name: &str,
+    type_name: &str,
+    kind: &ParquetKind,
+) -> Result<ParquetType, ParquetError> {
+    let decimal = |precision, scale| LogicalType::Decimal { scale, precision };
+    let (physical, logical) = match *kind {
+        ParquetKind::Bool => (PhysicalType::BOOLEAN, None),
+        ParquetKind::Int32 => (PhysicalType::INT32, None),
+        ParquetKind::Int64 => (PhysicalType::INT64, None),
+        ParquetKind::Float => (PhysicalType::FLOAT, None),
+        ParquetKind::Double => (PhysicalType::DOUBLE, None),
+        ParquetKind::Date => (PhysicalType::INT32, Some(LogicalType::Date)),
+        ParquetKind::Timestamp(utc) => (
+            PhysicalType::INT64,
+            Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: utc,
+                unit: TimeUnit::MICROS(MicroSeconds {}),
+            }),
+        ),
+        ParquetKind::Decimal { precision, scale } if precision <= INT64_DIGITS => {
+            (PhysicalType::INT64, Some(decimal(precision, scale)))
+        }
+        ParquetKind::Decimal { precision, scale } => (
+            PhysicalType::FIXED_LEN_BYTE_ARRAY,
+            Some(decimal(precision, scale)),
+        ),
+        ParquetKind::String if matches!(type_name, "json" | "jsonb") => {
+            (PhysicalType::BYTE_ARRAY, Some(LogicalType::Json))
+        }
+        ParquetKind::String => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+    };
+
+    let mut builder = ParquetType::primitive_type_builder(name, physical)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical);
+
+    if let ParquetKind::Decimal { precision, scale } = *kind {
+        builder = builder.with_precision(precision).with_scale(scale);
+        if precision > INT64_DIGITS {
+            builder = builder.with_length(16);
+        }
+    }
+
+    builder.build()
+}
+
+fn write_column<T: DataType>(
+    column: &mut SerializedColumnWriter,
+    rows: &[Vec<Value>],
+    index: usize,
+    convert: impl Fn(&Value) -> Option<T::T>,
+) -> Result<(), Error> {
+    let mut values = Vec::with_capacity(rows.len());
+    let mut levels = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        match convert(&row[index]) {
+            Some(value) => {
+                values.push(value);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+
+    column
+        .typed::<T>()
+        .write_batch(&values, Some(&levels), None)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::Column;
+
+    fn result(names: &[&str], rows: Vec<Vec<Value>>) -> ResultSet {
+        ResultSet {
+            columns: names
+                .iter()
+                .map(|name| Column {
+                    name: name.to_string(),
+                    type_name: "text".into(),
+                    source: None,
+                })
+                .collect(),
+            rows,
+            ..ResultSet::default()
+        }
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Text(text.into())
+    }
+
+    #[test]
+    fn writes_delimited_text_with_quoting() {
+        let result = result(
+            &["id", "note"],
+            vec![
+                vec![Value::Int(1), text("a, \"b\"")],
+                vec![Value::Int(2), Value::Null],
+            ],
+        );
+
+        assert_eq!(
+            to_string(&result, Format::Csv).unwrap(),
+            "id,note\n1,\"a, \"\"b\"\"\"\n2,\n"
+        );
+        assert_eq!(
+            to_string(&result, Format::Tsv).unwrap(),
+            "id\tnote\n1\t\"a, \"\"b\"\"\"\n2\t\n"
+        );
+    }
+
+    #[test]
+    fn writes_markdown_with_escaped_cells() {
+        let result = result(&["a|b"], vec![vec![text("x|y\nz")], vec![Value::Null]]);
+
+        assert_eq!(
+            to_string(&result, Format::Markdown).unwrap(),
+            "| a\\|b |\n| --- |\n| x\\|y z |\n|  |\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_keep_repeated_columns() {
+        let result = result(
+            &["id", "id", "id_2"],
+            vec![vec![Value::Int(1), Value::Int(2), Value::Int(3)]],
+        );
+
+        assert_eq!(unique_names(&result), ["id", "id_3", "id_2"]);
+        assert_eq!(
+            to_string(&result, Format::JsonLines).unwrap(),
+            "{\"id\":1,\"id_3\":2,\"id_2\":3}\n"
+        );
+    }
+
+    #[test]
+    fn binary_formats_are_not_text() {
+        let result = result(&["id"], vec![]);
+
+        assert!(to_string(&result, Format::Xlsx).is_err());
+        assert!(to_string(&result, Format::Parquet).is_err());
+    }
+
+    #[test]
+    fn numeric_columns_fit_a_decimal() {
+        let numeric = |n: &str| vec![Value::Numeric(n.into())];
+
+        assert!(matches!(
+            decimal(
+                &[numeric("-12.5"), numeric("003.125"), vec![Value::Null]],
+                0
+            ),
+            Some(ParquetKind::Decimal {
+                precision: 5,
+                scale: 3
+            })
+        ));
+        assert!(decimal(&[numeric("NaN")], 0).is_none());
+        assert!(decimal(&[numeric(&"9".repeat(39))], 0).is_none());
+        assert!(decimal(&[vec![Value::Int(1)]], 0).is_none());
+
+        assert_eq!(unscaled("-12.5", 3), Some(-12500));
+        assert_eq!(unscaled("7", 2), Some(700));
+    }
+
+    #[test]
+    fn copies_only_queries() {
+        assert_eq!(
+            copy_statement("select * from orders;", Format::Csv).unwrap(),
+            "COPY (select * from orders) TO STDOUT WITH (FORMAT csv, HEADER)"
+        );
+        assert!(copy_statement("select 1", Format::JsonLines)
+            .unwrap()
+            .starts_with("COPY (SELECT row_to_json(q) FROM (select 1) q)"));
+        assert!(copy_statement("insert into t values (1) returning *", Format::Csv).is_err());
+        assert!(copy_statement("select 1; select 2", Format::Csv).is_err());
+        assert!(copy_statement("select 1", Format::Markdown).is_err());
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+
+use regex::Regex;
+use sqlparser::ast::{
+    visit_expressions_mut, BinaryOperator, Expr, FromTable, Ident, ObjectName, Query, SetExpr,
+    Statement, Table, TableAlias, TableFactor, Visit, VisitMut, Visitor, VisitorMut,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::Token;
+
+use crate::access::{names, normalize, uncheckable, Namespace};
+use crate::config::RowFilter;
+use crate::connection::Connection;
+
+/// The row filters of a connection, with their variables filled in.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    /// Schema and name of each filtered table with its predicate, or why
+    /// the predicate cannot be used.
+    predicates: BTreeMap<(String, String), Result<Expr, String>>,
+}
+
+impl Filters {
+    pub fn new(connection: &Connection) -> Self {
+        let predicates = connection
+            .row_filters
+            .iter()
+            .map(|filter| {
+                let table = match filter.table.split_once('.') {
+                    Some((schema, table)) => (schema.to_string(), table.to_string()),
+                    None => ("public".to_string(), filter.table.clone()),
+                };
+                (table, predicate(filter, &connection.variables))
+            })
+            .collect();
+
+        Self { predicates }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.predicates.is_empty()
+    }
+
+    /// Whether rows of the table are filtered.
+    pub fn applies(&self, schema: &str, table: &str) -> bool {
+        self.predicates
+            .contains_key(&(schema.to_string(), table.to_string()))
+    }
+
+    /// `sql` with the predicates added to every filtered table it reads,
+    /// in subqueries, CTEs and joins too. Filtered tables are read through
+    /// `(SELECT * FROM table WHERE predicate)` under the table's name or
+    /// alias; `UPDATE` and `DELETE` get the predicate in their `WHERE`.
+    /// SQL that cannot be rewritten safely is refused. Returns `sql` as it
+    /// is when no filter applies.
+    pub fn apply(&self, sql: &str, namespace: &Namespace) -> Result<String, String> {
+        if self.is_empty() {
+            return Ok(sql.to_string());
+        }
+
+        let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, sql)
+            .map_err(|e| format!("the statement cannot be scoped by the row filters: {}", e))?;
+
+        let mut rewritten = false;
+
+        for statement in &mut statements {
+            rewritten |= self.scope(statement, namespace)?;
+        }
+
+        if !rewritten {
+            return Ok(sql.to_string());
+        }
+
+        Ok(statements
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(";\n"))
+    }
+
+    /// Adds the predicates to one statement. Every reference to a filtered
+    /// table must end up scoped, otherwise the statement is refused.
+    fn scope(&self, statement: &mut Statement, namespace: &Namespace) -> Result<bool, String> {
+        let mut uses = Uses::default();
+        let _ = Visit::visit(&*statement, &mut uses);
+
+        // What these read cannot be seen, let alone scoped.
+        if let Some(problem) = uses.functions.iter().find_map(|f| uncheckable(f)) {
+            return Err(problem);
+        }
+
+        if let Some(cte) = uses
+            .ctes
+            .iter()
+            .find(|cte| self.is_filtered(&[(*cte).clone()], namespace))
+        {
+            return Err(format!(
+                "the CTE {} has the name of a table with a row filter",
+                cte
+            ));
+        }
+
+        if let Some(problem) = uses
+            .relations
+            .iter()
+            .filter(|r| !(r.len() == 1 && uses.ctes.contains(&r[0])))
+            .find_map(|r| namespace.ambiguous(r))
+        {
+            return Err(problem);
+        }
+
+        if let Some(problem) = uses
+            .relations
+            .iter()
+            .filter(|r| !(r.len() == 1 && uses.ctes.contains(&r[0])))
+            .find_map(|r| self.view_problem(r, namespace))
+        {
+            return Err(problem);
+        }
+
+        let filtered: Vec<&Vec<String>> = uses
+            .relations
+            .iter()
+            .filter(|relation| self.is_filtered(relation, namespace))
+            .collect();
+
+        if filtered.is_empty() {
+            return Ok(false);
+        }
+
+        if let Statement::Insert(insert) = statement {
+            if self.is_filtered(&names(&insert.table_name), namespace) {
+                return Err(format!(
+                    "rows cannot be added to {}, which has a row filter",
+                    insert.table_name
+                ));
+            }
+        }
+
+        let mut scoper = Scoper {
+            filters: self,
+            namespace,
+            targets: vec![],
+            scoped: 0,
+            error: None,
+        };
+        let _ = VisitMut::visit(statement, &mut scoper);
+
+        if let Some(error) = scoper.error {
+            return Err(error);
+        }
+
+        if scoper.scoped != filtered.len() {
+            return Err(format!(
+                "the statement uses {} in a way the row filter cannot be applied to",
+                filtered[0].join(".")
+            ));
+        }
+
+        Ok(true)
+    }
+
+    /// Why a view cannot be read: it reads a filtered table, which the
+    /// predicate cannot be added to from outside.
+    fn view_problem(&self, relation: &[String], namespace: &Namespace) -> Option<String> {
+        let view = namespace.resolve(relation)?;
+        let table = namespace
+            .view_tables(&view)
+            .into_iter()
+            .find(|(schema, table)| self.applies(schema, table))?;
+
+        Some(format!(
+            "{}.{} is a view over {}.{}, which has a row filter",
+            view.0, view.1, table.0, table.1
+        ))
+    }
+
+    fn is_filtered(&self, relation: &[String], namespace: &Namespace) -> bool {
+        match namespace.resolve(relation) {
+            Some((schema, table)) => self.applies(&schema, &table),
+            None => false,
+        }
+    }
+
+    fn predicate(
+        &self,
+        relation: &[String],
+        namespace: &Namespace,
+    ) -> Option<Result<Expr, String>> {
+        let table = namespace.resolve(relation)?;
+        self.predicates.get(&table).cloned()
+    }
+}
+
+/// CTE names, relations and function calls of a statement.
+#[derive(Default)]
+struct Uses {
+    ctes: BTreeSet<String>,
+    relations: Vec<Vec<String>>,
+    functions: Vec<Vec<String>>,
+}
+
+impl Visitor for Uses {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.ctes.insert(normalize(&cte.alias.name));
+            }
+        }
+
+        // `TABLE name` holds its name as text, which is not visited as a
+        // relation.
+        let mut tables = vec![];
+        table_commands(&query.body, &mut tables);
+        self.relations.extend(tables.into_iter().map(table_names));
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<()> {
+        self.relations.push(names(relation));
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        match table_factor {
+            TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            }
+            | TableFactor::Function { name, .. } => self.functions.push(names(name)),
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if let Expr::Function(function) = expr {
+            self.functions.push(names(&function.name));
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+/// The `TABLE name` commands of a query body, set operations included.
+fn table_commands<'a>(body: &'a SetExpr, tables: &mut Vec<&'a Table>) {
+    match body {
+        SetExpr::Table(table) => tables.push(table),
+        SetExpr::SetOperation { left, right, .. } => {
+            table_commands(left, tables);
+            table_commands(right, tables);
+        }
+        _ => {}
+    }
+}
+
+/// Schema and name of a `TABLE name` command. Their quotes are not kept,
+/// so they are folded as Postgres folds the command as it is written out.
+fn table_names(table: &Table) -> Vec<String> {
+    [&table.schema_name, &table.table_name]
+        .into_iter()
+        .flatten()
+        .map(|name| name.to_lowercase())
+        .collect()
+}
+
+/// Rewrites the references to filtered tables, counting those it scoped.
+struct Scoper<'a> {
+    filters: &'a Filters,
+    namespace: &'a Namespace,
+    /// Tables changed by `UPDATE` or `DELETE`, which are filtered in the
+    /// statement's `WHERE` rather than replaced.
+    targets: Vec<*const TableFactor>,
+    scoped: usize,
+    error: Option<String>,
+}
+
+impl Scoper<'_> {
+    fn table_commands(&mut self, body: &mut SetExpr) -> ControlFlow<()> {
+        match body {
+            SetExpr::Table(table) => {
+                let relation = table_names(table);
+                if !self.filters.is_filtered(&relation, self.namespace) {
+                    return ControlFlow::Continue(());
+                }
+
+                let name = ObjectName(relation.iter().map(|n| Ident::new(n.as_str())).collect());
+                match Parser::parse_sql(&PostgreSqlDialect {}, &format!("SELECT * FROM {}", name))
+                    .ok()
+                    .and_then(|mut statements| statements.pop())
+                {
+                    Some(Statement::Query(query)) => {
+                        *body = *query.body;
+                        ControlFlow::Continue(())
+                    }
+                    _ => {
+                        self.error = Some(format!("the row filter on {} cannot be applied", name));
+                        ControlFlow::Break(())
+                    }
+                }
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.table_commands(left)?;
+                self.table_commands(right)
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    /// The predicate of a filtered table, or none for other tables.
+    fn predicate(&mut self, name: &ObjectName) -> ControlFlow<(), Option<Expr>> {
+        match self.filters.predicate(&names(name), self.namespace) {
+            Some(Ok(predicate)) => ControlFlow::Continue(Some(predicate)),
+            Some(Err(e)) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+            None => ControlFlow::Continue(None),
+        }
+    }
+
+    /// Adds the predicate of a changed table to the statement's `WHERE`.
+    fn target(&mut self, factor: &TableFactor, selection: &mut Option<Expr>) -> ControlFlow<()> {
+        let TableFactor::Table { name, alias, .. } = factor else {
+            return ControlFlow::Continue(());
+        };
+
+        let Some(mut predicate) = self.predicate(name)? else {
+            return ControlFlow::Continue(());
+        };
+
+        let qualifier = match alias {
+            Some(alias) => alias.name.clone(),
+            None => name.0.last().cloned().unwrap_or_else(|| Ident::new("")),
+        };
+        let _ = visit_expressions_mut(&mut predicate, |expr| {
+            if let Expr::Identifier(column) = expr {
+                *expr = Expr::CompoundIdentifier(vec![qualifier.clone(), column.clone()]);
+            }
+            ControlFlow::<()>::Continue(())
+        });
+
+        let predicate = Expr::Nested(Box::new(predicate));
+        *selection = Some(match selection.take() {
+            Some(selection) => Expr::BinaryOp {
+                left: Box::new(Expr::Nested(Box::new(selection))),
+                op: BinaryOperator::And,
+                right: Box::new(predicate),
+            },
+            None => predicate,
+        });
+
+        self.targets.push(factor);
+        self.scoped += 1;
+
+        ControlFlow::Continue(())
+    }
+}
+
+impl VisitorMut for Scoper<'_> {
+    type Break = ();
+
+    // `TABLE name` on a filtered table becomes `SELECT * FROM name`, whose
+    // table is then scoped like any other when the body is visited.
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        self.table_commands(&mut query.body)
+    }
+
+    fn pre_visit_statement(&mut self, statement: &mut Statement) -> ControlFlow<()> {
+        match statement {
+            Statement::Update {
+                table, selection, ..
+            } => self.target(&table.relation, selection),
+            Statement::Delete(delete) => {
+                let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) =
+                    &delete.from;
+                for table in from {
+                    self.target(&table.relation, &mut delete.selection)?;
+                }
+                ControlFlow::Continue(())
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    // Replacing after the children were visited keeps the new subquery from
+    // being visited again.
+    fn post_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<()> {
+        if self.targets.contains(&(factor as *const TableFactor)) {
+            return ControlFlow::Continue(());
+        }
+
+        let TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } = factor
+        else {
+            return ControlFlow::Continue(());
+        };
+
+        let Some(predicate) = self.predicate(name)? else {
+            return ControlFlow::Continue(());
+        };
+
+        let sql = format!("SELECT * FROM {} WHERE {}", name, predicate);
+        let subquery = match Parser::parse_sql(&PostgreSqlDialect {}, &sql) {
+            Ok(mut statements) => match statements.pop() {
+                Some(Statement::Query(query)) => query,
+                _ => {
+                    self.error = Some(format!("the row filter on {} cannot be applied", name));
+                    return ControlFlow::Break(());
+                }
+            },
+            Err(e) => {
+                self.error = Some(format!(
+                    "the row filter on {} cannot be applied: {}",
+                    name, e
+                ));
+                return ControlFlow::Break(());
+            }
+        };
+
+        let alias = alias.clone().unwrap_or_else(|| TableAlias {
+            name: name.0.last().cloned().unwrap_or_else(|| Ident::new("")),
+            columns: vec![],
+        });
+
+        *factor = TableFactor::Derived {
+            lateral: false,
+            subquery,
+            alias: Some(alias),
+        };
+        self.scoped += 1;
+
+        ControlFlow::Continue(())
+    }
+}
+
+/// The predicate of a filter with its variables replaced by quoted
+/// literals.
+fn predicate(filter: &RowFilter, variables: &BTreeMap<String, String>) -> Result<Expr, String> {
+    let variable = Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    let mut missing = None;
+
+    let text = variable.replace_all(
+        &filter.predicate,
+        |captures: &regex::Captures| match variables.get(&captures[1]) {
+            Some(value) => format!("'{}'", value.replace('\'', "''")),
+            None => {
+                missing = Some(captures[0].to_string());
+                String::new()
+            }
+        },
+    );
+
+    if let Some(missing) = missing {
+        return Err(format!(
+            "the row filter on {} uses {}, which is not set",
+            filter.table, missing
+        ));
+    }
+
+    Parser::new(&PostgreSqlDialect {})
+        .try_with_sql(&text)
+        .and_then(|mut parser| {
+            let expr = parser.parse_expr()?;
+            parser.expect_token(&Token::EOF)?;
+            Ok(expr)
+        })
+        .map_err(|e| format!("the row filter on {} is not valid: {}", filter.table, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::catalog;
+
+    fn filters(table: &str, predicate: &str) -> Filters {
+        let filter = RowFilter {
+            table: table.to_string(),
+            predicate: predicate.to_string(),
+        };
+        let variables = BTreeMap::from([("tenant".to_string(), "acme".to_string())]);
+        let (schema, name) = table.split_once('.').unwrap();
+
+        Filters {
+            predicates: BTreeMap::from([(
+                (schema.to_string(), name.to_string()),
+                super::predicate(&filter, &variables),
+            )]),
+        }
+    }
+
+    fn orders() -> Namespace {
+        namespace(&[
+            ("public.orders", &["id", "tenant_id"]),
+            ("public.users", &["id", "name"]),
+        ])
+    }
+
+    fn namespace(tables: &[(&str, &[&str])]) -> Namespace {
+        Namespace::new(&catalog(tables), vec!["public".to_string()], vec![])
+    }
+
+    #[test]
+    fn scopes_filtered_tables_under_their_alias() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+        let sql = filters
+            .apply(
+                "SELECT o.id FROM orders AS o JOIN users u ON u.id = o.id",
+                &orders(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            sql,
+            "SELECT o.id FROM (SELECT * FROM orders WHERE tenant_id = 'acme') AS o \
+             JOIN users AS u ON u.id = o.id"
+        );
+    }
+
+    #[test]
+    fn scopes_table_commands() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+        let sql = filters
+            .apply("SELECT * FROM users UNION ALL TABLE orders", &orders())
+            .unwrap();
+
+        assert!(sql.contains("tenant_id = 'acme'"), "{}", sql);
+
+        // The parser only reads `TABLE` in set operations, so on its own it
+        // is refused rather than run unfiltered.
+        assert!(filters.apply("TABLE orders", &orders()).is_err());
+    }
+
+    #[test]
+    fn leaves_unfiltered_tables_alone() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+        let sql = "SELECT * FROM users";
+
+        assert_eq!(filters.apply(sql, &orders()).unwrap(), sql);
+    }
+
+    #[test]
+    fn refuses_names_in_more_than_one_schema() {
+        let catalog = namespace(&[
+            ("analytics.orders", &["id"]),
+            ("public.orders", &["id", "tenant_id"]),
+        ]);
+        let filters = filters("public.orders", "tenant_id = $tenant");
+
+        assert!(filters.apply("SELECT * FROM orders", &catalog).is_err());
+        assert!(filters
+            .apply("SELECT * FROM public.orders", &catalog)
+            .unwrap()
+            .contains("tenant_id = 'acme'"));
+    }
+
+    #[test]
+    fn refuses_ctes_named_like_filtered_tables() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+        let sql = "WITH orders AS (SELECT 1) SELECT * FROM orders";
+
+        assert!(filters.apply(sql, &orders()).is_err());
+    }
+
+    #[test]
+    fn refuses_inserts_into_filtered_tables() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+        let sql = "INSERT INTO orders SELECT * FROM orders";
+
+        assert!(filters.apply(sql, &orders()).is_err());
+    }
+
+    #[test]
+    fn quotes_variables() {
+        let filter = RowFilter {
+            table: "orders".to_string(),
+            predicate: "tenant_id = $tenant".to_string(),
+        };
+        let variables = BTreeMap::from([("tenant".to_string(), "a' OR '1'='1".to_string())]);
+
+        assert_eq!(
+            predicate(&filter, &variables).unwrap().to_string(),
+            "tenant_id = 'a'' OR ''1''=''1'"
+        );
+        assert!(predicate(&filter, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn refuses_functions_that_run_sql_text() {
+        let filters = filters("public.orders", "tenant_id = $tenant");
+
+        for sql in [
+            "SELECT query_to_xml('SELECT * FROM orders', true, true, '')",
+            "SELECT table_to_xml('orders', true, true, '')",
+            "SELECT * FROM dblink('dbname=postgres', 'SELECT * FROM orders') AS t(id int)",
+        ] {
+            assert!(filters.apply(sql, &orders()).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn refuses_views_over_filtered_tables() {
+        let catalog = catalog(&[
+            ("public.orders", &["id", "tenant_id"]),
+            ("public.recent", &["id"]),
+            ("public.names", &["name"]),
+        ]);
+        let view_tables = vec![(
+            ("public".to_string(), "recent".to_string()),
+            ("public".to_string(), "orders".to_string()),
+        )];
+        let namespace = Namespace::new(&catalog, vec!["public".to_string()], view_tables);
+        let filters = filters("public.orders", "tenant_id = $tenant");
+
+        assert!(filters.apply("SELECT * FROM recent", &namespace).is_err());
+        assert!(filters
+            .apply("SELECT * FROM public.recent", &namespace)
+            .is_err());
+        assert!(filters.apply("SELECT * FROM names", &namespace).is_ok());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::results::{ResultSet, Value};
+
+static DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"\b\d{4}-\d{2}-\d{2}(?:[ T]\d{2}:\d{2}(?::\d{2})?)?\b",
+        r"|\b\d{1,2}:\d{2}(?::\d{2})?\b",
+    ))
+    .unwrap()
+});
+
+static ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\*\*[^*\n]+\*\*|`[^`\n]+`|"[^"\n]+"|“[^”\n]+”"#).unwrap());
+
+/// Numbers not part of a word, with their sign and a scale or percent sign
+/// after them.
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?i)(?:^|[^\w.])",
+        r"(?P<number>[-−]?(?:\d{1,3}(?:,\d{3})+(?:\.\d+)?|\d+(?:\.\d+)?))",
+        r"(?:\s*(?P<scale>%|percent\b|k\b|thousand\b|m\b|million\b|bn\b|billion\b))?",
+    ))
+    .unwrap()
+});
+
+/// A number or name stated in an answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claim {
+    /// The claim as written in the answer.
+    pub text: String,
+    pub kind: ClaimKind,
+    /// What the claim was found in, none when it is unsupported.
+    pub support: Option<Support>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClaimKind {
+    Number,
+    /// A quoted, bold or code span, or a date.
+    Entity,
+}
+
+/// Where a claim was found. Results are named by the index of their
+/// function message, as in the session's results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Support {
+    /// A value of a row of a result.
+    Value { result: usize, row: usize },
+    /// The number of rows of a result.
+    RowCount { result: usize },
+    /// A column name or the SQL of a result.
+    Query { result: usize },
+    /// The question itself.
+    Question,
+}
+
+/// The result sets an answer was based on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// Index of the function message with the result.
+    pub result: usize,
+    pub query: String,
+    pub rows: usize,
+}
+
+/// How well an answer is backed by the results of its turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grounding {
+    pub claims: Vec<Claim>,
+    pub sources: Vec<Source>,
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Support::Value { row, .. } => write!(f, "row {}", row + 1),
+            Support::RowCount { .. } => write!(f, "row count"),
+            Support::Query { .. } => write!(f, "query"),
+            Support::Question => write!(f, "question"),
+        }
+    }
+}
+
+impl Grounding {
+    pub fn unsupported(&self) -> impl Iterator<Item = &Claim> {
+        self.claims.iter().filter(|claim| claim.support.is_none())
+    }
+}
+
+/// Checks the numbers and names of an answer against the results it was
+/// written from. Numbers match a value when they round to it as written,
+/// so `12.3` matches `12.34` and `1.2 million` matches `1234567`; shares
+/// written with `%` also match fractions. Numbers and names that only
+/// appear in the SQL or the question are supported by those.
+pub fn check(answer: &str, question: &str, results: &BTreeMap<usize, &ResultSet>) -> Grounding {
+    let sources = results
+        .iter()
+        .map(|(result, set)| Source {
+            result: *result,
+            query: set.query.clone(),
+            rows: set.rows.len(),
+        })
+        .collect();
+
+    let mut claims = vec![];
+    let mut rest = answer.to_string();
+
+    for (text, range) in spans(&DATE, &rest) {
+        let support = find_entity(&text, question, results);
+        claims.push(Claim {
+            text,
+            kind: ClaimKind::Entity,
+            support,
+        });
+        rest.replace_range(range.clone(), &" ".repeat(range.len()));
+    }
+
+    for (text, _) in spans(&ENTITY, &rest) {
+        let name = text.trim_matches(|c| "\"'`*“”".contains(c)).trim();
+        // Spans with digits are checked as numbers.
+        if name.is_empty() || name.chars().any(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        claims.push(Claim {
+            text: name.to_string(),
+            kind: ClaimKind::Entity,
+            support: find_entity(name, question, results),
+        });
+    }
+
+    for captures in NUMBER.captures_iter(&rest) {
+        let digits = captures.name("number").unwrap();
+        let scale = captures.name("scale");
+        let end = scale.map_or(digits.end(), |s| s.end());
+
+        // Numbers run into a word, as in `3rd`, are not amounts.
+        let word = rest[end..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+        if word || is_list_marker(&rest, digits.start(), digits.end()) {
+            continue;
+        }
+
+        let text = &rest[digits.start()..end];
+        let digits = digits.as_str();
+        let Ok(value) = plain(digits).parse::<f64>() else {
+            continue;
+        };
+        let decimals = digits.split_once('.').map_or(0, |(_, d)| d.len());
+        let scale = scale.map(|s| s.as_str().to_lowercase());
+        let number = Number {
+            value,
+            decimals,
+            multiplier: match scale.as_deref() {
+                Some("k" | "thousand") => 1e3,
+                Some("m" | "million") => 1e6,
+                Some("bn" | "billion") => 1e9,
+                _ => 1.0,
+            },
+            percent: matches!(scale.as_deref(), Some("%" | "percent")),
+        };
+
+        claims.push(Claim {
+            text: text.to_string(),
+            kind: ClaimKind::Number,
+            support: find_number(&number, digits, question, results),
+        });
+    }
+
+    Grounding { claims, sources }
+}
+
+/// A number as written: its value, how precisely it was given and its unit.
+struct Number {
+    value: f64,
+    decimals: usize,
+    multiplier: f64,
+    percent: bool,
+}
+
+impl Number {
+    /// Whether the number is `value` rounded as written.
+    fn matches(&self, value: f64) -> bool {
+        let tolerance = 0.5 * 10f64.powi(-(self.decimals as i32)) * self.multiplier;
+        let stated = self.value * self.multiplier;
+        let close = |value: f64| (value - stated).abs() <= tolerance + f64::EPSILON;
+
+        close(value) || (self.percent && close(value * 100.0))
+    }
+}
+
+fn find_number(
+    number: &Number,
+    digits: &str,
+    question: &str,
+    results: &BTreeMap<usize, &ResultSet>,
+) -> Option<Support> {
+    for (result, set) in results {
+        for (row, values) in set.rows.iter().enumerate() {
+            if values
+                .iter()
+                .filter_map(number_value)
+                .any(|v| number.matches(v))
+            {
+                return Some(Support::Value {
+                    result: *result,
+                    row,
+                });
+            }
+        }
+    }
+
+    for (result, set) in results {
+        if number.multiplier == 1.0 && number.matches(set.rows.len() as f64) {
+            return Some(Support::RowCount { result: *result });
+        }
+    }
+
+    let digits = plain(digits);
+    let mentions = |text: &str| {
+        NUMBER
+            .captures_iter(text)
+            .any(|c| plain(&c["number"]) == digits)
+    };
+
+    for (result, set) in results {
+        if mentions(&set.query) {
+            return Some(Support::Query { result: *result });
+        }
+    }
+
+    mentions(question).then_some(Support::Question)
+}
+
+fn find_entity(
+    name: &str,
+    question: &str,
+    results: &BTreeMap<usize, &ResultSet>,
+) -> Option<Support> {
+    let name = name.to_lowercase();
+
+    for (result, set) in results {
+        for (row, values) in set.rows.iter().enumerate() {
+            let found = values.iter().any(|value| {
+                let text = value.to_string().to_lowercase();
+                text == name || (!value.is_null() && text.starts_with(&name))
+            });
+            if found {
+                return Some(Support::Value {
+                    result: *result,
+                    row,
+                });
+            }
+        }
+    }
+
+    for (result, set) in results {
+        let in_query = set.columns.iter().any(|c| c.name.to_lowercase() == name)
+            || set.query.to_lowercase().contains(&name);
+        if in_query {
+            return Some(Support::Query { result: *result });
+        }
+    }
+
+    question
+        .to_lowercase()
+        .contains(&name)
+        .then_some(Support::Question)
+}
+
+/// A number as written, without thousands separators and with an ASCII
+/// minus sign.
+fn plain(digits: &str) -> String {
+    digits.replace(',', "").replace('−', "-")
+}
+
+/// Numeric values of a cell, including numbers held as text.
+fn number_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::Text(text) => text.trim().parse().ok(),
+        value => value.as_f64(),
+    }
+}
+
+/// Whether a number starts a line as in `1.` or `2)`, numbering a list.
+fn is_list_marker(text: &str, start: usize, end: usize) -> bool {
+    let line_start = text[..start].trim_end_matches([' ', '\t']).is_empty()
+        || text[..start].trim_end_matches([' ', '\t']).ends_with('\n');
+
+    line_start && (text[end..].starts_with(". ") || text[end..].starts_with(") "))
+}
+
+fn spans(pattern: &Regex, text: &str) -> Vec<(String, std::ops::Range<usize>)> {
+    pattern
+        .find_iter(text)
+        .map(|m| (m.as_str().to_string(), m.range()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::Column;
+
+    fn sales() -> ResultSet {
+        ResultSet {
+            query: "SELECT city, total FROM sales WHERE year = 2023".into(),
+            columns: ["city", "total"]
+                .iter()
+                .map(|name| Column {
+                    name: name.to_string(),
+                    type_name: "text".into(),
+                    source: None,
+                })
+                .collect(),
+            rows: vec![
+                vec![
+                    Value::Text("Berlin".into()),
+                    Value::Numeric("1234567.00".into()),
+                ],
+                vec![Value::Text("Paris".into()), Value::Int(42)],
+            ],
+            ..ResultSet::default()
+        }
+    }
+
+    fn support(grounding: &Grounding, text: &str) -> Option<Support> {
+        grounding
+            .claims
+            .iter()
+            .find(|claim| claim.text == text)
+            .unwrap_or_else(|| panic!("no claim {:?} in {:?}", text, grounding.claims))
+            .support
+    }
+
+    #[test]
+    fn finds_numbers_and_names_in_results() {
+        let sales = sales();
+        let results = BTreeMap::from([(3, &sales)]);
+        let answer = "**Berlin** sold 1.2 million and `paris` sold 42 across 2 cities in 2023.";
+        let grounding = check(answer, "How did sales go?", &results);
+
+        assert_eq!(
+            support(&grounding, "Berlin"),
+            Some(Support::Value { result: 3, row: 0 })
+        );
+        assert_eq!(
+            support(&grounding, "paris"),
+            Some(Support::Value { result: 3, row: 1 })
+        );
+        assert_eq!(
+            support(&grounding, "1.2 million"),
+            Some(Support::Value { result: 3, row: 0 })
+        );
+        assert_eq!(
+            support(&grounding, "42"),
+            Some(Support::Value { result: 3, row: 1 })
+        );
+        assert_eq!(
+            support(&grounding, "2"),
+            Some(Support::RowCount { result: 3 })
+        );
+        assert_eq!(
+            support(&grounding, "2023"),
+            Some(Support::Query { result: 3 })
+        );
+        assert_eq!(grounding.unsupported().count(), 0);
+        assert_eq!(grounding.sources[0].rows, 2);
+    }
+
+    #[test]
+    fn flags_what_nothing_supports() {
+        let sales = sales();
+        let results = BTreeMap::from([(3, &sales)]);
+        let answer = "**Madrid** sold 1.3 million, up 15% since 2024-01-05.";
+        let grounding = check(answer, "What changed since 2024-01-05?", &results);
+
+        let unsupported: Vec<&str> = grounding.unsupported().map(|c| c.text.as_str()).collect();
+        assert_eq!(unsupported, ["Madrid", "1.3 million", "15%"]);
+        assert_eq!(support(&grounding, "2024-01-05"), Some(Support::Question));
+    }
+
+    #[test]
+    fn percentages_match_fractions() {
+        let share = ResultSet {
+            rows: vec![vec![Value::Float(0.1234)]],
+            ..ResultSet::default()
+        };
+        let results = BTreeMap::from([(1, &share)]);
+
+        let grounding = check("About 12.3% of orders", "", &results);
+        assert_eq!(
+            support(&grounding, "12.3%"),
+            Some(Support::Value { result: 1, row: 0 })
+        );
+
+        let grounding = check("About 13% of orders", "", &results);
+        assert_eq!(support(&grounding, "13%"), None);
+    }
+
+    #[test]
+    fn skips_list_markers_and_ordinals() {
+        let results = BTreeMap::new();
+        let grounding = check("Top cities:\n1. Berlin\n2) Paris, the 3rd", "", &results);
+
+        assert!(grounding.claims.is_empty(), "{:?}", grounding.claims);
+    }
+}
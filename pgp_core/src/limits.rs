@@ -0,0 +1,217 @@
+use std::error::Error as StdError;
+use std::future::Future;
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use sqlparser::ast::{Expr, SetExpr, Statement as SqlStatement, Value as SqlValue};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use tokio::time::{timeout_at, Instant};
+use tokio_postgres::types::{FromSql, ToSql, Type};
+use tokio_postgres::{Client, Row, Statement};
+
+use crate::cancel::Canceller;
+use crate::config::Limits;
+use crate::results::Truncation;
+
+/// `sql` with a `LIMIT` one row over `max_rows`, so a cut result can be
+/// told from one that fits exactly. Queries with a lower limit, statements
+/// other than queries, `WITH … INSERT`/`UPDATE` and SQL that does not parse
+/// are left as they are.
+pub fn limit(sql: &str, max_rows: usize) -> String {
+    if max_rows == 0 {
+        return sql.to_string();
+    }
+
+    let Ok(mut statements) = Parser::parse_sql(&PostgreSqlDialect {}, sql) else {
+        return sql.to_string();
+    };
+
+    let [SqlStatement::Query(query)] = statements.as_mut_slice() else {
+        return sql.to_string();
+    };
+
+    if query.fetch.is_some() || matches!(*query.body, SetExpr::Insert(_) | SetExpr::Update(_)) {
+        return sql.to_string();
+    }
+
+    let wanted = max_rows + 1;
+    // `LIMIT ALL` and expressions are replaced.
+    let lower = match &query.limit {
+        Some(Expr::Value(SqlValue::Number(n, _))) => n.parse::<usize>().is_ok_and(|n| n <= wanted),
+        _ => false,
+    };
+
+    if lower {
+        return sql.to_string();
+    }
+
+    query.limit = Some(Expr::Value(SqlValue::Number(wanted.to_string(), false)));
+
+    query.to_string()
+}
+
+/// Reads the rows of a prepared query as they arrive and stops at the first
+/// limit reached. A query still running at the deadline is cancelled on the
+/// server; the rows read by then are returned when `partial`, otherwise the
+/// cancel is, as the transaction it aborted can only be rolled back.
+pub async fn fetch(
+    client: &Client,
+    statement: &Statement,
+    limits: &Limits,
+    canceller: &Canceller,
+    partial: bool,
+) -> Result<(Vec<Row>, Option<Truncation>), tokio_postgres::Error> {
+    let max_duration = Duration::from_secs(limits.max_duration);
+    let deadline = match limits.max_duration {
+        0 => None,
+        _ => Some(Instant::now() + max_duration),
+    };
+
+    let mut rows = vec![];
+    let mut bytes = 0;
+
+    let mut query = std::pin::pin!(client.query_raw(statement, no_params()));
+    let stream = match within(deadline, query.as_mut()).await {
+        Some(stream) => stream?,
+        None => {
+            let finished = async { drain(query.await?).await };
+            return cut(canceller, finished, rows, max_duration, partial).await;
+        }
+    };
+    let mut stream = std::pin::pin!(stream);
+
+    loop {
+        let row = match within(deadline, stream.next()).await {
+            Some(Some(row)) => row?,
+            Some(None) => return Ok((rows, None)),
+            None => return cut(canceller, drain(stream), rows, max_duration, partial).await,
+        };
+
+        if limits.max_rows > 0 && rows.len() == limits.max_rows {
+            return Ok((rows, Some(Truncation::Rows(limits.max_rows))));
+        }
+
+        bytes += size(&row);
+
+        if limits.max_bytes > 0 && bytes > limits.max_bytes {
+            return Ok((rows, Some(Truncation::Bytes(limits.max_bytes))));
+        }
+
+        rows.push(row);
+    }
+}
+
+/// Cancels the query and waits for the server to end it, so the cancel
+/// can't reach a later statement.
+async fn cut(
+    canceller: &Canceller,
+    finished: impl Future<Output = Result<(), tokio_postgres::Error>>,
+    rows: Vec<Row>,
+    max_duration: Duration,
+    partial: bool,
+) -> Result<(Vec<Row>, Option<Truncation>), tokio_postgres::Error> {
+    let _ = canceller.cancel_query().await;
+
+    match finished.await {
+        Err(e) if !partial => Err(e),
+        _ => Ok((rows, Some(Truncation::Duration(max_duration)))),
+    }
+}
+
+/// Reads what is left of a result, until it ends or fails.
+async fn drain(
+    stream: impl Stream<Item = Result<Row, tokio_postgres::Error>>,
+) -> Result<(), tokio_postgres::Error> {
+    let mut stream = std::pin::pin!(stream);
+    while let Some(row) = stream.next().await {
+        row?;
+    }
+    Ok(())
+}
+
+/// The future's output, or none when the deadline passes first.
+async fn within<F>(deadline: Option<Instant>, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+fn no_params() -> impl ExactSizeIterator<Item = &'static (dyn ToSql + Sync)> {
+    std::iter::empty()
+}
+
+/// Bytes of a row as sent by the server.
+fn size(row: &Row) -> usize {
+    (0..row.len())
+        .map(|i| row.try_get::<_, RawSize>(i).map_or(0, |s| s.0))
+        .sum()
+}
+
+/// Length of a value in the wire format, whatever its type.
+struct RawSize(usize);
+
+impl<'a> FromSql<'a> for RawSize {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn StdError + Sync + Send>> {
+        Ok(RawSize(raw.len()))
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Self, Box<dyn StdError + Sync + Send>> {
+        Ok(RawSize(0))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_queries_one_row_over() {
+        assert_eq!(limit("SELECT * FROM t", 10), "SELECT * FROM t LIMIT 11");
+        assert_eq!(
+            limit("SELECT * FROM t LIMIT ALL", 10),
+            "SELECT * FROM t LIMIT 11"
+        );
+        assert_eq!(
+            limit("SELECT * FROM t LIMIT 500", 10),
+            "SELECT * FROM t LIMIT 11"
+        );
+        assert_eq!(
+            limit("WITH x AS (SELECT 1) SELECT * FROM x", 10),
+            "WITH x AS (SELECT 1) SELECT * FROM x LIMIT 11"
+        );
+    }
+
+    #[test]
+    fn keeps_lower_limits() {
+        let sql = "SELECT * FROM t LIMIT 5";
+        assert_eq!(limit(sql, 10), sql);
+
+        let sql = "SELECT * FROM t FETCH FIRST 5 ROWS ONLY";
+        assert_eq!(limit(sql, 10), sql);
+    }
+
+    #[test]
+    fn leaves_other_statements_alone() {
+        for sql in [
+            "UPDATE t SET a = 1",
+            "INSERT INTO t SELECT * FROM u",
+            "WITH x AS (SELECT 1 AS v) INSERT INTO t SELECT v FROM x",
+            "WITH x AS (SELECT 1 AS v) UPDATE t SET a = x.v FROM x",
+            "SELECT 1; SELECT 2",
+            "not sql",
+        ] {
+            assert_eq!(limit(sql, 10), sql);
+        }
+
+        assert_eq!(limit("SELECT * FROM t", 0), "SELECT * FROM t");
+    }
+}
@@ -0,0 +1,524 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+
+use crate::config::{MaskAction, Masking};
+use crate::results::{ResultSet, Value};
+use crate::stats::TableStats;
+
+/// The kinds of personal data recognised in values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Email,
+    Phone,
+    Ip,
+    Card,
+    Geo,
+    /// Anything in a column matched by name.
+    Column,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Email => write!(f, "email"),
+            Kind::Phone => write!(f, "phone"),
+            Kind::Ip => write!(f, "ip"),
+            Kind::Card => write!(f, "card"),
+            Kind::Geo => write!(f, "geo"),
+            Kind::Column => write!(f, "redacted"),
+        }
+    }
+}
+
+/// Masks personal data in what is sent to the model, following the
+/// configured column rules and value patterns.
+#[derive(Debug, Clone)]
+pub struct Masker {
+    enabled: bool,
+    action: MaskAction,
+    detect_values: bool,
+    columns: Vec<(Regex, MaskAction)>,
+    detectors: Vec<(Kind, Regex)>,
+    salt: String,
+}
+
+impl Masker {
+    pub fn new(masking: &Masking) -> Self {
+        let columns = masking
+            .columns
+            .iter()
+            .filter_map(|rule| Some((glob(&rule.pattern)?, rule.action)))
+            .collect();
+
+        let salt = masking
+            .salt
+            .clone()
+            .unwrap_or_else(|| format!("{:016x}", RandomState::new().build_hasher().finish()));
+
+        Self {
+            enabled: masking.enabled,
+            action: masking.action,
+            detect_values: masking.detect_values,
+            columns,
+            detectors: detectors(),
+            salt,
+        }
+    }
+
+    /// The result as the model may see it. Dropped columns are left out.
+    pub fn result(&self, result: &ResultSet) -> ResultSet {
+        if !self.enabled {
+            return result.clone();
+        }
+
+        let actions: Vec<Option<MaskAction>> = result
+            .columns
+            .iter()
+            .map(|c| {
+                c.source
+                    .as_deref()
+                    .and_then(|source| self.column_action(source))
+                    .or_else(|| self.column_action(&c.name))
+            })
+            .collect();
+        let keep = |i: &usize| actions[*i] != Some(MaskAction::Drop);
+
+        let columns = (0..result.columns.len())
+            .filter(keep)
+            .map(|i| result.columns[i].clone())
+            .collect();
+
+        let rows = result
+            .rows
+            .iter()
+            .map(|row| {
+                (0..row.len())
+                    .filter(keep)
+                    .map(|i| match actions[i] {
+                        Some(action) => self.column_value(&row[i], action),
+                        None => self.value(&row[i]),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        ResultSet {
+            query: result.query.clone(),
+            columns,
+            rows,
+            truncated: result.truncated,
+        }
+    }
+
+    /// Masks the sampled values in column statistics. Columns dropped by a
+    /// rule are reported as excluded.
+    pub fn stats(&self, stats: &mut TableStats) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut excluded = vec![];
+
+        stats.columns.retain_mut(|column| {
+            let mask = |text: &str| match self.column_action(&column.column) {
+                Some(action) => Some(self.replace(text, Kind::Column, action)),
+                None if self.detect_values => self.text_value(text),
+                None => Some(text.to_string()),
+            };
+
+            if self.column_action(&column.column) == Some(MaskAction::Drop) {
+                excluded.push(column.column.clone());
+                return false;
+            }
+
+            column.common_values = column
+                .common_values
+                .iter()
+                .filter_map(|(value, frequency)| Some((mask(value)?, *frequency)))
+                .collect();
+            column.min = column.min.as_deref().and_then(mask);
+            column.max = column.max.as_deref().and_then(mask);
+
+            true
+        });
+
+        stats.excluded.extend(excluded);
+    }
+
+    /// Free text, such as an error message, with recognised values masked.
+    pub fn text(&self, text: &str) -> String {
+        if !self.enabled || !self.detect_values {
+            return text.to_string();
+        }
+
+        self.detectors
+            .iter()
+            .fold(text.to_string(), |text, (kind, regex)| {
+                regex
+                    .replace_all(&text, |captures: &regex::Captures| {
+                        let found = &captures[0];
+                        if *kind == Kind::Card && !luhn(found) {
+                            return found.to_string();
+                        }
+                        match self.action {
+                            MaskAction::Drop => String::new(),
+                            action => self.replace(found, *kind, action),
+                        }
+                    })
+                    .into_owned()
+            })
+    }
+
+    fn column_action(&self, name: &str) -> Option<MaskAction> {
+        self.columns
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(name))
+            .map(|(_, action)| *action)
+    }
+
+    fn column_value(&self, value: &Value, action: MaskAction) -> Value {
+        match value {
+            Value::Null => Value::Null,
+            value => Value::Text(self.replace(&value.to_string(), Kind::Column, action)),
+        }
+    }
+
+    fn value(&self, value: &Value) -> Value {
+        if !self.detect_values {
+            return value.clone();
+        }
+
+        match value {
+            Value::Text(text) => match self.text_value(text) {
+                Some(text) => Value::Text(text),
+                None => Value::Null,
+            },
+            Value::Json(json) => Value::Json(self.json(json)),
+            Value::Array(values) => Value::Array(values.iter().map(|v| self.value(v)).collect()),
+            Value::Int(_) | Value::Numeric(_) => {
+                let text = value.to_string();
+                match self.text_value(&text) {
+                    Some(masked) if masked == text => value.clone(),
+                    Some(masked) => Value::Text(masked),
+                    None => Value::Null,
+                }
+            }
+            value => value.clone(),
+        }
+    }
+
+    /// A text value with recognised data masked, or nothing when it holds
+    /// some and values are dropped.
+    fn text_value(&self, text: &str) -> Option<String> {
+        let masked = self.text(text);
+
+        if self.action == MaskAction::Drop && masked != text {
+            return None;
+        }
+
+        Some(masked)
+    }
+
+    fn json(&self, json: &serde_json::Value) -> serde_json::Value {
+        match json {
+            serde_json::Value::String(text) => match self.text_value(text) {
+                Some(text) => serde_json::Value::String(text),
+                None => serde_json::Value::Null,
+            },
+            serde_json::Value::Number(number) => {
+                let text = number.to_string();
+                match self.text_value(&text) {
+                    Some(masked) if masked == text => json.clone(),
+                    Some(masked) => serde_json::Value::String(masked),
+                    None => serde_json::Value::Null,
+                }
+            }
+            serde_json::Value::Array(values) => values.iter().map(|v| self.json(v)).collect(),
+            serde_json::Value::Object(object) => object
+                .iter()
+                .filter_map(|(key, value)| match (self.column_action(key), value) {
+                    (Some(MaskAction::Drop), _) => None,
+                    (_, serde_json::Value::Null) => Some((key.clone(), value.clone())),
+                    (Some(action), value) => {
+                        let text = match value {
+                            serde_json::Value::String(text) => text.clone(),
+                            value => value.to_string(),
+                        };
+                        let masked = self.replace(&text, Kind::Column, action);
+                        Some((key.clone(), serde_json::Value::String(masked)))
+                    }
+                    (None, value) => Some((key.clone(), self.json(value))),
+                })
+                .collect(),
+            json => json.clone(),
+        }
+    }
+
+    fn replace(&self, text: &str, kind: Kind, action: MaskAction) -> String {
+        match action {
+            MaskAction::Hash => {
+                let digest = Sha256::new()
+                    .chain_update(&self.salt)
+                    .chain_update(text)
+                    .finalize();
+                let hash: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+
+                format!("[{}:{}]", kind, hash)
+            }
+            MaskAction::Mask | MaskAction::Drop => format!("[{}]", kind),
+        }
+    }
+}
+
+/// A case insensitive regex for a column name pattern where `*` matches any
+/// run of characters.
+fn glob(pattern: &str) -> Option<Regex> {
+    let parts: Vec<String> = pattern.split('*').map(regex::escape).collect();
+
+    RegexBuilder::new(&format!("^{}$", parts.join(".*")))
+        .case_insensitive(true)
+        .build()
+        .ok()
+}
+
+fn detectors() -> Vec<(Kind, Regex)> {
+    let patterns = [
+        (
+            Kind::Email,
+            r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+        ),
+        (Kind::Card, r"\b\d(?:[ -]?\d){12,18}\b"),
+        (
+            Kind::Phone,
+            r"\+\d[\d\s().-]{6,}\d|\(\d{2,4}\)\s?\d[\d\s.-]{4,}\d|\b\d{3}[\s.-]\d{3}[\s.-]\d{4}\b",
+        ),
+        (
+            Kind::Ip,
+            r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b|\b(?:[0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}\b|\b(?:[0-9a-fA-F]{1,4}:){1,6}:(?:[0-9a-fA-F]{1,4}(?::[0-9a-fA-F]{1,4}){0,5})?\b",
+        ),
+        (
+            Kind::Geo,
+            r"(?i)point\s*\(\s*-?\d+(?:\.\d+)?\s+-?\d+(?:\.\d+)?\s*\)|\(?-?\d{1,3}\.\d{3,}\s*,\s*-?\d{1,3}\.\d{3,}\)?",
+        ),
+    ];
+
+    patterns
+        .iter()
+        .map(|(kind, pattern)| (*kind, Regex::new(pattern).unwrap()))
+        .collect()
+}
+
+/// Whether the digits pass the Luhn check used by card numbers.
+fn luhn(text: &str) -> bool {
+    let digits: Vec<u32> = text.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match (i % 2, d * 2) {
+            (1, doubled) if doubled > 9 => doubled - 9,
+            (1, doubled) => doubled,
+            _ => *d,
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::results::Column;
+    use crate::stats::ColumnStats;
+
+    fn masker() -> Masker {
+        Masker::new(&Masking {
+            salt: Some("salt".into()),
+            ..Masking::default()
+        })
+    }
+
+    fn column(name: &str, source: Option<&str>) -> Column {
+        Column {
+            name: name.into(),
+            type_name: "text".into(),
+            source: source.map(String::from),
+        }
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Text(text.into())
+    }
+
+    #[test]
+    fn column_rules_mask_hash_and_drop() {
+        let result = ResultSet {
+            columns: vec![
+                column("id", None),
+                column("email", None),
+                column("phone_number", None),
+                column("password_hash", None),
+            ],
+            rows: vec![vec![
+                Value::Int(1),
+                text("a@example.com"),
+                text("555 123 4567"),
+                text("secret"),
+            ]],
+            ..ResultSet::default()
+        };
+        let masked = masker().result(&result);
+
+        let names: Vec<&str> = masked.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["id", "email", "phone_number"]);
+        assert_eq!(masked.rows[0][0], Value::Int(1));
+        assert!(matches!(&masked.rows[0][1], Value::Text(t) if t.starts_with("[redacted:")));
+        assert_eq!(masked.rows[0][2], text("[redacted]"));
+    }
+
+    #[test]
+    fn hashes_are_stable_for_a_salt() {
+        let result = ResultSet {
+            columns: vec![column("email", None)],
+            rows: vec![vec![text("a@example.com")], vec![text("a@example.com")]],
+            ..ResultSet::default()
+        };
+        let masked = masker().result(&result);
+
+        assert_eq!(masked.rows[0][0], masked.rows[1][0]);
+        assert_ne!(masked.rows[0][0], text("a@example.com"));
+    }
+
+    #[test]
+    fn aliases_are_matched_by_their_source_column() {
+        let result = ResultSet {
+            columns: vec![
+                column("contact", Some("email")),
+                column("x", Some("password")),
+            ],
+            rows: vec![vec![text("someone"), text("hunter2")]],
+            ..ResultSet::default()
+        };
+        let masked = masker().result(&result);
+
+        assert_eq!(masked.columns.len(), 1);
+        assert!(matches!(&masked.rows[0][0], Value::Text(t) if t.starts_with("[redacted:")));
+    }
+
+    #[test]
+    fn detects_values_in_other_columns() {
+        let masker = masker();
+
+        assert_eq!(masker.text("mail a@example.com now"), "mail [email] now");
+        assert_eq!(masker.text("from 192.168.0.1"), "from [ip]");
+        assert_eq!(masker.text("call +1 555 123 4567"), "call [phone]");
+        assert_eq!(masker.text("at POINT(12.5 41.9)"), "at [geo]");
+        assert_eq!(masker.text("card 4111 1111 1111 1111"), "card [card]");
+        assert_eq!(masker.text("order 1234567890123"), "order 1234567890123");
+    }
+
+    #[test]
+    fn numeric_values_that_are_cards_are_masked() {
+        let result = ResultSet {
+            columns: vec![column("n", None), column("m", None)],
+            rows: vec![vec![
+                Value::Int(4111111111111111),
+                Value::Numeric("42.5".into()),
+            ]],
+            ..ResultSet::default()
+        };
+        let masked = masker().result(&result);
+
+        assert_eq!(masked.rows[0][0], text("[card]"));
+        assert_eq!(masked.rows[0][1], Value::Numeric("42.5".into()));
+    }
+
+    #[test]
+    fn json_keys_follow_column_rules() {
+        let result = ResultSet {
+            columns: vec![column("doc", None)],
+            rows: vec![vec![Value::Json(json!({
+                "password": "hunter2",
+                "phone": 5551234567_i64,
+                "note": "write to a@example.com",
+                "id": 7
+            }))]],
+            ..ResultSet::default()
+        };
+        let masked = masker().result(&result);
+
+        assert_eq!(
+            masked.rows[0][0],
+            Value::Json(json!({
+                "phone": "[redacted]",
+                "note": "write to [email]",
+                "id": 7
+            }))
+        );
+    }
+
+    #[test]
+    fn drop_action_drops_detected_values() {
+        let masker = Masker::new(&Masking {
+            action: MaskAction::Drop,
+            ..Masking::default()
+        });
+        let result = ResultSet {
+            columns: vec![column("note", None)],
+            rows: vec![vec![text("a@example.com")], vec![text("fine")]],
+            ..ResultSet::default()
+        };
+        let masked = masker.result(&result);
+
+        assert_eq!(masked.rows[0][0], Value::Null);
+        assert_eq!(masked.rows[1][0], text("fine"));
+    }
+
+    #[test]
+    fn stats_are_masked_and_dropped_columns_excluded() {
+        let column = |name: &str, value: &str| ColumnStats {
+            column: name.into(),
+            null_fraction: 0.0,
+            n_distinct: 1.0,
+            common_values: vec![(value.into(), 1.0)],
+            min: Some(value.into()),
+            max: None,
+        };
+        let mut stats = TableStats {
+            table: "public.users".into(),
+            estimated_rows: Some(1),
+            columns: vec![
+                column("password", "hunter2"),
+                column("note", "a@example.com"),
+            ],
+            excluded: vec![],
+        };
+        masker().stats(&mut stats);
+
+        assert_eq!(stats.excluded, ["password"]);
+        assert_eq!(stats.columns.len(), 1);
+        assert_eq!(stats.columns[0].common_values[0].0, "[email]");
+        assert_eq!(stats.columns[0].min.as_deref(), Some("[email]"));
+    }
+
+    #[test]
+    fn disabled_masker_changes_nothing() {
+        let masker = Masker::new(&Masking {
+            enabled: false,
+            ..Masking::default()
+        });
+
+        assert_eq!(masker.text("a@example.com"), "a@example.com");
+    }
+}
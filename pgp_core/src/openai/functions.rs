@@ -1,8 +1,10 @@
 use async_openai::types::{ChatCompletionFunctions, ChatCompletionFunctionsArgs};
 use serde_json::json;
 
-pub fn list_functions() -> [ChatCompletionFunctions; 1] {
-    [ChatCompletionFunctionsArgs::default()
+use crate::stats;
+
+pub fn list_functions(column_stats: bool) -> Vec<ChatCompletionFunctions> {
+    let mut functions = vec![ChatCompletionFunctionsArgs::default()
         .name("run_sql_query")
         .description("Get data from database using SQL query")
         .parameters(json!({
@@ -16,5 +18,36 @@ pub fn list_functions() -> [ChatCompletionFunctions; 1] {
             "required": ["query"],
         }))
         .build()
-        .unwrap()]
+        .unwrap()];
+
+    if column_stats {
+        functions.push(
+            ChatCompletionFunctionsArgs::default()
+                .name(stats::FUNCTION)
+                .description(
+                    "Get statistics about columns of a table: null fraction, number of \
+                     distinct values, most common values and range. Use it to learn what \
+                     values a column holds before filtering on it.",
+                )
+                .parameters(json!({
+                    "type": "object",
+                    "properties": {
+                        "table": {
+                            "type": "string",
+                            "description": "Schema-qualified table name, e.g. public.users",
+                        },
+                        "columns": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "Columns to describe, all of them when left out",
+                        }
+                    },
+                    "required": ["table"],
+                }))
+                .build()
+                .unwrap(),
+        );
+    }
+
+    functions
 }
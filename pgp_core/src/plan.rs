@@ -0,0 +1,375 @@
+use std::fmt;
+use std::time::Instant;
+
+use async_openai::types::{
+    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+};
+use serde_json::Value as Json;
+use sqlparser::ast::Statement as SqlStatement;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use tokio_postgres::Client;
+
+use crate::audit;
+use crate::errors::Error;
+use crate::limits;
+use crate::script;
+use crate::Session;
+
+/// Share of the time, or of the cost when not analyzed, a node must take
+/// by itself to be a hotspot.
+const HOTSPOT_SHARE: f64 = 0.25;
+
+/// How far actual rows may be off the estimate before it is a hotspot.
+const MISESTIMATE_FACTOR: f64 = 10.0;
+
+const SYSTEM_PROMPT: &str = "You explain PostgreSQL query plans to people who know SQL \
+    but not the planner. In a few short paragraphs, say how the query is executed, \
+    where the time goes and what could make it faster, such as an index or a rewrite.";
+
+/// The plan of a query as `EXPLAIN` reports it.
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    pub query: String,
+    /// Whether the query was run, so nodes have actual timings and rows.
+    pub analyzed: bool,
+    pub root: PlanNode,
+    pub planning_ms: Option<f64>,
+    pub execution_ms: Option<f64>,
+}
+
+/// One step of a plan. Rows are per loop, times cover every loop.
+#[derive(Debug, Clone)]
+pub struct PlanNode {
+    pub node_type: String,
+    /// The table or index read, with its alias.
+    pub relation: Option<String>,
+    /// The condition the node applies, such as a join or index condition.
+    pub condition: Option<String>,
+    pub total_cost: f64,
+    pub estimated_rows: f64,
+    pub actual_rows: Option<f64>,
+    pub loops: u64,
+    pub time_ms: Option<f64>,
+    /// Time spent in this node alone, without its children.
+    pub self_ms: Option<f64>,
+    pub shared_hit: Option<u64>,
+    pub shared_read: Option<u64>,
+    pub hotspots: Vec<Hotspot>,
+    pub children: Vec<PlanNode>,
+}
+
+/// Why a node stands out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotspot {
+    /// Share of the execution time spent in the node itself.
+    Slow(f64),
+    /// Share of the estimated cost of the node itself.
+    Costly(f64),
+    /// How many times actual rows are off the estimate.
+    Misestimate(f64),
+}
+
+impl fmt::Display for Hotspot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hotspot::Slow(share) => write!(f, "{:.0}% of the time", share * 100.0),
+            Hotspot::Costly(share) => write!(f, "{:.0}% of the cost", share * 100.0),
+            Hotspot::Misestimate(factor) => write!(f, "rows off the estimate by {:.0}x", factor),
+        }
+    }
+}
+
+impl QueryPlan {
+    /// Reads the output of `EXPLAIN (FORMAT JSON)`.
+    pub fn from_json(query: &str, analyzed: bool, json: &Json) -> Option<Self> {
+        let explained = json.get(0)?;
+        let mut root = PlanNode::from_json(explained.get("Plan")?);
+
+        let total_ms = root.time_ms.unwrap_or_default();
+        let total_cost = root.total_cost;
+        root.mark_hotspots(total_ms, total_cost);
+
+        Some(Self {
+            query: query.to_string(),
+            analyzed,
+            root,
+            planning_ms: explained["Planning Time"].as_f64(),
+            execution_ms: explained["Execution Time"].as_f64(),
+        })
+    }
+
+    /// The plan as indented text, one node per line.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Query: {}\n", self.query);
+
+        if let (Some(planning), Some(execution)) = (self.planning_ms, self.execution_ms) {
+            text.push_str(&format!(
+                "Planning {:.2} ms, execution {:.2} ms\n",
+                planning, execution
+            ));
+        }
+
+        self.root.write(&mut text, 0);
+
+        text
+    }
+}
+
+impl PlanNode {
+    fn from_json(node: &Json) -> Self {
+        let relation = match (node["Relation Name"].as_str(), node["Index Name"].as_str()) {
+            (Some(table), _) => Some(match node["Alias"].as_str() {
+                Some(alias) if alias != table => format!("{} {}", table, alias),
+                _ => table.to_string(),
+            }),
+            (None, Some(index)) => Some(index.to_string()),
+            (None, None) => node["CTE Name"].as_str().map(str::to_string),
+        };
+
+        let condition = [
+            "Hash Cond",
+            "Merge Cond",
+            "Index Cond",
+            "Recheck Cond",
+            "Join Filter",
+            "Filter",
+        ]
+        .iter()
+        .find_map(|key| node[*key].as_str())
+        .map(str::to_string)
+        .or_else(|| {
+            let keys = node["Sort Key"]
+                .as_array()
+                .or(node["Group Key"].as_array())?;
+            let keys: Vec<&str> = keys.iter().filter_map(|k| k.as_str()).collect();
+            Some(keys.join(", "))
+        });
+
+        let children: Vec<PlanNode> = node["Plans"]
+            .as_array()
+            .map(|children| children.iter().map(PlanNode::from_json).collect())
+            .unwrap_or_default();
+
+        let loops = node["Actual Loops"].as_u64().unwrap_or(1);
+        let time_ms = node["Actual Total Time"]
+            .as_f64()
+            .map(|time| time * loops as f64);
+        let self_ms = time_ms.map(|time| {
+            let children: f64 = children.iter().filter_map(|c| c.time_ms).sum();
+            (time - children).max(0.0)
+        });
+
+        Self {
+            node_type: node["Node Type"].as_str().unwrap_or_default().to_string(),
+            relation,
+            condition,
+            total_cost: node["Total Cost"].as_f64().unwrap_or_default(),
+            estimated_rows: node["Plan Rows"].as_f64().unwrap_or_default(),
+            actual_rows: node["Actual Rows"].as_f64(),
+            loops,
+            time_ms,
+            self_ms,
+            shared_hit: node["Shared Hit Blocks"].as_u64(),
+            shared_read: node["Shared Read Blocks"].as_u64(),
+            hotspots: vec![],
+            children,
+        }
+    }
+
+    fn mark_hotspots(&mut self, total_ms: f64, total_cost: f64) {
+        match self.self_ms {
+            Some(self_ms) if total_ms > 0.0 && self_ms / total_ms >= HOTSPOT_SHARE => {
+                self.hotspots.push(Hotspot::Slow(self_ms / total_ms));
+            }
+            Some(_) => {}
+            None => {
+                let children: f64 = self.children.iter().map(|c| c.total_cost).sum();
+                let self_cost = (self.total_cost - children).max(0.0);
+                if total_cost > 0.0 && self_cost / total_cost >= HOTSPOT_SHARE {
+                    self.hotspots.push(Hotspot::Costly(self_cost / total_cost));
+                }
+            }
+        }
+
+        if let Some(actual) = self.actual_rows {
+            let (estimated, actual) = (self.estimated_rows.max(1.0), actual.max(1.0));
+            let factor = (estimated / actual).max(actual / estimated);
+            if factor >= MISESTIMATE_FACTOR {
+                self.hotspots.push(Hotspot::Misestimate(factor));
+            }
+        }
+
+        for child in &mut self.children {
+            child.mark_hotspots(total_ms, total_cost);
+        }
+    }
+
+    /// The node on one line: what it does, its rows and its time.
+    pub fn summary(&self) -> String {
+        let mut summary = self.node_type.clone();
+
+        if let Some(relation) = &self.relation {
+            summary.push_str(&format!(" on {}", relation));
+        }
+
+        summary.push_str(&format!(" · est {:.0} rows", self.estimated_rows));
+
+        if let Some(actual) = self.actual_rows {
+            summary.push_str(&format!(", actual {:.0}", actual));
+        }
+        if self.loops > 1 {
+            summary.push_str(&format!(" × {} loops", self.loops));
+        }
+
+        match (self.time_ms, self.self_ms) {
+            (Some(time), Some(self_ms)) => {
+                summary.push_str(&format!(" · {:.2} ms (self {:.2} ms)", time, self_ms))
+            }
+            _ => summary.push_str(&format!(" · cost {:.2}", self.total_cost)),
+        }
+
+        if let (Some(hit), Some(read)) = (self.shared_hit, self.shared_read) {
+            summary.push_str(&format!(" · buffers hit {} read {}", hit, read));
+        }
+
+        summary
+    }
+
+    fn write(&self, text: &mut String, depth: usize) {
+        text.push_str(&format!("{}- {}", "  ".repeat(depth), self.summary()));
+
+        if let Some(condition) = &self.condition {
+            text.push_str(&format!(" [{}]", condition));
+        }
+        if !self.hotspots.is_empty() {
+            let hotspots: Vec<String> = self.hotspots.iter().map(|h| h.to_string()).collect();
+            text.push_str(&format!(" HOTSPOT: {}", hotspots.join(", ")));
+        }
+        text.push('\n');
+
+        for child in &self.children {
+            child.write(text, depth + 1);
+        }
+    }
+}
+
+/// The plan of `query`. Queries are analyzed when the connection allows
+/// it, inside a read-only transaction that is rolled back and within the
+/// duration limit. The query must pass the same checks as when it runs.
+pub async fn explain(session: Session, query: String) -> Result<QueryPlan, Error> {
+    let query = crate::check_statement(&session, &query).map_err(Error::Rejected)?;
+
+    let analyze = session.explain.analyze && is_query(&query);
+    let options = if analyze {
+        "ANALYZE, BUFFERS, FORMAT JSON"
+    } else {
+        "FORMAT JSON"
+    };
+    let statement = format!(
+        "EXPLAIN ({}) {}",
+        options,
+        limits::limit(&query, session.limits.max_rows)
+    );
+
+    session.audit.ready()?;
+
+    let client = session
+        .db_client
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(Error::ConnectionError)?;
+
+    let started = Instant::now();
+    let explained = run(&client, &statement, analyze, session.limits.max_duration).await;
+
+    *session.db_client.lock().unwrap() = Some(client);
+
+    if analyze {
+        let outcome = match &explained {
+            Ok(_) => "ok".to_string(),
+            Err(e) => "error: ".to_string() + e.to_string().as_str(),
+        };
+        let execution = audit::Execution {
+            statement: &statement,
+            rows: None,
+            duration: started.elapsed(),
+            outcome,
+        };
+        session.audit.record(None, None, execution)?;
+    }
+
+    let explained = explained.map_err(|e| {
+        Error::Failed(match e.as_db_error() {
+            Some(db_error) => db_error.to_string(),
+            None => e.to_string(),
+        })
+    })?;
+
+    QueryPlan::from_json(&query, analyze, &explained).ok_or(Error::QueryError)
+}
+
+/// A plain-English explanation of a plan, written by the model.
+pub async fn describe(session: Session, plan: QueryPlan) -> Result<String, Error> {
+    let system_message = ChatCompletionRequestMessageArgs::default()
+        .role(Role::System)
+        .content(SYSTEM_PROMPT)
+        .build()?;
+    let plan_message = ChatCompletionRequestMessageArgs::default()
+        .role(Role::User)
+        .content(session.masker.text(&plan.to_text()))
+        .build()?;
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(session.budget.response)
+        .model(&session.model)
+        .messages([system_message, plan_message])
+        .build()?;
+
+    let response = crate::create_chat_completion(&session, request).await?;
+
+    Ok(response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default())
+}
+
+async fn run(
+    client: &Client,
+    statement: &str,
+    analyze: bool,
+    max_duration: u64,
+) -> Result<Json, tokio_postgres::Error> {
+    if !analyze {
+        return client.query_one(statement, &[]).await?.try_get(0);
+    }
+
+    let savepoint = script::begin_read_only(client).await?;
+
+    let explained = async {
+        if max_duration > 0 {
+            client
+                .batch_execute(&format!(
+                    "SET LOCAL statement_timeout = {}",
+                    max_duration * 1000
+                ))
+                .await?;
+        }
+        client.query_one(statement, &[]).await?.try_get(0)
+    }
+    .await;
+
+    script::end_read_only(client, savepoint).await?;
+
+    explained
+}
+
+/// Whether the SQL is a single query, which is safe to analyze.
+fn is_query(sql: &str) -> bool {
+    matches!(
+        Parser::parse_sql(&PostgreSqlDialect {}, sql).as_deref(),
+        Ok([SqlStatement::Query(_)])
+    )
+}
@@ -0,0 +1,116 @@
+use serde_json::{json, Map, Value as Json};
+use tokio_postgres::error::SqlState;
+
+use crate::injection;
+use crate::results::{ResultSet, Value};
+
+/// Rows a result needs before numeric aggregates are reported, so the
+/// values of a single row are never given away.
+const MIN_ROWS: usize = 5;
+
+/// What the model sees of a result in schema-only mode: the columns with
+/// their types, the row count and aggregates computed here.
+pub fn shape(result: &ResultSet) -> String {
+    let columns: Vec<Json> = result
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let values: Vec<&Value> = result.rows.iter().filter_map(|row| row.get(i)).collect();
+            let nulls = values.iter().filter(|v| v.is_null()).count();
+
+            let mut distinct: Vec<String> = values
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(|v| v.to_string())
+                .collect();
+            distinct.sort();
+            distinct.dedup();
+
+            let mut object = Map::new();
+            object.insert("name".into(), json!(column.name));
+            object.insert("type".into(), json!(column.type_name));
+            object.insert("nulls".into(), json!(nulls));
+            object.insert("distinct".into(), json!(distinct.len()));
+
+            let numbers: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+
+            if result.rows.len() >= MIN_ROWS && !numbers.is_empty() {
+                let sum: f64 = numbers.iter().sum();
+                let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+                object.insert("min".into(), json!(min));
+                object.insert("max".into(), json!(max));
+                object.insert("sum".into(), json!(sum));
+                object.insert("avg".into(), json!(sum / numbers.len() as f64));
+            }
+
+            Json::Object(object)
+        })
+        .collect();
+
+    let mut shape = json!({
+        "row_count": result.rows.len(),
+        "columns": columns,
+        "note": "Row data is not shared in schema-only mode.",
+    });
+
+    if let Some(truncation) = &result.truncated {
+        shape["truncated"] = json!(truncation.to_string());
+    }
+
+    injection::delimit_json(&shape.to_string())
+}
+
+/// A failed statement as the model is told about it in schema-only mode.
+/// Postgres messages may quote values, as in `invalid input syntax for
+/// type integer: "Alice"`, so only the error code is given.
+pub fn error(sqlstate: Option<&SqlState>) -> String {
+    match sqlstate {
+        Some(code) => format!(
+            "the statement failed with SQLSTATE {}; the message is not shared in schema-only mode",
+            code.code()
+        ),
+        None => "the statement failed; the message is not shared in schema-only mode".to_string(),
+    }
+}
+
+/// The answer written locally in place of the model's, from what is known
+/// without reading rows. The rows themselves are shown in the result grid.
+pub fn answer(result: Option<&ResultSet>, outcome: &str) -> String {
+    let note = "Row data stays on this machine in schema-only mode, \
+                so the result is shown without a written summary.";
+
+    match result {
+        Some(ResultSet {
+            truncated: Some(truncation),
+            rows,
+            ..
+        }) => format!(
+            "The query returned {} rows before the result was {}, so more rows may exist. {}",
+            rows.len(),
+            truncation,
+            note
+        ),
+        Some(result) if result.rows.is_empty() => {
+            format!("The query returned no rows. {}", note)
+        }
+        Some(result) => {
+            let columns: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+            let rows = match result.rows.len() {
+                1 => "1 row".to_string(),
+                n => format!("{} rows", n),
+            };
+
+            format!(
+                "The query returned {} with columns {}. {}",
+                rows,
+                columns.join(", "),
+                note
+            )
+        }
+        None if outcome.starts_with("Error:") => format!("The query failed. {}", outcome),
+        None => format!("The statement completed: {}.", outcome),
+    }
+}
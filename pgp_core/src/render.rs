@@ -0,0 +1,310 @@
+use serde_json::{json, Map, Value};
+
+use crate::config::{SchemaFormat, SchemaOptions};
+use crate::schema::{quote_ident, Catalog, Column, Table, TableKind};
+use crate::tokens::Counter;
+
+/// Long type names and the short forms the compact notation uses.
+const SHORT_TYPES: [(&str, &str); 6] = [
+    ("character varying", "varchar"),
+    ("character", "char"),
+    ("double precision", "float8"),
+    ("integer", "int"),
+    ("boolean", "bool"),
+    ("bit varying", "varbit"),
+];
+
+/// Writes tables in the configured format for the system message.
+pub fn render<'a>(
+    tables: impl Iterator<Item = &'a Table>,
+    catalog: &Catalog,
+    options: &SchemaOptions,
+) -> String {
+    match options.format {
+        SchemaFormat::Ddl => {
+            let tables: Vec<&Table> = tables.collect();
+            let mut ddl = String::new();
+
+            if options.enum_values {
+                ddl.push_str(&enum_types(&tables, catalog));
+            }
+
+            for table in tables {
+                ddl.push_str(&table_ddl(table, options));
+                ddl.push('\n');
+            }
+
+            ddl
+        }
+        SchemaFormat::Compact => tables
+            .map(|table| compact(table, catalog, options))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        SchemaFormat::Json => {
+            let tables: Vec<Value> = tables.map(|t| to_json(t, catalog, options)).collect();
+            Value::Array(tables).to_string()
+        }
+    }
+}
+
+/// A line for the system message explaining the notation, if it needs one.
+pub fn legend(format: SchemaFormat) -> &'static str {
+    match format {
+        SchemaFormat::Ddl => "",
+        SchemaFormat::Compact => {
+            "Each line is schema.table(column type, ...). pk marks the primary key, \
+             -> a foreign key, enum(...) the allowed values and /* */ a comment."
+        }
+        SchemaFormat::Json => "Tables are given as a JSON array.",
+    }
+}
+
+/// Token cost of the whole catalog in every format, for comparing them on
+/// the current database.
+pub fn measure(
+    catalog: &Catalog,
+    options: &SchemaOptions,
+    model: &str,
+) -> Vec<(SchemaFormat, usize)> {
+    let counter = Counter::new(model);
+
+    SchemaFormat::ALL
+        .iter()
+        .map(|format| {
+            let options = SchemaOptions {
+                format: *format,
+                ..options.clone()
+            };
+            let tokens = counter.count(&render(catalog.tables(), catalog, &options));
+
+            (*format, tokens)
+        })
+        .collect()
+}
+
+/// `CREATE` statement describing the table, its keys and indexes.
+fn table_ddl(table: &Table, options: &SchemaOptions) -> String {
+    let mut lines: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| {
+            let mut line = format!("    {} {}", quote_ident(&c.name), c.type_name);
+            if !c.nullable {
+                line.push_str(" NOT NULL");
+            }
+            if let Some(default) = &c.default {
+                line.push_str(&format!(" DEFAULT {}", default));
+            }
+            line
+        })
+        .collect();
+
+    let keys = table.primary_key.iter().chain(&table.unique);
+    lines.extend(keys.map(|k| format!("    CONSTRAINT {} {}", quote_ident(&k.name), k.definition)));
+
+    if options.foreign_keys {
+        lines.extend(
+            table
+                .foreign_keys
+                .iter()
+                .map(|fk| format!("    CONSTRAINT {} {}", quote_ident(&fk.name), fk.definition)),
+        );
+    }
+
+    let mut ddl = String::new();
+
+    if let (Some(comment), true) = (&table.comment, options.comments) {
+        ddl.push_str(&format!("-- {}\n", one_line(comment)));
+    }
+
+    if let (Some(rows), true) = (table.estimated_rows, options.row_estimates) {
+        ddl.push_str(&format!("-- about {} rows\n", rows));
+    }
+
+    ddl.push_str(&format!(
+        "CREATE {} {} (\n{}\n);\n",
+        table.kind.keyword(),
+        table.qualified_name(),
+        lines.join(",\n")
+    ));
+
+    if options.comments {
+        for c in &table.columns {
+            if let Some(comment) = &c.comment {
+                ddl.push_str(&format!(
+                    "COMMENT ON COLUMN {}.{} IS '{}';\n",
+                    table.qualified_name(),
+                    quote_ident(&c.name),
+                    one_line(comment).replace('\'', "''")
+                ));
+            }
+        }
+    }
+
+    for index in &table.indexes {
+        ddl.push_str(&format!("{};\n", index));
+    }
+
+    ddl
+}
+
+/// `CREATE TYPE` statements for the enums the tables use.
+fn enum_types(tables: &[&Table], catalog: &Catalog) -> String {
+    let mut types: Vec<(&str, &[String])> = vec![];
+
+    for column in tables.iter().flat_map(|t| &t.columns) {
+        if let Some(values) = catalog.enum_values(column) {
+            let name = column.type_name.trim_end_matches("[]");
+            if !types.iter().any(|(n, _)| *n == name) {
+                types.push((name, values));
+            }
+        }
+    }
+
+    types
+        .iter()
+        .map(|(name, values)| format!("CREATE TYPE {} AS ENUM ({});\n", name, quoted(values)))
+        .collect()
+}
+
+/// `schema.table(column type, ...)` on one line.
+fn compact(table: &Table, catalog: &Catalog, options: &SchemaOptions) -> String {
+    let columns: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| {
+            let mut column = format!(
+                "{} {}",
+                quote_ident(&c.name),
+                short_type(c, catalog, options)
+            );
+
+            if table.is_key(&c.name) {
+                column.push_str(" pk");
+            }
+            if let (Some(fk), true) = (table.foreign_key(&c.name), options.foreign_keys) {
+                let position = fk.columns.iter().position(|n| *n == c.name).unwrap_or(0);
+                let referenced = fk
+                    .referenced
+                    .get(position)
+                    .map(String::as_str)
+                    .unwrap_or("?");
+                column.push_str(&format!(
+                    " -> {}.{}.{}",
+                    quote_ident(&fk.schema),
+                    quote_ident(&fk.table),
+                    quote_ident(referenced)
+                ));
+            }
+            if let (Some(comment), true) = (&c.comment, options.comments) {
+                column.push_str(&format!(" /* {} */", one_line(comment)));
+            }
+
+            column
+        })
+        .collect();
+
+    let mut line = format!("{}({})", table.qualified_name(), columns.join(", "));
+
+    let mut notes = vec![];
+    if table.kind != TableKind::Table {
+        notes.push(table.kind.to_string());
+    }
+    if let (Some(rows), true) = (table.estimated_rows, options.row_estimates) {
+        notes.push(format!("~{} rows", rows));
+    }
+    if let (Some(comment), true) = (&table.comment, options.comments) {
+        notes.push(one_line(comment));
+    }
+    if !notes.is_empty() {
+        line.push_str(&format!(" -- {}", notes.join("; ")));
+    }
+
+    line
+}
+
+fn short_type(column: &Column, catalog: &Catalog, options: &SchemaOptions) -> String {
+    if let (Some(values), true) = (catalog.enum_values(column), options.enum_values) {
+        let array = if column.type_name.ends_with("[]") {
+            "[]"
+        } else {
+            ""
+        };
+        return format!("enum({}){}", quoted(values), array);
+    }
+
+    let type_name = SHORT_TYPES
+        .iter()
+        .find(|(long, _)| column.type_name.starts_with(long))
+        .map(|(long, short)| column.type_name.replacen(long, short, 1))
+        .unwrap_or_else(|| column.type_name.clone());
+
+    // timestamp(0) with time zone -> timestamptz(0)
+    if let Some(base) = type_name.strip_suffix(" with time zone") {
+        let (name, precision) = base.split_at(base.find('(').unwrap_or(base.len()));
+        return format!("{}tz{}", name, precision);
+    }
+
+    type_name.replace(" without time zone", "")
+}
+
+fn to_json(table: &Table, catalog: &Catalog, options: &SchemaOptions) -> Value {
+    let columns: Vec<Value> = table
+        .columns
+        .iter()
+        .map(|c| {
+            let mut column = Map::new();
+            column.insert("name".into(), json!(c.name));
+            column.insert("type".into(), json!(c.type_name));
+
+            if !c.nullable {
+                column.insert("not_null".into(), json!(true));
+            }
+            if table.is_key(&c.name) {
+                column.insert("primary_key".into(), json!(true));
+            }
+            if let (Some(fk), true) = (table.foreign_key(&c.name), options.foreign_keys) {
+                column.insert(
+                    "references".into(),
+                    json!({ "schema": fk.schema, "table": fk.table, "columns": fk.referenced }),
+                );
+            }
+            if let (Some(values), true) = (catalog.enum_values(c), options.enum_values) {
+                column.insert("values".into(), json!(values));
+            }
+            if let (Some(comment), true) = (&c.comment, options.comments) {
+                column.insert("comment".into(), json!(comment));
+            }
+
+            Value::Object(column)
+        })
+        .collect();
+
+    let mut object = Map::new();
+    object.insert("schema".into(), json!(table.schema));
+    object.insert("name".into(), json!(table.name));
+    object.insert("kind".into(), json!(table.kind.to_string()));
+
+    if let (Some(comment), true) = (&table.comment, options.comments) {
+        object.insert("comment".into(), json!(comment));
+    }
+    if let (Some(rows), true) = (table.estimated_rows, options.row_estimates) {
+        object.insert("estimated_rows".into(), json!(rows));
+    }
+
+    object.insert("columns".into(), Value::Array(columns));
+
+    Value::Object(object)
+}
+
+fn quoted(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| format!("'{}'", v.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn one_line(text: &str) -> String {
+    text.replace('\n', " ")
+}
@@ -0,0 +1,401 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+use tokio_postgres::types::{FromSql, Kind, Type};
+use tokio_postgres::{Client, Column as PgColumn, Row};
+use uuid::Uuid;
+
+use crate::injection;
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub type_name: String,
+    /// The table column the values come straight from, if any.
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Numeric(String),
+    Text(String),
+    Json(serde_json::Value),
+    Bytes(Vec<u8>),
+    Uuid(Uuid),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<Utc>),
+    Array(Vec<Value>),
+    Unsupported(String),
+}
+
+/// Rows returned by a statement, decoded into owned values so they can be
+/// kept in the session and rendered after the client is released.
+#[derive(Debug, Clone, Default)]
+pub struct ResultSet {
+    pub query: String,
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+    /// Set when reading stopped at a limit, so more rows may exist.
+    pub truncated: Option<Truncation>,
+}
+
+/// The limit a result was cut at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Truncation {
+    Rows(usize),
+    Bytes(usize),
+    Duration(Duration),
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Truncation::Rows(rows) => write!(f, "cut at {} rows", rows),
+            Truncation::Bytes(bytes) => write!(f, "cut at {} bytes", bytes),
+            Truncation::Duration(duration) => {
+                write!(f, "stopped after {} seconds", duration.as_secs())
+            }
+        }
+    }
+}
+
+impl ResultSet {
+    pub fn new(query: &str, columns: &[PgColumn], rows: &[Row]) -> Self {
+        let columns = columns
+            .iter()
+            .map(|c| Column {
+                name: c.name().to_string(),
+                type_name: c.type_().name().to_string(),
+                source: None,
+            })
+            .collect();
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                (0..row.len())
+                    .map(|i| {
+                        row.try_get(i)
+                            .unwrap_or_else(|e| Value::Unsupported(e.to_string()))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            query: query.to_string(),
+            columns,
+            rows,
+            truncated: None,
+        }
+    }
+
+    /// Names the table columns the result columns come from, so rules on a
+    /// column still apply under an alias. Left unnamed when the lookup fails.
+    pub async fn trace(&mut self, client: &Client, columns: &[PgColumn]) {
+        let (tables, numbers): (Vec<u32>, Vec<i16>) = columns
+            .iter()
+            .filter_map(|c| Some((c.table_oid()?, c.column_id()?)))
+            .unzip();
+
+        if tables.is_empty() {
+            return;
+        }
+
+        let Ok(rows) = client
+            .query(
+                "SELECT a.attrelid, a.attnum, a.attname::text FROM pg_attribute a
+                JOIN unnest($1::oid[], $2::int2[]) s(rel, num)
+                    ON a.attrelid = s.rel AND a.attnum = s.num",
+                &[&tables, &numbers],
+            )
+            .await
+        else {
+            return;
+        };
+
+        let names: HashMap<(u32, i16), String> = rows
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect();
+
+        for (column, source) in self.columns.iter_mut().zip(columns) {
+            column.source = source
+                .table_oid()
+                .zip(source.column_id())
+                .and_then(|key| names.get(&key).cloned());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Compact JSON form of the result sent back to the model, delimited as
+    /// data.
+    pub fn to_prompt(&self) -> String {
+        self.to_prompt_rows(self.rows.len())
+    }
+
+    /// The prompt form with only the first `limit` rows, noting how many
+    /// rows there were when some are left out.
+    pub fn to_prompt_rows(&self, limit: usize) -> String {
+        let columns: Vec<_> = self.columns.iter().map(|c| c.name.as_str()).collect();
+        let rows: Vec<Vec<serde_json::Value>> = self
+            .rows
+            .iter()
+            .take(limit)
+            .map(|row| row.iter().map(Value::to_json).collect())
+            .collect();
+
+        let mut prompt = json!({ "columns": columns, "rows": rows });
+
+        if limit < self.rows.len() {
+            prompt["truncated"] = json!(true);
+            prompt["row_count"] = json!(self.rows.len());
+        }
+
+        if let Some(truncation) = &self.truncated {
+            prompt["truncated"] = json!(true);
+            prompt["note"] = json!(format!(
+                "The result was {}; more rows may exist.",
+                truncation
+            ));
+        }
+
+        injection::delimit_json(&prompt.to_string())
+    }
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(v) => json!(v),
+            Value::Int(v) => json!(v),
+            Value::Float(v) => json!(v),
+            Value::Json(v) => v.clone(),
+            Value::Timestamp(v) => json!(v.format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+            Value::TimestampTz(v) => json!(v.to_rfc3339()),
+            Value::Array(values) => values.iter().map(Value::to_json).collect(),
+            _ => json!(self.to_string()),
+        }
+    }
+
+    /// Ordering used when sorting result rows: NULLs first, numbers by
+    /// value and everything else by its textual form.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.cmp(b),
+            (Value::Time(a), Value::Time(b)) => a.cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (Value::TimestampTz(a), Value::TimestampTz(b)) => a.cmp(b),
+            _ => match (self.as_f64(), other.as_f64()) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                _ => self.to_string().cmp(&other.to_string()),
+            },
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(v) => Some(*v as f64),
+            Value::Float(v) => Some(*v),
+            Value::Numeric(v) => v.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Numeric(v) | Value::Text(v) => write!(f, "{}", v),
+            Value::Json(v) => write!(f, "{}", v),
+            Value::Bytes(v) => {
+                write!(f, "\\x")?;
+                v.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            Value::Uuid(v) => write!(f, "{}", v),
+            Value::Date(v) => write!(f, "{}", v),
+            Value::Time(v) => write!(f, "{}", v),
+            Value::Timestamp(v) => write!(f, "{}", v),
+            Value::TimestampTz(v) => write!(f, "{}", v),
+            Value::Array(values) => {
+                write!(f, "{{")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Unsupported(type_name) => write!(f, "<{}>", type_name),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Value {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn StdError + Sync + Send>> {
+        let value = match *ty {
+            Type::BOOL => Value::Bool(bool::from_sql(ty, raw)?),
+            Type::INT2 => Value::Int(i16::from_sql(ty, raw)? as i64),
+            Type::INT4 => Value::Int(i32::from_sql(ty, raw)? as i64),
+            Type::INT8 => Value::Int(i64::from_sql(ty, raw)?),
+            Type::OID => Value::Int(u32::from_sql(ty, raw)? as i64),
+            Type::FLOAT4 => Value::Float(f32::from_sql(ty, raw)? as f64),
+            Type::FLOAT8 => Value::Float(f64::from_sql(ty, raw)?),
+            Type::NUMERIC => Value::Numeric(decode_numeric(raw)?),
+            Type::JSON | Type::JSONB => Value::Json(serde_json::Value::from_sql(ty, raw)?),
+            Type::BYTEA => Value::Bytes(Vec::<u8>::from_sql(ty, raw)?),
+            Type::UUID => Value::Uuid(Uuid::from_sql(ty, raw)?),
+            Type::DATE => Value::Date(NaiveDate::from_sql(ty, raw)?),
+            Type::TIME => Value::Time(NaiveTime::from_sql(ty, raw)?),
+            Type::TIMESTAMP => Value::Timestamp(NaiveDateTime::from_sql(ty, raw)?),
+            Type::TIMESTAMPTZ => Value::TimestampTz(DateTime::<Utc>::from_sql(ty, raw)?),
+            _ => match ty.kind() {
+                Kind::Enum(_) => Value::Text(String::from_utf8(raw.to_vec())?),
+                Kind::Array(_) => Value::Array(Vec::<Value>::from_sql(ty, raw)?),
+                Kind::Domain(inner) => Value::from_sql(inner, raw)?,
+                _ if <String as FromSql>::accepts(ty) => Value::Text(String::from_sql(ty, raw)?),
+                _ => Value::Unsupported(ty.name().to_string()),
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Self, Box<dyn StdError + Sync + Send>> {
+        Ok(Value::Null)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+/// Decodes the binary `numeric` representation into its decimal text.
+fn decode_numeric(raw: &[u8]) -> Result<String, Box<dyn StdError + Sync + Send>> {
+    const NUMERIC_NEG: u16 = 0x4000;
+    const NUMERIC_NAN: u16 = 0xC000;
+    const NUMERIC_PINF: u16 = 0xD000;
+    const NUMERIC_NINF: u16 = 0xF000;
+
+    if raw.len() < 8 {
+        return Err("invalid numeric".into());
+    }
+
+    let read = |i: usize| u16::from_be_bytes([raw[i], raw[i + 1]]);
+    let ndigits = read(0) as usize;
+    let weight = read(2) as i16 as i32;
+    let sign = read(4);
+    let dscale = read(6) as usize;
+
+    if raw.len() < 8 + ndigits * 2 {
+        return Err("invalid numeric".into());
+    }
+
+    match sign {
+        NUMERIC_NAN => return Ok("NaN".to_string()),
+        NUMERIC_PINF => return Ok("Infinity".to_string()),
+        NUMERIC_NINF => return Ok("-Infinity".to_string()),
+        _ => {}
+    }
+
+    let digit = |i: i32| -> u16 {
+        if i < 0 || i as usize >= ndigits {
+            0
+        } else {
+            read(8 + i as usize * 2)
+        }
+    };
+
+    let mut out = String::new();
+
+    if sign == NUMERIC_NEG {
+        out.push('-');
+    }
+
+    if weight < 0 {
+        out.push('0');
+    } else {
+        for i in 0..=weight {
+            if i == 0 {
+                out.push_str(&digit(i).to_string());
+            } else {
+                out.push_str(&format!("{:04}", digit(i)));
+            }
+        }
+    }
+
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+
+        while fraction.len() < dscale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+
+        fraction.truncate(dscale);
+        out.push('.');
+        out.push_str(&fraction);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric(weight: i16, sign: u16, dscale: u16, digits: &[u16]) -> Vec<u8> {
+        [digits.len() as u16, weight as u16, sign, dscale]
+            .iter()
+            .chain(digits)
+            .flat_map(|n| n.to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_numeric() {
+        let decode = |raw: Vec<u8>| decode_numeric(&raw).unwrap();
+
+        assert_eq!(decode(numeric(0, 0, 2, &[123, 4500])), "123.45");
+        assert_eq!(decode(numeric(1, 0, 0, &[1234, 5678])), "12345678");
+        assert_eq!(decode(numeric(1, 0, 0, &[1])), "10000");
+        assert_eq!(decode(numeric(-1, 0x4000, 3, &[10])), "-0.001");
+        assert_eq!(decode(numeric(0, 0, 2, &[])), "0.00");
+        assert_eq!(decode(numeric(0, 0xC000, 0, &[])), "NaN");
+        assert_eq!(decode(numeric(0, 0xF000, 0, &[])), "-Infinity");
+    }
+
+    #[test]
+    fn refuses_short_numeric() {
+        assert!(decode_numeric(&[0, 1]).is_err());
+        assert!(decode_numeric(&numeric(0, 0, 0, &[1, 2])[..10]).is_err());
+    }
+}
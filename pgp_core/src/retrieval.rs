@@ -0,0 +1,280 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::config::Retrieval;
+use crate::schema::{Catalog, Table};
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+const NAME_WEIGHT: usize = 3;
+
+const STOPWORDS: [&str; 32] = [
+    "a", "all", "an", "and", "are", "as", "at", "by", "for", "from", "get", "give", "how", "i",
+    "in", "is", "list", "me", "many", "much", "of", "on", "or", "show", "the", "their", "to",
+    "what", "which", "who", "with", "each",
+];
+
+/// Why a table ended up in the prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    /// The schema is small enough to send whole.
+    All,
+    /// Ranked among the top matches with this BM25 score.
+    Match(f64),
+    /// Joined by a foreign key to a matched table.
+    Neighbour(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selected {
+    pub schema: String,
+    pub table: String,
+    pub reason: Reason,
+}
+
+/// Tables chosen for the prompt of one question.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    pub tables: Vec<Selected>,
+    pub total: usize,
+}
+
+impl Selection {
+    pub fn contains(&self, table: &Table) -> bool {
+        self.tables
+            .iter()
+            .any(|t| t.schema == table.schema && t.table == table.name)
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.tables.len() < self.total
+    }
+
+    /// The selected tables, in the order they were picked.
+    pub fn tables_in<'a>(&'a self, catalog: &'a Catalog) -> impl Iterator<Item = &'a Table> {
+        self.tables
+            .iter()
+            .filter_map(|t| catalog.table(&t.schema, &t.table))
+    }
+}
+
+/// BM25 index over the tables of a catalog. A table is described by its
+/// name (weighted up), schema, column names, comments and the names of the
+/// tables it joins to.
+pub struct Index<'a> {
+    tables: Vec<&'a Table>,
+    terms: Vec<HashMap<String, usize>>,
+    lengths: Vec<usize>,
+    frequencies: HashMap<String, usize>,
+    average: f64,
+}
+
+impl<'a> Index<'a> {
+    pub fn new(catalog: &'a Catalog) -> Self {
+        let tables: Vec<&Table> = catalog.tables().collect();
+        let mut terms = vec![];
+        let mut lengths = vec![];
+        let mut frequencies: HashMap<String, usize> = HashMap::new();
+
+        for table in &tables {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            let mut add = |text: &str, weight: usize| {
+                for token in tokenize(text) {
+                    *counts.entry(token).or_default() += weight;
+                }
+            };
+
+            add(&table.name, NAME_WEIGHT);
+            add(&table.schema, 1);
+            add(table.comment.as_deref().unwrap_or_default(), 1);
+
+            for column in &table.columns {
+                add(&column.name, 1);
+                add(column.comment.as_deref().unwrap_or_default(), 1);
+            }
+
+            for fk in &table.foreign_keys {
+                add(&fk.table, 1);
+            }
+
+            for term in counts.keys() {
+                *frequencies.entry(term.clone()).or_default() += 1;
+            }
+
+            lengths.push(counts.values().sum());
+            terms.push(counts);
+        }
+
+        let average = lengths.iter().sum::<usize>() as f64 / lengths.len().max(1) as f64;
+
+        Self {
+            tables,
+            terms,
+            lengths,
+            frequencies,
+            average,
+        }
+    }
+
+    /// Tables scoring above zero for the query, best first.
+    pub fn search(&self, query: &str) -> Vec<(&'a Table, f64)> {
+        let query: BTreeSet<String> = tokenize(query).collect();
+        let n = self.tables.len() as f64;
+
+        let mut scores: Vec<(&Table, f64)> = self
+            .tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| {
+                let norm = 1.0 - B + B * self.lengths[i] as f64 / self.average.max(1.0);
+                let score = query
+                    .iter()
+                    .filter_map(|term| {
+                        let tf = *self.terms[i].get(term)? as f64;
+                        let df = self.frequencies[term] as f64;
+                        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        Some(idf * tf * (K1 + 1.0) / (tf + K1 * norm))
+                    })
+                    .sum();
+
+                (*table, score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+    }
+}
+
+/// Picks the tables to describe in the prompt for a question: everything
+/// for small schemas, otherwise the best matches and their join neighbours.
+pub fn select(catalog: &Catalog, question: &str, settings: &Retrieval) -> Selection {
+    let total = catalog.tables().count();
+
+    if total <= settings.min_tables {
+        let tables = catalog
+            .tables()
+            .map(|t| Selected {
+                schema: t.schema.clone(),
+                table: t.name.clone(),
+                reason: Reason::All,
+            })
+            .collect();
+
+        return Selection { tables, total };
+    }
+
+    let index = Index::new(catalog);
+    let ranked = index.search(question);
+    let mut selection = Selection {
+        tables: vec![],
+        total,
+    };
+
+    for (table, score) in ranked.iter().take(settings.top_k) {
+        selection.tables.push(Selected {
+            schema: table.schema.clone(),
+            table: table.name.clone(),
+            reason: Reason::Match(*score),
+        });
+    }
+
+    if !settings.neighbours {
+        return selection;
+    }
+
+    // Neighbours in both directions of a foreign key, the better matching
+    // ones first, capped at the number of matched tables.
+    let matched: Vec<&Table> = ranked
+        .iter()
+        .take(settings.top_k)
+        .map(|(t, _)| *t)
+        .collect();
+    let score = |t: &Table| {
+        ranked
+            .iter()
+            .find(|(r, _)| r.schema == t.schema && r.name == t.name)
+            .map(|(_, s)| *s)
+            .unwrap_or_default()
+    };
+
+    let mut neighbours: Vec<(&Table, String)> = vec![];
+
+    for table in &matched {
+        for fk in &table.foreign_keys {
+            if let Some(target) = catalog.table(&fk.schema, &fk.table) {
+                neighbours.push((target, table.name.clone()));
+            }
+        }
+
+        for other in catalog.tables() {
+            let references = other
+                .foreign_keys
+                .iter()
+                .any(|fk| fk.schema == table.schema && fk.table == table.name);
+
+            if references {
+                neighbours.push((other, table.name.clone()));
+            }
+        }
+    }
+
+    neighbours.sort_by(|a, b| score(b.0).total_cmp(&score(a.0)));
+
+    for (table, via) in neighbours {
+        if selection.tables.len() >= matched.len() * 2 {
+            break;
+        }
+
+        if !selection.contains(table) {
+            selection.tables.push(Selected {
+                schema: table.schema.clone(),
+                table: table.name.clone(),
+                reason: Reason::Neighbour(via),
+            });
+        }
+    }
+
+    selection
+}
+
+/// Lowercase terms of a text. Identifiers are split on underscores and
+/// camel case, common question words are dropped and plurals are folded.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .flat_map(split_camel_case)
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.len() > 1 && !STOPWORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+}
+
+fn split_camel_case(word: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut previous_lower = false;
+
+    for c in word.chars() {
+        if c.is_uppercase() && previous_lower {
+            words.push(std::mem::take(&mut current));
+        }
+        previous_lower = c.is_lowercase() || c.is_ascii_digit();
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    words
+}
+
+fn stem(word: &str) -> String {
+    if let Some(stem) = word.strip_suffix("ies") {
+        return format!("{}y", stem);
+    }
+
+    match word.strip_suffix('s') {
+        Some(stem) if word.len() > 3 && !stem.ends_with('s') => stem.to_string(),
+        _ => word.to_string(),
+    }
+}
@@ -0,0 +1,735 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tokio_postgres::Client;
+
+/// Schemas that belong to Postgres itself and are never introspected.
+const SYSTEM_SCHEMAS: &str = "n.nspname NOT IN ('pg_catalog', 'information_schema')
+    AND n.nspname NOT LIKE 'pg_toast%'
+    AND n.nspname NOT LIKE 'pg_temp%'";
+
+/// Keywords other than the unreserved ones, which Postgres only reads as
+/// names when quoted; `pg_get_keywords()` as of Postgres 17.
+const RESERVED: [&str; 163] = [
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "authorization",
+    "between",
+    "bigint",
+    "binary",
+    "bit",
+    "boolean",
+    "both",
+    "case",
+    "cast",
+    "char",
+    "character",
+    "check",
+    "coalesce",
+    "collate",
+    "collation",
+    "column",
+    "concurrently",
+    "constraint",
+    "create",
+    "cross",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "dec",
+    "decimal",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "exists",
+    "extract",
+    "false",
+    "fetch",
+    "float",
+    "for",
+    "foreign",
+    "freeze",
+    "from",
+    "full",
+    "grant",
+    "greatest",
+    "group",
+    "grouping",
+    "having",
+    "ilike",
+    "in",
+    "initially",
+    "inner",
+    "inout",
+    "int",
+    "integer",
+    "intersect",
+    "interval",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "json_array",
+    "json_arrayagg",
+    "json_exists",
+    "json_object",
+    "json_objectagg",
+    "json_query",
+    "json_scalar",
+    "json_serialize",
+    "json_table",
+    "json_value",
+    "lateral",
+    "leading",
+    "least",
+    "left",
+    "like",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "merge_action",
+    "national",
+    "natural",
+    "nchar",
+    "none",
+    "normalize",
+    "not",
+    "notnull",
+    "null",
+    "nullif",
+    "numeric",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "out",
+    "outer",
+    "overlaps",
+    "overlay",
+    "placing",
+    "position",
+    "precision",
+    "primary",
+    "real",
+    "references",
+    "returning",
+    "right",
+    "row",
+    "select",
+    "session_user",
+    "setof",
+    "similar",
+    "smallint",
+    "some",
+    "substring",
+    "symmetric",
+    "system_user",
+    "table",
+    "tablesample",
+    "then",
+    "time",
+    "timestamp",
+    "to",
+    "trailing",
+    "treat",
+    "trim",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "values",
+    "varchar",
+    "variadic",
+    "verbose",
+    "when",
+    "where",
+    "window",
+    "with",
+    "xmlattributes",
+    "xmlconcat",
+    "xmlelement",
+    "xmlexists",
+    "xmlforest",
+    "xmlnamespaces",
+    "xmlparse",
+    "xmlpi",
+    "xmlroot",
+    "xmlserialize",
+    "xmltable",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TableKind {
+    Table,
+    View,
+    MaterializedView,
+    ForeignTable,
+    PartitionedTable,
+}
+
+impl TableKind {
+    fn from_relkind(relkind: &str) -> Self {
+        match relkind {
+            "v" => TableKind::View,
+            "m" => TableKind::MaterializedView,
+            "f" => TableKind::ForeignTable,
+            "p" => TableKind::PartitionedTable,
+            _ => TableKind::Table,
+        }
+    }
+
+    pub(crate) fn keyword(&self) -> &'static str {
+        match self {
+            TableKind::Table | TableKind::PartitionedTable => "TABLE",
+            TableKind::View => "VIEW",
+            TableKind::MaterializedView => "MATERIALIZED VIEW",
+            TableKind::ForeignTable => "FOREIGN TABLE",
+        }
+    }
+}
+
+impl fmt::Display for TableKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableKind::Table => write!(f, "table"),
+            TableKind::View => write!(f, "view"),
+            TableKind::MaterializedView => write!(f, "materialized view"),
+            TableKind::ForeignTable => write!(f, "foreign table"),
+            TableKind::PartitionedTable => write!(f, "partitioned table"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    pub type_name: String,
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// A primary key or unique constraint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Key {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub schema: String,
+    pub table: String,
+    pub referenced: Vec<String>,
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub kind: TableKind,
+    pub comment: Option<String>,
+    pub columns: Vec<Column>,
+    pub primary_key: Option<Key>,
+    pub unique: Vec<Key>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub indexes: Vec<String>,
+    /// Planner estimate from `reltuples`, unknown until the table is analyzed.
+    pub estimated_rows: Option<i64>,
+    /// Columns left out by the access rules of the connection.
+    #[serde(default)]
+    pub hidden: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub schema: String,
+    pub name: String,
+    pub arguments: String,
+    pub result: String,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub name: String,
+    pub tables: Vec<Table>,
+    pub functions: Vec<Function>,
+}
+
+/// Everything the prompt and the schema browser know about a database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Catalog {
+    pub schemas: Vec<Schema>,
+    /// Labels of enum types, keyed by the type name as columns show it.
+    pub enums: BTreeMap<String, Vec<String>>,
+}
+
+impl Table {
+    /// Schema-qualified name, quoted where Postgres needs it.
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+
+    pub fn is_key(&self, column: &str) -> bool {
+        self.primary_key
+            .as_ref()
+            .is_some_and(|key| key.columns.iter().any(|c| c == column))
+    }
+
+    pub fn foreign_key(&self, column: &str) -> Option<&ForeignKey> {
+        self.foreign_keys
+            .iter()
+            .find(|fk| fk.columns.iter().any(|c| c == column))
+    }
+}
+
+impl Function {
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.arguments)
+    }
+}
+
+impl Catalog {
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.schemas.iter().flat_map(|s| s.tables.iter())
+    }
+
+    pub fn table(&self, schema: &str, name: &str) -> Option<&Table> {
+        self.tables().find(|t| t.schema == schema && t.name == name)
+    }
+
+    /// Labels of the enum type of a column, if it has one.
+    pub fn enum_values(&self, column: &Column) -> Option<&[String]> {
+        self.enums
+            .get(column.type_name.trim_end_matches("[]"))
+            .map(Vec::as_slice)
+    }
+}
+
+/// First rows of a table, for the schema browser. Tables with hidden
+/// columns list the visible ones instead of `*`.
+pub fn preview_query(table: &Table) -> String {
+    let columns = if table.hidden.is_empty() {
+        "*".to_string()
+    } else {
+        table
+            .columns
+            .iter()
+            .map(|c| quote_ident(&c.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!("SELECT {} FROM {} LIMIT 100", columns, table.qualified_name())
+}
+
+/// Columns of a table with their types, defaults and comments.
+pub fn describe_query(table: &Table) -> String {
+    let hidden = if table.hidden.is_empty() {
+        String::new()
+    } else {
+        let names: Vec<String> = table
+            .hidden
+            .iter()
+            .map(|c| format!("'{}'", c.replace('\'', "''")))
+            .collect();
+        format!("\n    AND a.attname::text NOT IN ({})", names.join(", "))
+    };
+
+    format!(
+        "SELECT a.attname AS column, format_type(a.atttypid, a.atttypmod) AS type,
+    NOT a.attnotnull AS nullable, pg_get_expr(d.adbin, d.adrelid) AS default,
+    col_description(a.attrelid, a.attnum) AS comment
+FROM pg_attribute a
+LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+WHERE a.attrelid = '{}'::regclass AND a.attnum > 0 AND NOT a.attisdropped{}
+ORDER BY a.attnum",
+        table.qualified_name().replace('\'', "''"),
+        hidden
+    )
+}
+
+/// Reads schemas, relations, columns, keys, indexes, functions and enum
+/// types from the system catalogs. Objects owned by extensions are skipped.
+pub async fn introspect(client: &Client) -> Result<Catalog, tokio_postgres::Error> {
+    let mut tables: BTreeMap<(String, String), Table> = BTreeMap::new();
+
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, c.relkind::text,
+            obj_description(c.oid, 'pg_class'), c.reltuples::float8,
+            a.attname::text, format_type(a.atttypid, a.atttypmod), a.attnotnull,
+            pg_get_expr(d.adbin, d.adrelid), col_description(c.oid, a.attnum)
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        LEFT JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped
+        LEFT JOIN pg_attrdef d ON d.adrelid = c.oid AND d.adnum = a.attnum
+        WHERE c.relkind IN ('r', 'v', 'm', 'f', 'p')
+            AND NOT c.relispartition
+            AND {}
+            AND NOT EXISTS (
+                SELECT 1 FROM pg_depend dep
+                WHERE dep.classid = 'pg_class'::regclass AND dep.objid = c.oid AND dep.deptype = 'e'
+            )
+        ORDER BY n.nspname, c.relname, a.attnum",
+        SYSTEM_SCHEMAS
+    );
+
+    for row in client.query(&query, &[]).await? {
+        let schema: String = row.get(0);
+        let name: String = row.get(1);
+
+        let table = tables
+            .entry((schema.clone(), name.clone()))
+            .or_insert_with(|| Table {
+                schema,
+                name,
+                kind: TableKind::from_relkind(row.get(2)),
+                comment: row.get(3),
+                columns: vec![],
+                primary_key: None,
+                unique: vec![],
+                foreign_keys: vec![],
+                indexes: vec![],
+                estimated_rows: row
+                    .get::<_, Option<f64>>(4)
+                    .filter(|rows| *rows >= 0.0)
+                    .map(|rows| rows as i64),
+                hidden: vec![],
+            });
+
+        if let Some(column) = row.get::<_, Option<String>>(5) {
+            table.columns.push(Column {
+                name: column,
+                type_name: row.get(6),
+                nullable: !row.get::<_, bool>(7),
+                default: row.get(8),
+                comment: row.get(9),
+            });
+        }
+    }
+
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, con.conname::text, con.contype::text,
+            ARRAY(
+                SELECT a.attname::text FROM unnest(con.conkey) WITH ORDINALITY k(attnum, ord)
+                JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+                ORDER BY k.ord
+            ),
+            fn.nspname::text, fc.relname::text,
+            ARRAY(
+                SELECT a.attname::text FROM unnest(con.confkey) WITH ORDINALITY k(attnum, ord)
+                JOIN pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+                ORDER BY k.ord
+            ),
+            pg_get_constraintdef(con.oid)
+        FROM pg_constraint con
+        JOIN pg_class c ON c.oid = con.conrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        LEFT JOIN pg_class fc ON fc.oid = con.confrelid
+        LEFT JOIN pg_namespace fn ON fn.oid = fc.relnamespace
+        WHERE con.contype IN ('p', 'u', 'f') AND {}
+        ORDER BY n.nspname, c.relname, con.conname",
+        SYSTEM_SCHEMAS
+    );
+
+    for row in client.query(&query, &[]).await? {
+        let Some(table) = tables.get_mut(&(row.get(0), row.get(1))) else {
+            continue;
+        };
+
+        let name: String = row.get(2);
+        let columns: Vec<String> = row.get(4);
+        let definition: String = row.get(8);
+
+        match row.get::<_, &str>(3) {
+            "p" => {
+                table.primary_key = Some(Key {
+                    name,
+                    columns,
+                    definition,
+                })
+            }
+            "u" => table.unique.push(Key {
+                name,
+                columns,
+                definition,
+            }),
+            _ => table.foreign_keys.push(ForeignKey {
+                name,
+                columns,
+                schema: row.get(5),
+                table: row.get(6),
+                referenced: row.get(7),
+                definition,
+            }),
+        }
+    }
+
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, pg_get_indexdef(i.indexrelid)
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE NOT EXISTS (
+                SELECT 1 FROM pg_constraint con
+                WHERE con.conindid = i.indexrelid AND con.conrelid = i.indrelid
+            )
+            AND {}
+        ORDER BY 1, 2, 3",
+        SYSTEM_SCHEMAS
+    );
+
+    for row in client.query(&query, &[]).await? {
+        if let Some(table) = tables.get_mut(&(row.get(0), row.get(1))) {
+            table.indexes.push(row.get(2));
+        }
+    }
+
+    let mut schemas: BTreeMap<String, Schema> = BTreeMap::new();
+
+    let query = format!(
+        "SELECT n.nspname::text FROM pg_namespace n WHERE {} ORDER BY 1",
+        SYSTEM_SCHEMAS
+    );
+
+    for row in client.query(&query, &[]).await? {
+        let name: String = row.get(0);
+        schemas.insert(
+            name.clone(),
+            Schema {
+                name,
+                tables: vec![],
+                functions: vec![],
+            },
+        );
+    }
+
+    let query = format!(
+        "SELECT n.nspname::text, p.proname::text, pg_get_function_identity_arguments(p.oid),
+            COALESCE(pg_get_function_result(p.oid), ''), obj_description(p.oid, 'pg_proc')
+        FROM pg_proc p
+        JOIN pg_namespace n ON n.oid = p.pronamespace
+        WHERE {}
+            AND NOT EXISTS (
+                SELECT 1 FROM pg_depend dep
+                WHERE dep.classid = 'pg_proc'::regclass AND dep.objid = p.oid AND dep.deptype = 'e'
+            )
+        ORDER BY 1, 2, 3",
+        SYSTEM_SCHEMAS
+    );
+
+    for row in client.query(&query, &[]).await? {
+        if let Some(schema) = schemas.get_mut(row.get::<_, &str>(0)) {
+            schema.functions.push(Function {
+                schema: row.get(0),
+                name: row.get(1),
+                arguments: row.get(2),
+                result: row.get(3),
+                comment: row.get(4),
+            });
+        }
+    }
+
+    let query = format!(
+        "SELECT format_type(t.oid, NULL), array_agg(e.enumlabel::text ORDER BY e.enumsortorder)
+        FROM pg_type t
+        JOIN pg_enum e ON e.enumtypid = t.oid
+        JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE {}
+        GROUP BY t.oid",
+        SYSTEM_SCHEMAS
+    );
+
+    let enums = client
+        .query(&query, &[])
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    for ((schema, _), table) in tables {
+        if let Some(schema) = schemas.get_mut(&schema) {
+            schema.tables.push(table);
+        }
+    }
+
+    // Empty schemas such as a bare `public` only add noise.
+    let schemas = schemas
+        .into_values()
+        .filter(|s| !s.tables.is_empty() || !s.functions.is_empty())
+        .collect();
+
+    Ok(Catalog { schemas, enums })
+}
+
+/// The schemas Postgres searches for unqualified names, in order, the
+/// implicit ones included.
+pub async fn search_path(client: &Client) -> Result<Vec<String>, tokio_postgres::Error> {
+    client
+        .query_one("SELECT current_schemas(true)::text[]", &[])
+        .await?
+        .try_get(0)
+}
+
+/// The tables each view reads, through other views too, as schema and name
+/// of the view and of the table.
+pub async fn view_tables(
+    client: &Client,
+) -> Result<Vec<((String, String), (String, String))>, tokio_postgres::Error> {
+    let query = format!(
+        "WITH RECURSIVE reads(view, relation) AS (
+            SELECT DISTINCT r.ev_class, d.refobjid
+            FROM pg_rewrite r
+            JOIN pg_depend d ON d.classid = 'pg_rewrite'::regclass AND d.objid = r.oid
+                AND d.refclassid = 'pg_class'::regclass AND d.refobjid <> r.ev_class
+          UNION
+            SELECT reads.view, d.refobjid
+            FROM reads
+            JOIN pg_rewrite r ON r.ev_class = reads.relation
+            JOIN pg_depend d ON d.classid = 'pg_rewrite'::regclass AND d.objid = r.oid
+                AND d.refclassid = 'pg_class'::regclass AND d.refobjid <> r.ev_class
+        )
+        SELECT n.nspname, v.relname, tn.nspname, t.relname
+        FROM reads
+        JOIN pg_class v ON v.oid = reads.view
+        JOIN pg_namespace n ON n.oid = v.relnamespace
+        JOIN pg_class t ON t.oid = reads.relation AND t.relkind IN ('r', 'p', 'f', 'm')
+        JOIN pg_namespace tn ON tn.oid = t.relnamespace
+        WHERE {}",
+        SYSTEM_SCHEMAS
+    );
+
+    Ok(client
+        .query(&query, &[])
+        .await?
+        .iter()
+        .map(|row| ((row.get(0), row.get(1)), (row.get(2), row.get(3))))
+        .collect())
+}
+
+/// Hash over everything `introspect` reads, cheap enough to run on every
+/// connect. It changes with tables, columns, types, comments, constraints,
+/// indexes, functions and enum labels, but not with row estimates.
+pub async fn fingerprint(client: &Client) -> Result<String, tokio_postgres::Error> {
+    let query = format!(
+        "SELECT md5(COALESCE(string_agg(marker, ',' ORDER BY marker), ''))
+        FROM (
+            SELECT c.oid::text || c.relname || c.relkind::text || c.relnamespace
+                    || COALESCE(obj_description(c.oid, 'pg_class'), '') AS marker
+            FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind IN ('r', 'v', 'm', 'f', 'p', 'i') AND {0}
+            UNION ALL
+            SELECT a.attrelid::text || '.' || a.attnum || a.attname || a.atttypid || a.atttypmod
+                    || a.attnotnull || COALESCE(d.oid::text, '')
+                    || COALESCE(col_description(a.attrelid, a.attnum), '')
+            FROM pg_attribute a
+            JOIN pg_class c ON c.oid = a.attrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+            WHERE a.attnum > 0 AND NOT a.attisdropped
+                AND c.relkind IN ('r', 'v', 'm', 'f', 'p') AND {0}
+            UNION ALL
+            SELECT con.oid::text || con.conname
+            FROM pg_constraint con JOIN pg_namespace n ON n.oid = con.connamespace
+            WHERE {0}
+            UNION ALL
+            SELECT p.oid::text || p.proname || COALESCE(obj_description(p.oid, 'pg_proc'), '')
+            FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace
+            WHERE {0}
+            UNION ALL
+            SELECT e.oid::text || e.enumlabel FROM pg_enum e
+        ) markers",
+        SYSTEM_SCHEMAS
+    );
+
+    Ok(client.query_one(&query, &[]).await?.get(0))
+}
+
+/// Quotes an identifier unless Postgres would read it back unchanged.
+pub fn quote_ident(ident: &str) -> String {
+    let plain = ident
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && ident
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '$')
+        && !RESERVED.contains(&ident);
+
+    if plain {
+        ident.to_string()
+    } else {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A catalog of plain `text` tables, given as `schema.table` and their
+    /// columns.
+    pub(crate) fn catalog(tables: &[(&str, &[&str])]) -> Catalog {
+        let mut catalog = Catalog::default();
+
+        for (name, columns) in tables {
+            let (schema, name) = name.split_once('.').unwrap();
+            let table = Table {
+                schema: schema.to_string(),
+                name: name.to_string(),
+                kind: TableKind::Table,
+                comment: None,
+                columns: columns
+                    .iter()
+                    .map(|column| Column {
+                        name: column.to_string(),
+                        type_name: "text".to_string(),
+                        nullable: true,
+                        default: None,
+                        comment: None,
+                    })
+                    .collect(),
+                primary_key: None,
+                unique: vec![],
+                foreign_keys: vec![],
+                indexes: vec![],
+                estimated_rows: None,
+                hidden: vec![],
+            };
+
+            match catalog.schemas.iter_mut().find(|s| s.name == schema) {
+                Some(existing) => existing.tables.push(table),
+                None => catalog.schemas.push(Schema {
+                    name: schema.to_string(),
+                    tables: vec![table],
+                    functions: vec![],
+                }),
+            }
+        }
+
+        catalog
+    }
+}
@@ -0,0 +1,579 @@
+use std::time::{Duration, Instant};
+
+use sqlparser::ast::{SetExpr, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{Client, SimpleQueryMessage};
+
+use crate::cancel::Canceller;
+use crate::config::{Limits, OnError, Scripts};
+use crate::errors::Error;
+use crate::limits;
+use crate::results::ResultSet;
+
+const SAVEPOINT: &str = "pgp_statement";
+const READ_ONLY: &str = "pgp_read_only";
+
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Rows(ResultSet),
+    Command,
+    Failed(String),
+}
+
+/// What one statement of a script did: its command tag as Postgres reports
+/// it, and the number of rows returned or affected.
+#[derive(Debug, Clone)]
+pub struct StatementResult {
+    pub statement: String,
+    pub command: String,
+    pub rows: u64,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    /// Why the statement was found to be expensive, when it ran anyway.
+    pub warning: Option<String>,
+    /// The error code when Postgres failed the statement.
+    pub sqlstate: Option<SqlState>,
+}
+
+impl StatementResult {
+    fn note(command: &str) -> Self {
+        Self {
+            statement: command.to_string(),
+            command: command.to_string(),
+            rows: 0,
+            outcome: Outcome::Command,
+            duration: Duration::ZERO,
+            warning: None,
+            sqlstate: None,
+        }
+    }
+
+    /// A statement that was not run.
+    pub fn rejected(statement: &str, reason: String) -> Self {
+        Self {
+            statement: statement.to_string(),
+            command: String::new(),
+            rows: 0,
+            outcome: Outcome::Failed(reason),
+            duration: Duration::ZERO,
+            warning: None,
+            sqlstate: None,
+        }
+    }
+
+    fn failed(statement: &str, error: &tokio_postgres::Error) -> Self {
+        Self {
+            statement: statement.to_string(),
+            command: String::new(),
+            rows: 0,
+            outcome: Outcome::Failed(match error.as_db_error() {
+                Some(db_error) => db_error.to_string(),
+                None => error.to_string(),
+            }),
+            duration: Duration::ZERO,
+            warning: None,
+            sqlstate: error.code().cloned(),
+        }
+    }
+}
+
+/// Splits SQL text into statements on top-level semicolons. Semicolons in
+/// strings, quoted identifiers, comments and dollar-quoted bodies are left
+/// alone, and statements that are only comments are dropped.
+pub fn split(sql: &str) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut code = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let begin = i;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        match c {
+            '-' if next == Some('-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if next == Some('*') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    match (chars[i], chars.get(i + 1).copied()) {
+                        ('/', Some('*')) => {
+                            depth += 1;
+                            i += 2;
+                        }
+                        ('*', Some('/')) => {
+                            depth -= 1;
+                            i += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => i += 1,
+                    }
+                }
+                continue;
+            }
+            '\'' => {
+                let escapes = i > 0
+                    && matches!(chars[i - 1], 'e' | 'E')
+                    && (i < 2 || !is_identifier(chars[i - 2]));
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += if escapes && chars[i] == '\\' { 2 } else { 1 };
+                }
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+            }
+            '$' if i == 0 || !is_identifier(chars[i - 1]) => {
+                if let Some(tag) = dollar_tag(&chars[i..]) {
+                    let body = i + tag.len();
+                    i = (body..chars.len())
+                        .find(|&j| chars[j..].starts_with(&tag))
+                        .map(|j| j + tag.len() - 1)
+                        .unwrap_or(chars.len());
+                }
+            }
+            ';' => {
+                if code {
+                    statements.push(chars[start..i].iter().collect::<String>());
+                }
+                code = false;
+                i += 1;
+                continue;
+            }
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        // Leading comments are not part of the statement.
+        if !code {
+            start = begin;
+            code = true;
+        }
+
+        i += 1;
+    }
+
+    if code {
+        statements.push(chars[start..].iter().collect::<String>());
+    }
+
+    statements
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .collect()
+}
+
+/// Whether a transaction is in progress on the connection, such as one the
+/// user began in the editor. Only the first statement of a transaction
+/// starts at the transaction's own start time. It runs as a simple query,
+/// since the extended protocol starts the transaction before the statement.
+pub async fn in_transaction(client: &Client) -> Result<bool, tokio_postgres::Error> {
+    let messages = client
+        .simple_query("SELECT now() <> statement_timestamp()")
+        .await?;
+
+    Ok(messages.iter().any(|message| match message {
+        SimpleQueryMessage::Row(row) => row.get(0) == Some("t"),
+        _ => false,
+    }))
+}
+
+/// Makes what runs next read-only until `end_read_only`: in a transaction
+/// of its own, or in a savepoint when one is in progress, so that ending it
+/// leaves that transaction as it was. Returns whether a savepoint was used.
+pub async fn begin_read_only(client: &Client) -> Result<bool, tokio_postgres::Error> {
+    if in_transaction(client).await? {
+        client
+            .batch_execute(&format!(
+                "SAVEPOINT {}; SET TRANSACTION READ ONLY",
+                READ_ONLY
+            ))
+            .await?;
+        return Ok(true);
+    }
+
+    client.batch_execute("BEGIN READ ONLY").await?;
+    Ok(false)
+}
+
+/// Undoes everything since `begin_read_only`.
+pub async fn end_read_only(client: &Client, savepoint: bool) -> Result<(), tokio_postgres::Error> {
+    if savepoint {
+        client
+            .batch_execute(&format!(
+                "ROLLBACK TO SAVEPOINT {0}; RELEASE SAVEPOINT {0}",
+                READ_ONLY
+            ))
+            .await
+    } else {
+        client.batch_execute("ROLLBACK").await
+    }
+}
+
+/// Runs statements in order. With `transaction` set the script runs inside
+/// one transaction, and continuing past an error rolls back to a savepoint
+/// taken before the failed statement. A cancelled statement always ends the
+/// script. Rows are read within `limits`. Each result is passed to `record`
+/// as it comes; when that fails, the script stops, is rolled back, and the
+/// error is returned.
+pub async fn execute(
+    client: &Client,
+    statements: &[String],
+    settings: &Scripts,
+    limits: &Limits,
+    canceller: &Canceller,
+    record: impl Fn(&StatementResult) -> Result<(), Error>,
+) -> Result<Vec<StatementResult>, Error> {
+    let continues = settings.on_error == OnError::Continue;
+    let savepoints = settings.transaction && continues;
+    let mut results = vec![];
+    let mut aborted = false;
+    let mut unrecorded = None;
+
+    if settings.transaction {
+        if let Err(e) = client.batch_execute("BEGIN").await {
+            let result = StatementResult::failed("BEGIN", &e);
+            record(&result)?;
+            return Ok(vec![result]);
+        }
+    }
+
+    for statement in statements {
+        if canceller.is_cancelled() {
+            aborted = true;
+            break;
+        }
+
+        if savepoints {
+            if let Err(e) = client
+                .batch_execute(&format!("SAVEPOINT {}", SAVEPOINT))
+                .await
+            {
+                let result = StatementResult::failed(statement, &e);
+                unrecorded = record(&result).err();
+                results.push(result);
+                aborted = true;
+                break;
+            }
+        }
+
+        let started = Instant::now();
+
+        let ran = run_statement(client, statement, settings, limits, canceller).await;
+
+        let (result, stop) = match ran {
+            Ok(mut result) => {
+                result.duration = started.elapsed();
+                if savepoints {
+                    let _ = client
+                        .batch_execute(&format!("RELEASE SAVEPOINT {}", SAVEPOINT))
+                        .await;
+                }
+                (result, false)
+            }
+            Err(e) => {
+                let cancelled = e.code() == Some(&SqlState::QUERY_CANCELED);
+                let result = StatementResult {
+                    duration: started.elapsed(),
+                    ..StatementResult::failed(statement, &e)
+                };
+
+                if savepoints && !cancelled {
+                    let _ = client
+                        .batch_execute(&format!("ROLLBACK TO SAVEPOINT {}", SAVEPOINT))
+                        .await;
+                }
+
+                (result, cancelled || !continues)
+            }
+        };
+
+        unrecorded = record(&result).err();
+        results.push(result);
+
+        if stop || unrecorded.is_some() {
+            aborted = true;
+            break;
+        }
+    }
+
+    if settings.transaction {
+        let end = if aborted { "ROLLBACK" } else { "COMMIT" };
+
+        let result = match client.batch_execute(end).await {
+            Ok(()) => StatementResult::note(end),
+            Err(e) => StatementResult::failed(end, &e),
+        };
+
+        if unrecorded.is_none() {
+            unrecorded = record(&result).err();
+        }
+        results.push(result);
+    }
+
+    match unrecorded {
+        Some(e) => Err(e),
+        None => Ok(results),
+    }
+}
+
+async fn run_statement(
+    client: &Client,
+    statement: &str,
+    settings: &Scripts,
+    limits: &Limits,
+    canceller: &Canceller,
+) -> Result<StatementResult, tokio_postgres::Error> {
+    let prepared = client
+        .prepare(&limits::limit(statement, limits.max_rows))
+        .await?;
+
+    if prepared.columns().is_empty() {
+        let affected = client.execute(&prepared, &[]).await?;
+
+        return Ok(StatementResult {
+            statement: statement.to_string(),
+            command: command_tag(statement, affected),
+            rows: affected,
+            outcome: Outcome::Command,
+            duration: Duration::ZERO,
+            warning: None,
+            sqlstate: None,
+        });
+    }
+
+    let (rows, truncated) =
+        limits::fetch(client, &prepared, limits, canceller, !settings.transaction).await?;
+    let count = rows.len() as u64;
+    let mut result = ResultSet {
+        truncated,
+        ..ResultSet::new(statement, prepared.columns(), &rows)
+    };
+    result.trace(client, prepared.columns()).await;
+
+    Ok(StatementResult {
+        statement: statement.to_string(),
+        command: command_tag(statement, count),
+        rows: count,
+        outcome: Outcome::Rows(result),
+        duration: Duration::ZERO,
+        warning: None,
+        sqlstate: None,
+    })
+}
+
+/// Rebuilds the command tag from the statement, since the extended protocol
+/// only hands back the row count.
+fn command_tag(statement: &str, rows: u64) -> String {
+    const MODIFIERS: [&str; 8] = [
+        "OR",
+        "REPLACE",
+        "TEMP",
+        "TEMPORARY",
+        "UNIQUE",
+        "UNLOGGED",
+        "GLOBAL",
+        "LOCAL",
+    ];
+
+    let mut words = statement
+        .split(|c: char| c.is_whitespace() || c == '(')
+        .filter(|w| !w.is_empty())
+        .map(str::to_uppercase);
+
+    let first = words.next().unwrap_or_default();
+    let verb = row_command(statement).map_or(first, str::to_string);
+
+    match verb.as_str() {
+        "SELECT" | "WITH" | "VALUES" | "TABLE" => format!("SELECT {}", rows),
+        "INSERT" => format!("INSERT 0 {}", rows),
+        "UPDATE" | "DELETE" | "MERGE" | "FETCH" | "MOVE" | "COPY" => {
+            format!("{} {}", verb, rows)
+        }
+        "CREATE" | "DROP" | "ALTER" => {
+            let mut words = words.filter(|w| !MODIFIERS.contains(&w.as_str()));
+
+            match words.next() {
+                Some(object) if object == "MATERIALIZED" || object == "FOREIGN" => {
+                    let rest = words.next().unwrap_or_default();
+                    format!("{} {} {}", verb, object, rest)
+                }
+                Some(object) => format!("{} {}", verb, object),
+                None => verb,
+            }
+        }
+        _ => verb,
+    }
+}
+
+/// The command of a statement that reads or changes rows, from its parsed
+/// form, so a statement after `WITH` is tagged by what it does.
+fn row_command(statement: &str) -> Option<&'static str> {
+    match Parser::parse_sql(&PostgreSqlDialect {}, statement).as_deref() {
+        Ok([Statement::Query(query)]) => Some(match query.body.as_ref() {
+            SetExpr::Insert(_) => "INSERT",
+            SetExpr::Update(_) => "UPDATE",
+            _ => "SELECT",
+        }),
+        Ok([Statement::Insert(_)]) => Some("INSERT"),
+        Ok([Statement::Update { .. }]) => Some("UPDATE"),
+        Ok([Statement::Delete(_)]) => Some("DELETE"),
+        Ok([Statement::Merge { .. }]) => Some("MERGE"),
+        Ok(_) => None,
+        // The parser takes no `DELETE` or `MERGE` after `WITH`.
+        Err(_) => after_with(statement),
+    }
+}
+
+/// The command after the common table expressions of a `WITH` statement:
+/// its first command keyword outside parentheses.
+fn after_with(statement: &str) -> Option<&'static str> {
+    let tokens = Tokenizer::new(&PostgreSqlDialect {}, statement)
+        .tokenize()
+        .ok()?;
+    let mut tokens = tokens
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)));
+
+    match tokens.next()? {
+        Token::Word(word) if word.keyword == Keyword::WITH => {}
+        _ => return None,
+    }
+
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Word(word) if depth == 0 && word.quote_style.is_none() => match word.keyword {
+                Keyword::SELECT | Keyword::VALUES | Keyword::TABLE => return Some("SELECT"),
+                Keyword::INSERT => return Some("INSERT"),
+                Keyword::UPDATE => return Some("UPDATE"),
+                Keyword::DELETE => return Some("DELETE"),
+                Keyword::MERGE => return Some("MERGE"),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The `$tag$` opening a dollar-quoted string at the start of `chars`.
+fn dollar_tag(chars: &[char]) -> Option<Vec<char>> {
+    let end = chars[1..].iter().position(|c| *c == '$')? + 1;
+    let name = &chars[1..end];
+
+    let valid =
+        name.iter().all(|c| is_identifier(*c)) && !name.first().is_some_and(|c| c.is_ascii_digit());
+
+    valid.then(|| chars[..=end].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_top_level_semicolons() {
+        assert_eq!(
+            split("select 1; select 2;\n\nselect 3"),
+            ["select 1", "select 2", "select 3"]
+        );
+    }
+
+    #[test]
+    fn keeps_semicolons_in_strings_identifiers_and_comments() {
+        let sql = concat!(
+            "select 'a;b', \"c;d\" /* e; /* nested; */ f; */ from t -- g; h\n;",
+            "select E'it\\'s;' as x"
+        );
+
+        assert_eq!(
+            split(sql),
+            [
+                "select 'a;b', \"c;d\" /* e; /* nested; */ f; */ from t -- g; h",
+                "select E'it\\'s;' as x",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_dollar_quoted_bodies_whole() {
+        let sql =
+            "create function f() returns int as $body$ select 1; $body$ language sql; select $1";
+
+        assert_eq!(
+            split(sql),
+            [
+                "create function f() returns int as $body$ select 1; $body$ language sql",
+                "select $1",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_comment_only_statements() {
+        assert_eq!(
+            split("-- only a comment\n; /* and another */; ;select 1"),
+            ["select 1"]
+        );
+        assert!(split("  ;  -- nothing\n").is_empty());
+    }
+
+    #[test]
+    fn tags_commands_by_what_they_do() {
+        assert_eq!(command_tag("select * from t", 3), "SELECT 3");
+        assert_eq!(command_tag("insert into t values (1)", 1), "INSERT 0 1");
+        assert_eq!(command_tag("update t set a = 1", 2), "UPDATE 2");
+        assert_eq!(
+            command_tag("with x as (select 1) delete from t", 4),
+            "DELETE 4"
+        );
+        assert_eq!(
+            command_tag("with x as (select 1) insert into t select * from x", 5),
+            "INSERT 0 5"
+        );
+    }
+
+    #[test]
+    fn tags_ddl_by_its_object() {
+        assert_eq!(command_tag("create table t (a int)", 0), "CREATE TABLE");
+        assert_eq!(
+            command_tag("create or replace temp view v as select 1", 0),
+            "CREATE VIEW"
+        );
+        assert_eq!(
+            command_tag("drop materialized view v", 0),
+            "DROP MATERIALIZED VIEW"
+        );
+        assert_eq!(
+            command_tag("create unique index i on t (a)", 0),
+            "CREATE INDEX"
+        );
+        assert_eq!(command_tag("vacuum t", 0), "VACUUM");
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::access;
+use crate::config::Config;
+use crate::connection::Connection;
+use crate::errors::Error;
+use crate::schema::{self, Catalog, Table};
+
+const CACHE_DIR: &str = "cache";
+
+/// An introspected catalog saved to disk with the fingerprint it was taken
+/// at, so connecting does not have to wait for introspection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub fingerprint: String,
+    pub taken_at: String,
+    pub catalog: Catalog,
+}
+
+/// A catalog read again because the database no longer matches the
+/// fingerprint it was cached with.
+#[derive(Debug, Clone)]
+pub struct Refresh {
+    pub fingerprint: String,
+    pub catalog: Catalog,
+    /// Where names lead in the database as it is now.
+    pub namespace: access::Namespace,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    AddedTable(String),
+    DroppedTable(String),
+    AddedColumn(String, String),
+    DroppedColumn(String, String),
+    AlteredColumn {
+        table: String,
+        column: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::AddedTable(table) => write!(f, "added table {}", table),
+            Change::DroppedTable(table) => write!(f, "dropped table {}", table),
+            Change::AddedColumn(table, column) => write!(f, "added column {}.{}", table, column),
+            Change::DroppedColumn(table, column) => {
+                write!(f, "dropped column {}.{}", table, column)
+            }
+            Change::AlteredColumn {
+                table,
+                column,
+                from,
+                to,
+            } => write!(f, "altered column {}.{}: {} -> {}", table, column, from, to),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn new(fingerprint: String, catalog: Catalog) -> Self {
+        Self {
+            fingerprint,
+            taken_at: Utc::now().to_rfc3339(),
+            catalog,
+        }
+    }
+
+    /// The cached snapshot of a connection, if there is a readable one.
+    pub fn load(connection: &Connection) -> Option<Self> {
+        let contents = std::fs::read_to_string(path(connection)).ok()?;
+
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn save(&self, connection: &Connection) -> Result<(), Error> {
+        let contents = serde_json::to_string(self)?;
+
+        std::fs::create_dir_all(CACHE_DIR)?;
+        std::fs::write(path(connection), contents)?;
+
+        Ok(())
+    }
+}
+
+/// Checks a cached catalog against the database over a connection of its
+/// own, so it can run while the session is busy. Nothing is returned when
+/// the fingerprint still matches; otherwise the schema is introspected,
+/// cached again and compared with the old catalog, both as the access rules
+/// of the connection let them be seen.
+pub async fn refresh(
+    config: Config,
+    id: u8,
+    catalog: Catalog,
+    fingerprint: String,
+) -> Result<Option<Refresh>, Error> {
+    let connection = config.get_connection(id);
+    let client = crate::open_client(connection).await?;
+
+    let current = schema::fingerprint(&client).await?;
+
+    if current == fingerprint {
+        return Ok(None);
+    }
+
+    let fresh = schema::introspect(&client).await?;
+    let _ = Snapshot::new(current.clone(), fresh.clone()).save(connection);
+    let namespace = access::Namespace::new(
+        &fresh,
+        schema::search_path(&client).await?,
+        schema::view_tables(&client).await?,
+    );
+    let fresh = access::filter(fresh, &connection.access);
+
+    Ok(Some(Refresh {
+        fingerprint: current,
+        changes: diff(&catalog, &fresh),
+        catalog: fresh,
+        namespace,
+    }))
+}
+
+/// Tables and columns added, dropped or changed between two catalogs.
+pub fn diff(old: &Catalog, new: &Catalog) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for table in new.tables() {
+        match old.table(&table.schema, &table.name) {
+            None => changes.push(Change::AddedTable(table.qualified_name())),
+            Some(previous) => changes.extend(diff_columns(previous, table)),
+        }
+    }
+
+    for table in old.tables() {
+        if new.table(&table.schema, &table.name).is_none() {
+            changes.push(Change::DroppedTable(table.qualified_name()));
+        }
+    }
+
+    changes
+}
+
+/// One line summing up the changes, for a notice.
+pub fn describe(changes: &[Change]) -> String {
+    if changes.is_empty() {
+        return "Schema refreshed, no table or column changes".to_string();
+    }
+
+    let changes: Vec<String> = changes.iter().map(Change::to_string).collect();
+
+    format!("Schema changed: {}", changes.join("; "))
+}
+
+fn diff_columns(old: &Table, new: &Table) -> Vec<Change> {
+    let table = new.qualified_name();
+    let mut changes = vec![];
+
+    for column in &new.columns {
+        match old.columns.iter().find(|c| c.name == column.name) {
+            None => changes.push(Change::AddedColumn(table.clone(), column.name.clone())),
+            Some(previous) => {
+                let (from, to) = (column_type(previous), column_type(column));
+                if from != to {
+                    changes.push(Change::AlteredColumn {
+                        table: table.clone(),
+                        column: column.name.clone(),
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+    }
+
+    for column in &old.columns {
+        if !new.columns.iter().any(|c| c.name == column.name) {
+            changes.push(Change::DroppedColumn(table.clone(), column.name.clone()));
+        }
+    }
+
+    changes
+}
+
+fn column_type(column: &schema::Column) -> String {
+    let mut description = column.type_name.clone();
+
+    if !column.nullable {
+        description.push_str(" not null");
+    }
+    if let Some(default) = &column.default {
+        description.push_str(&format!(" default {}", default));
+    }
+
+    description
+}
+
+fn path(connection: &Connection) -> PathBuf {
+    let name: String = format!(
+        "{}-{}-{}-{}",
+        connection.id, connection.host, connection.port, connection.database
+    )
+    .chars()
+    .map(|c| {
+        if c.is_alphanumeric() || c == '-' {
+            c
+        } else {
+            '_'
+        }
+    })
+    .collect();
+
+    PathBuf::from(CACHE_DIR).join(format!("schema-{}.json", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::catalog;
+
+    #[test]
+    fn diffs_tables_and_columns() {
+        let old = catalog(&[
+            ("public.users", &["id", "name", "email"]),
+            ("public.sessions", &["id"]),
+        ]);
+        let mut new = catalog(&[
+            ("public.users", &["id", "name", "phone"]),
+            ("public.orders", &["id"]),
+        ]);
+        new.schemas[0].tables[0].columns[1].nullable = false;
+
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Change::AlteredColumn {
+                    table: "public.users".into(),
+                    column: "name".into(),
+                    from: "text".into(),
+                    to: "text not null".into(),
+                },
+                Change::AddedColumn("public.users".into(), "phone".into()),
+                Change::DroppedColumn("public.users".into(), "email".into()),
+                Change::AddedTable("public.orders".into()),
+                Change::DroppedTable("public.sessions".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn describes_changes_in_one_line() {
+        let old = catalog(&[("public.users", &["id"])]);
+        let new = catalog(&[("public.users", &["id", "name"])]);
+
+        assert_eq!(
+            describe(&diff(&old, &new)),
+            "Schema changed: added column public.users.name"
+        );
+        assert_eq!(
+            describe(&diff(&old, &old)),
+            "Schema refreshed, no table or column changes"
+        );
+    }
+}
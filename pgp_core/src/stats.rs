@@ -0,0 +1,276 @@
+use tokio_postgres::Client;
+
+use crate::config::Hints;
+use crate::schema::Table;
+
+/// Name of the function the model calls to get column statistics.
+pub const FUNCTION: &str = "get_column_stats";
+
+/// What the planner statistics say about one column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub column: String,
+    pub null_fraction: f32,
+    /// Positive for a count of distinct values, negative for a fraction of
+    /// the rows, as in `pg_stats`.
+    pub n_distinct: f32,
+    pub common_values: Vec<(String, f32)>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub table: String,
+    pub estimated_rows: Option<i64>,
+    pub columns: Vec<ColumnStats>,
+    /// Requested columns that the configuration keeps out of hints.
+    pub excluded: Vec<String>,
+}
+
+/// Whether a table, or a column of it, is kept out of hints. Patterns are
+/// `schema.table` or `schema.table.column`, where any part may be `*`.
+pub fn is_excluded(hints: &Hints, schema: &str, table: &str, column: Option<&str>) -> bool {
+    hints.exclude.iter().any(|pattern| {
+        let parts: Vec<&str> = pattern.split('.').collect();
+        let matches = |part: &str, name: &str| part == "*" || part == name;
+
+        match (parts.as_slice(), column) {
+            ([s, t], _) => matches(s, schema) && matches(t, table),
+            ([s, t, c], Some(column)) => {
+                matches(s, schema) && matches(t, table) && matches(c, column)
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Reads `pg_stats` for the requested columns of a table. Excluded columns
+/// are never queried.
+pub async fn gather(
+    client: &Client,
+    table: &Table,
+    columns: &[String],
+    hints: &Hints,
+) -> Result<TableStats, tokio_postgres::Error> {
+    let requested: Vec<&str> = if columns.is_empty() {
+        table.columns.iter().map(|c| c.name.as_str()).collect()
+    } else {
+        table
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .filter(|name| columns.iter().any(|c| c == name))
+            .collect()
+    };
+
+    let (excluded, allowed): (Vec<&str>, Vec<&str>) = requested
+        .into_iter()
+        .partition(|c| is_excluded(hints, &table.schema, &table.name, Some(c)));
+
+    let mut stats = TableStats {
+        table: table.qualified_name(),
+        estimated_rows: table.estimated_rows,
+        columns: vec![],
+        excluded: excluded.into_iter().map(str::to_string).collect(),
+    };
+
+    if allowed.is_empty() {
+        return Ok(stats);
+    }
+
+    let rows = client
+        .query(
+            "SELECT attname::text, null_frac, n_distinct,
+                most_common_vals::text, most_common_freqs, histogram_bounds::text
+            FROM pg_stats
+            WHERE schemaname = $1 AND tablename = $2 AND attname = ANY($3)",
+            &[&table.schema, &table.name, &allowed],
+        )
+        .await?;
+
+    for name in allowed {
+        let Some(row) = rows.iter().find(|row| row.get::<_, &str>(0) == name) else {
+            continue;
+        };
+
+        let values = row
+            .get::<_, Option<&str>>(3)
+            .map(parse_array)
+            .unwrap_or_default();
+        let frequencies: Vec<f32> = row.get::<_, Option<Vec<f32>>>(4).unwrap_or_default();
+        let bounds = row
+            .get::<_, Option<&str>>(5)
+            .map(parse_array)
+            .unwrap_or_default();
+
+        stats.columns.push(ColumnStats {
+            column: name.to_string(),
+            null_fraction: row.get(1),
+            n_distinct: row.get(2),
+            common_values: values
+                .into_iter()
+                .zip(frequencies)
+                .take(hints.max_values)
+                .collect(),
+            min: bounds.first().cloned(),
+            max: bounds.last().cloned(),
+        });
+    }
+
+    Ok(stats)
+}
+
+impl TableStats {
+    /// Plain text form sent back to the model.
+    pub fn to_prompt(&self) -> String {
+        let mut lines = vec![match self.estimated_rows {
+            Some(rows) => format!("{} (about {} rows)", self.table, rows),
+            None => self.table.clone(),
+        }];
+
+        if self.columns.is_empty() && self.excluded.is_empty() {
+            lines.push("No statistics, the table has not been analyzed.".to_string());
+        }
+
+        for column in &self.columns {
+            let mut parts = vec![format!("{:.0}% null", column.null_fraction * 100.0)];
+
+            let n = column.n_distinct;
+            if n <= -1.0 {
+                parts.push("unique".to_string());
+            } else if n < 0.0 {
+                match self.estimated_rows {
+                    Some(rows) => parts.push(format!("~{:.0} distinct", -n * rows as f32)),
+                    None => parts.push(format!("{:.0}% distinct", -n * 100.0)),
+                }
+            } else if n > 0.0 {
+                parts.push(format!("{:.0} distinct", n));
+            }
+
+            if !column.common_values.is_empty() {
+                let values: Vec<String> = column
+                    .common_values
+                    .iter()
+                    .map(|(value, frequency)| format!("'{}' {:.0}%", value, frequency * 100.0))
+                    .collect();
+                parts.push(format!("common values: {}", values.join(", ")));
+            }
+
+            if let (Some(min), Some(max)) = (&column.min, &column.max) {
+                parts.push(format!("range '{}' to '{}'", min, max));
+            }
+
+            lines.push(format!("{}: {}", column.column, parts.join("; ")));
+        }
+
+        for column in &self.excluded {
+            lines.push(format!("{}: excluded from hints", column));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Elements of an array in Postgres text form, such as `{a,"b c",NULL}`.
+fn parse_array(text: &str) -> Vec<String> {
+    let inner = text
+        .strip_prefix('{')
+        .and_then(|t| t.strip_suffix('}'))
+        .unwrap_or(text);
+
+    let mut values = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => current.extend(chars.next()),
+            ',' if !quoted => values.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+
+    if !inner.is_empty() {
+        values.push(current);
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_tables_and_columns_by_pattern() {
+        let hints = Hints {
+            exclude: vec!["public.users.email".into(), "analytics.*".into()],
+            ..Hints::default()
+        };
+
+        assert!(is_excluded(&hints, "public", "users", Some("email")));
+        assert!(!is_excluded(&hints, "public", "users", Some("name")));
+        assert!(!is_excluded(&hints, "public", "users", None));
+        assert!(is_excluded(&hints, "analytics", "daily", None));
+        assert!(is_excluded(&hints, "analytics", "daily", Some("score")));
+    }
+
+    #[test]
+    fn parses_text_arrays() {
+        assert_eq!(
+            parse_array(r#"{a,"b, c","say \"hi\"",NULL}"#),
+            ["a", "b, c", "say \"hi\"", "NULL"]
+        );
+        assert!(parse_array("{}").is_empty());
+    }
+
+    #[test]
+    fn writes_stats_for_the_prompt() {
+        let stats = TableStats {
+            table: "public.orders".into(),
+            estimated_rows: Some(1000),
+            columns: vec![
+                ColumnStats {
+                    column: "id".into(),
+                    null_fraction: 0.0,
+                    n_distinct: -1.0,
+                    common_values: vec![],
+                    min: Some("1".into()),
+                    max: Some("1000".into()),
+                },
+                ColumnStats {
+                    column: "status".into(),
+                    null_fraction: 0.1,
+                    n_distinct: 3.0,
+                    common_values: vec![("paid".into(), 0.6)],
+                    min: None,
+                    max: None,
+                },
+                ColumnStats {
+                    column: "note".into(),
+                    null_fraction: 0.5,
+                    n_distinct: -0.25,
+                    common_values: vec![],
+                    min: None,
+                    max: None,
+                },
+            ],
+            excluded: vec!["email".into()],
+        };
+
+        assert_eq!(
+            stats.to_prompt(),
+            [
+                "public.orders (about 1000 rows)",
+                "id: 0% null; unique; range '1' to '1000'",
+                "status: 10% null; 3 distinct; common values: 'paid' 60%",
+                "note: 50% null; ~250 distinct",
+                "email: excluded from hints",
+            ]
+            .join("\n")
+        );
+    }
+}
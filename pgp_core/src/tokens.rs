@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_openai::types::{ChatCompletionRequestMessage, Role};
+use parking_lot::Mutex;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+use crate::config::Budget;
+use crate::injection;
+use crate::results::ResultSet;
+
+/// Every reply is primed with `<|start|>assistant<|message|>`.
+const REPLY_PRIMING: usize = 3;
+const TRUNCATED: &str = "… [truncated]";
+
+/// Counts tokens with the tokenizer of a model, falling back to cl100k for
+/// models tiktoken does not know.
+#[derive(Clone)]
+pub struct Counter {
+    bpe: Arc<Mutex<CoreBPE>>,
+    per_message: usize,
+}
+
+impl Counter {
+    pub fn new(model: &str) -> Self {
+        let bpe = match get_tokenizer(model) {
+            Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+            Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+            Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+            Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+            _ => tiktoken_rs::cl100k_base_singleton(),
+        };
+
+        // gpt-3.5 wraps messages in <im_start>{role}\n{content}<im_end>\n.
+        let per_message = if model.starts_with("gpt-3.5") { 4 } else { 3 };
+
+        Self { bpe, per_message }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe.lock().encode_with_special_tokens(text).len()
+    }
+
+    pub fn message(&self, message: &ChatCompletionRequestMessage) -> usize {
+        let mut tokens = self.per_message + self.count(&message.role.to_string());
+
+        if let Some(content) = &message.content {
+            tokens += self.count(content);
+        }
+        if let Some(name) = &message.name {
+            tokens += self.count(name);
+        }
+        if let Some(call) = &message.function_call {
+            tokens += self.count(&call.name) + self.count(&call.arguments);
+        }
+
+        tokens
+    }
+
+    pub fn messages(&self, messages: &[ChatCompletionRequestMessage]) -> usize {
+        messages.iter().map(|m| self.message(m)).sum::<usize>() + REPLY_PRIMING
+    }
+
+    /// The text cut down to at most `limit` tokens.
+    pub fn truncate(&self, text: &str, limit: usize) -> String {
+        let marker = self.count(TRUNCATED);
+        let bpe = self.bpe.lock();
+        let tokens = bpe.encode_with_special_tokens(text);
+
+        if tokens.len() <= limit {
+            return text.to_string();
+        }
+
+        let keep = limit.saturating_sub(marker);
+        let text = bpe.decode(tokens[..keep].to_vec()).unwrap_or_default();
+
+        text + TRUNCATED
+    }
+}
+
+/// Where the tokens of the last request went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    pub window: usize,
+    pub schema: usize,
+    pub history: usize,
+    pub results: usize,
+    /// Older messages left out and summarised.
+    pub dropped: usize,
+    /// Tool results cut down to fit.
+    pub truncated: usize,
+}
+
+impl Usage {
+    pub fn total(&self) -> usize {
+        self.schema + self.history
+    }
+}
+
+/// Size of the context window for a model, unless configured.
+pub fn window(model: &str, budget: &Budget) -> usize {
+    budget
+        .context_window
+        .unwrap_or_else(|| tiktoken_rs::model::get_context_size(model))
+}
+
+/// Tokens the prompt may use once the reply is accounted for.
+pub fn prompt_budget(model: &str, budget: &Budget) -> usize {
+    window(model, budget).saturating_sub(budget.response as usize)
+}
+
+/// The messages to send so the request fits the window. Tool results over
+/// their share are cut down, keeping whole rows where a result set is
+/// known, and the oldest turns are replaced by a short summary until the
+/// history fits. The first message is taken to be the system message.
+pub fn fit(
+    messages: &[ChatCompletionRequestMessage],
+    results: &BTreeMap<usize, ResultSet>,
+    counter: &Counter,
+    model: &str,
+    budget: &Budget,
+) -> (Vec<ChatCompletionRequestMessage>, Usage) {
+    let prompt = prompt_budget(model, budget);
+    let result_limit = (prompt as f32 * budget.result) as usize;
+    let mut usage = Usage {
+        window: window(model, budget),
+        ..Usage::default()
+    };
+
+    let mut messages: Vec<ChatCompletionRequestMessage> = messages
+        .iter()
+        .enumerate()
+        .map(|(i, message)| {
+            if message.role != Role::Function || counter.message(message) <= result_limit {
+                return message.clone();
+            }
+
+            usage.truncated += 1;
+            let (data, notes) = split_notes(message.content.as_deref().unwrap_or_default());
+            let limit = result_limit
+                .saturating_sub(counter.message(&without_content(message)))
+                .saturating_sub(counter.count(notes));
+
+            let content = match results.get(&i) {
+                Some(result) => fit_rows(result, counter, limit),
+                None => counter.truncate(data, limit),
+            };
+
+            ChatCompletionRequestMessage {
+                content: Some(content + notes),
+                ..message.clone()
+            }
+        })
+        .collect();
+
+    let system = match messages.first() {
+        Some(message) if message.role == Role::System => Some(messages.remove(0)),
+        _ => None,
+    };
+
+    usage.schema = system
+        .as_ref()
+        .map(|m| counter.message(m))
+        .unwrap_or_default()
+        + REPLY_PRIMING;
+
+    let sizes: Vec<usize> = messages.iter().map(|m| counter.message(m)).collect();
+    let available = prompt.saturating_sub(usage.schema);
+
+    // A summary of the dropped turns takes up to an eighth of the history.
+    let reserve = if sizes.iter().sum::<usize>() > available {
+        available / 8
+    } else {
+        0
+    };
+
+    // Keep the newest messages that fit, and always the last one.
+    let mut kept = 0;
+    let mut used = 0;
+    for size in sizes.iter().rev() {
+        if kept > 0 && used + size > available - reserve {
+            break;
+        }
+        used += size;
+        kept += 1;
+    }
+
+    // Start the kept history at a question, so no result or answer is left
+    // without what it answers.
+    let mut first = messages.len() - kept;
+    while first + 1 < messages.len() && messages[first].role != Role::User {
+        first += 1;
+    }
+
+    let mut history = vec![];
+
+    if first > 0 {
+        let mut summary = ChatCompletionRequestMessage {
+            role: Role::System,
+            content: None,
+            name: None,
+            function_call: None,
+        };
+        let limit = reserve.saturating_sub(counter.message(&summary));
+
+        summary.content = Some(counter.truncate(&summarise(&messages[..first]), limit));
+        history.push(summary);
+        usage.dropped = first;
+    }
+
+    history.extend(messages.drain(first..));
+
+    for message in &history {
+        let tokens = counter.message(message);
+        usage.history += tokens;
+        if message.role == Role::Function {
+            usage.results += tokens;
+        }
+    }
+
+    (system.into_iter().chain(history).collect(), usage)
+}
+
+/// The prompt form of a result with as many leading rows as fit the limit.
+fn fit_rows(result: &ResultSet, counter: &Counter, limit: usize) -> String {
+    let (mut low, mut high) = (0, result.rows.len());
+
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if counter.count(&result.to_prompt_rows(middle)) <= limit {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+
+    let content = result.to_prompt_rows(low);
+
+    // Even the columns alone can be too wide.
+    counter.truncate(&content, limit)
+}
+
+/// A tool result split into its data and the notes after it, such as cost
+/// warnings and corrected names, which are kept when the data is cut.
+fn split_notes(content: &str) -> (&str, &str) {
+    let end = format!("</{}>", injection::DATA_TAG);
+
+    match content.rfind(&end) {
+        Some(i) => content.split_at(i + end.len()),
+        None => (content, ""),
+    }
+}
+
+/// A plain summary of older turns: what was asked and which queries ran.
+fn summarise(messages: &[ChatCompletionRequestMessage]) -> String {
+    let lines: Vec<String> = messages
+        .iter()
+        .filter_map(|message| match (&message.role, &message.function_call) {
+            (Role::User, _) => Some(format!("- User asked: {}", message.content.as_deref()?)),
+            (Role::Assistant, Some(call)) => {
+                let args: serde_json::Value = call.arguments.parse().ok()?;
+                Some(format!("- Ran query: {}", args["query"].as_str()?))
+            }
+            _ => None,
+        })
+        .collect();
+
+    format!(
+        "Earlier messages were left out to fit the context window. Summary:\n{}",
+        lines.join("\n")
+    )
+}
+
+fn without_content(message: &ChatCompletionRequestMessage) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage {
+        content: None,
+        ..message.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::FunctionCall;
+
+    use super::*;
+
+    fn message(role: Role, content: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage {
+            role,
+            content: Some(content.into()),
+            name: None,
+            function_call: None,
+        }
+    }
+
+    fn call(query: &str) -> ChatCompletionRequestMessage {
+        ChatCompletionRequestMessage {
+            role: Role::Assistant,
+            content: None,
+            name: None,
+            function_call: Some(FunctionCall {
+                name: "run_sql_query".into(),
+                arguments: serde_json::json!({ "query": query }).to_string(),
+            }),
+        }
+    }
+
+    fn budget(window: usize) -> Budget {
+        Budget {
+            context_window: Some(window),
+            response: 100,
+            ..Budget::default()
+        }
+    }
+
+    #[test]
+    fn notes_after_the_data_are_split_off() {
+        let content = "<data>\nrows\n</data>\nNote: costly";
+
+        assert_eq!(
+            split_notes(content),
+            ("<data>\nrows\n</data>", "\nNote: costly")
+        );
+        assert_eq!(split_notes("plain"), ("plain", ""));
+    }
+
+    #[test]
+    fn summarises_questions_and_queries() {
+        let messages = [
+            message(Role::User, "how many?"),
+            call("SELECT 1"),
+            message(Role::Function, "1"),
+            message(Role::Assistant, "One."),
+        ];
+
+        assert!(summarise(&messages).ends_with("- User asked: how many?\n- Ran query: SELECT 1"));
+    }
+
+    #[test]
+    fn truncates_to_the_limit() {
+        let counter = Counter::new("gpt-4");
+        let text = "word ".repeat(100);
+        let truncated = counter.truncate(&text, 20);
+
+        assert!(truncated.ends_with(TRUNCATED));
+        assert!(counter.count(&truncated) <= 20);
+        assert_eq!(counter.truncate("short", 20), "short");
+    }
+
+    #[test]
+    fn fits_by_summarising_the_oldest_turns() {
+        let counter = Counter::new("gpt-4");
+        let messages = [
+            message(Role::System, "You answer questions."),
+            message(Role::User, &"first question ".repeat(100)),
+            call("SELECT 1"),
+            message(Role::Function, &"1 ".repeat(40)),
+            message(Role::User, "second question"),
+        ];
+        let (fitted, usage) = fit(&messages, &BTreeMap::new(), &counter, "gpt-4", &budget(300));
+
+        assert_eq!(fitted.len(), 3);
+        assert_eq!(fitted[0].content, messages[0].content);
+        assert_eq!(fitted[1].role, Role::System);
+        assert!(fitted[1]
+            .content
+            .as_deref()
+            .unwrap()
+            .starts_with("Earlier messages were left out"));
+        assert_eq!(fitted[2].content, messages[4].content);
+        assert_eq!(usage.dropped, 3);
+        assert!(usage.schema + usage.history <= 200);
+    }
+
+    #[test]
+    fn cuts_results_over_their_share() {
+        let counter = Counter::new("gpt-4");
+        let messages = [
+            message(Role::System, "You answer questions."),
+            message(Role::User, "question"),
+            message(
+                Role::Function,
+                &format!("<data>\n{}</data>\nNote: kept", "1 ".repeat(500)),
+            ),
+        ];
+        let (fitted, usage) = fit(
+            &messages,
+            &BTreeMap::new(),
+            &counter,
+            "gpt-4",
+            &budget(1000),
+        );
+
+        assert_eq!(usage.truncated, 1);
+        let content = fitted[2].content.as_deref().unwrap();
+        assert!(content.ends_with("</data>\nNote: kept") || content.ends_with("Note: kept"));
+        assert!(content.contains(TRUNCATED));
+        assert!(counter.message(&fitted[2]) <= 225);
+    }
+}
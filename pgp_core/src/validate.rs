@@ -0,0 +1,673 @@
+use std::collections::BTreeSet;
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    AssignmentTarget, Expr, Ident, ObjectName, Query, SelectItem, SetExpr, Statement, TableAlias,
+    TableFactor, VisitMut, VisitorMut,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+
+use crate::access::{is_system, names, normalize};
+use crate::schema::{quote_ident, Catalog, Table};
+
+/// Names offered when a name is unknown.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// SQL that passed validation, with the names that were corrected in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validated {
+    pub sql: String,
+    /// Each correction as `written → corrected`.
+    pub fixes: Vec<String>,
+}
+
+/// Checks the tables and columns of `sql` against the catalog. A name that
+/// is wrong only in case or quoting, such as `lastActive` for the column
+/// `"lastActive"`, is corrected when it matches a single name; other
+/// unknown names make it fail, with the nearest names as suggestions.
+///
+/// Only queries, `INSERT`, `UPDATE` and `DELETE` are checked. Columns are
+/// only reported unknown when every source of the statement is a table of
+/// the catalog, so CTEs, subqueries and functions in `FROM` never lead to
+/// false errors. SQL that does not parse is left to the database.
+pub fn validate(sql: &str, catalog: &Catalog) -> Result<Validated, String> {
+    let unchanged = Validated {
+        sql: sql.to_string(),
+        fixes: vec![],
+    };
+
+    let Ok(mut statements) = Parser::parse_sql(&PostgreSqlDialect {}, sql) else {
+        return Ok(unchanged);
+    };
+
+    if !statements.iter().all(is_checked) {
+        return Ok(unchanged);
+    }
+
+    let mut fixes = vec![];
+    let mut problems = vec![];
+
+    for statement in &mut statements {
+        let mut sources = Sources::new(catalog);
+        let _ = VisitMut::visit(statement, &mut sources);
+
+        let mut columns = Columns {
+            sources: &sources,
+            fixes: vec![],
+            problems: vec![],
+        };
+        let _ = VisitMut::visit(statement, &mut columns);
+
+        for fix in sources.fixes.iter().cloned().chain(columns.fixes) {
+            if !fixes.contains(&fix) {
+                fixes.push(fix);
+            }
+        }
+        for problem in sources.problems.iter().cloned().chain(columns.problems) {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(problems.join("; "));
+    }
+
+    if fixes.is_empty() {
+        return Ok(unchanged);
+    }
+
+    Ok(Validated {
+        sql: statements
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(";\n"),
+        fixes,
+    })
+}
+
+/// Validates the statements of a script in order. Statements after one that
+/// may change the schema are left unchecked, since the catalog no longer
+/// describes what they run against.
+pub fn validate_script(statements: &[String], catalog: &Catalog) -> Vec<Result<Validated, String>> {
+    let mut changed = false;
+
+    statements
+        .iter()
+        .map(|statement| {
+            changed = changed
+                || Parser::parse_sql(&PostgreSqlDialect {}, statement)
+                    .map_or(true, |s| !s.iter().all(is_checked));
+
+            if changed {
+                Ok(Validated {
+                    sql: statement.clone(),
+                    fixes: vec![],
+                })
+            } else {
+                validate(statement, catalog)
+            }
+        })
+        .collect()
+}
+
+fn is_checked(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Query(_)
+            | Statement::Insert(_)
+            | Statement::Update { .. }
+            | Statement::Delete(_)
+    )
+}
+
+/// A table the statement reads or writes, under the name columns are
+/// qualified with.
+struct Source<'a> {
+    reference: String,
+    aliased: bool,
+    table: &'a Table,
+}
+
+/// Checks and corrects the relations of a statement and collects what
+/// its columns may refer to.
+struct Sources<'a> {
+    catalog: &'a Catalog,
+    ctes: BTreeSet<String>,
+    sources: Vec<Source<'a>>,
+    /// Output, CTE and table aliases, which are not columns.
+    aliases: BTreeSet<String>,
+    /// Whether the statement reads from something other than catalog
+    /// tables, whose columns are unknown.
+    opaque: bool,
+    fixes: Vec<String>,
+    problems: Vec<String>,
+}
+
+impl<'a> Sources<'a> {
+    fn new(catalog: &'a Catalog) -> Self {
+        Self {
+            catalog,
+            ctes: BTreeSet::new(),
+            sources: vec![],
+            aliases: BTreeSet::new(),
+            opaque: false,
+            fixes: vec![],
+            problems: vec![],
+        }
+    }
+
+    /// The catalog table a relation names, or none for CTEs, system tables
+    /// and unknown tables, which are reported.
+    fn table(&mut self, name: &mut ObjectName) -> Option<&'a Table> {
+        let catalog = self.catalog;
+
+        let (schema, table) = match name.0.as_mut_slice() {
+            [table] if self.ctes.contains(&normalize(table)) => return None,
+            [table] => (None, table),
+            [schema, table] => (Some(schema), table),
+            _ => return None,
+        };
+
+        let schema = match schema {
+            Some(schema) if is_system(&normalize(schema)) => return None,
+            Some(schema) => {
+                let schemas: Vec<&str> = catalog.schemas.iter().map(|s| s.name.as_str()).collect();
+                Some(self.correct(schema, &schemas, "schema")?)
+            }
+            None => None,
+        };
+
+        let tables: Vec<&str> = catalog
+            .tables()
+            .filter(|t| schema.is_none_or(|s| t.schema == s))
+            .map(|t| t.name.as_str())
+            .collect();
+
+        // Unqualified names also reach the system catalogs.
+        let name = normalize(table);
+        if schema.is_none() && name.starts_with("pg_") && !tables.contains(&name.as_str()) {
+            return None;
+        }
+
+        let name = self.correct(table, &tables, "table")?;
+
+        match schema {
+            Some(schema) => catalog.table(schema, name),
+            None => catalog.tables().find(|t| t.name == name),
+        }
+    }
+
+    /// The name among `candidates` an identifier stands for, correcting the
+    /// identifier when only its case or quoting is wrong. Unknown names are
+    /// reported.
+    fn correct<'n>(
+        &mut self,
+        ident: &mut Ident,
+        candidates: &[&'n str],
+        kind: &str,
+    ) -> Option<&'n str> {
+        match lookup(ident, candidates) {
+            Lookup::Found(name) => Some(name),
+            Lookup::Corrected(name) => {
+                self.fixes.push(correction(ident, name));
+                *ident = quoted(name);
+                Some(name)
+            }
+            Lookup::Unknown => {
+                self.problems.push(format!(
+                    "{} {} does not exist{}",
+                    kind,
+                    ident,
+                    suggestions(ident, candidates)
+                ));
+                None
+            }
+        }
+    }
+
+    fn add(&mut self, name: &mut ObjectName, alias: Option<&Ident>) {
+        if let Some(alias) = alias {
+            self.aliases.insert(normalize(alias));
+        }
+
+        let Some(table) = self.table(name) else {
+            self.opaque = true;
+            return;
+        };
+
+        self.aliases.insert(table.name.clone());
+        self.sources.push(Source {
+            reference: alias.map_or_else(|| table.name.clone(), normalize),
+            aliased: alias.is_some(),
+            table,
+        });
+    }
+
+    /// Aliases of the select list, which `ORDER BY` and the like may use.
+    fn output_aliases(&mut self, body: &SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                for item in &select.projection {
+                    if let SelectItem::ExprWithAlias { alias, .. } = item {
+                        self.aliases.insert(normalize(alias));
+                    }
+                }
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.output_aliases(left);
+                self.output_aliases(right);
+            }
+            _ => {}
+        }
+    }
+
+    /// The source a qualifier such as `u` in `u.name` refers to.
+    fn source(&self, qualifier: &[Ident]) -> Option<&Source<'a>> {
+        match qualifier {
+            [name] => {
+                self.sources
+                    .iter()
+                    .find(|s| s.reference == normalize(name))
+                    .or_else(|| {
+                        let mut unaliased = self.sources.iter().filter(|s| {
+                            !s.aliased && s.reference.eq_ignore_ascii_case(&name.value)
+                        });
+                        unaliased.next().filter(|_| unaliased.next().is_none())
+                    })
+            }
+            [schema, name] => self
+                .sources
+                .iter()
+                .find(|s| s.table.schema == normalize(schema) && s.table.name == normalize(name)),
+            _ => None,
+        }
+    }
+}
+
+impl VisitorMut for Sources<'_> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.ctes.insert(normalize(&cte.alias.name));
+                self.aliases.insert(normalize(&cte.alias.name));
+                self.aliases
+                    .extend(cte.alias.columns.iter().map(|c| normalize(&c.name)));
+            }
+        }
+
+        self.output_aliases(&query.body);
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<()> {
+        match factor {
+            TableFactor::Table {
+                name,
+                alias,
+                args: None,
+                ..
+            } => {
+                if let Some(alias) = alias {
+                    self.aliases
+                        .extend(alias.columns.iter().map(|c| normalize(&c.name)));
+                }
+                self.add(name, alias.as_ref().map(|a| &a.name));
+            }
+            TableFactor::NestedJoin { alias: None, .. } => {}
+            factor => {
+                self.opaque = true;
+                if let Some(alias) = alias(factor) {
+                    self.aliases.insert(normalize(&alias.name));
+                    self.aliases
+                        .extend(alias.columns.iter().map(|c| normalize(&c.name)));
+                }
+            }
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &mut Statement) -> ControlFlow<()> {
+        if let Statement::Insert(insert) = statement {
+            let alias = insert.table_alias.clone();
+            self.add(&mut insert.table_name, alias.as_ref());
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+/// Checks and corrects the columns of a statement once its sources are
+/// known.
+struct Columns<'s, 'a> {
+    sources: &'s Sources<'a>,
+    fixes: Vec<String>,
+    problems: Vec<String>,
+}
+
+impl<'a> Columns<'_, 'a> {
+    /// Corrects a column of one table, or reports it.
+    fn qualified(&mut self, column: &mut Ident, table: &Table) {
+        let columns = columns(table);
+
+        match lookup(column, &columns) {
+            Lookup::Found(_) => {}
+            Lookup::Corrected(name) => {
+                self.fixes.push(correction(column, name));
+                *column = quoted(name);
+            }
+            Lookup::Unknown => self.problems.push(format!(
+                "column {} does not exist in {}{}",
+                column,
+                table.qualified_name(),
+                suggestions(column, &visible(table))
+            )),
+        }
+    }
+
+    /// Corrects a column of any source, or reports it when every source is
+    /// known.
+    fn unqualified(&mut self, column: &mut Ident) {
+        let sources = self.sources;
+
+        // `DEFAULT` in `VALUES` parses as an identifier.
+        if sources.aliases.contains(&normalize(column)) || is_default(column) {
+            return;
+        }
+
+        let all: Vec<&str> = sources
+            .sources
+            .iter()
+            .flat_map(|s| columns(s.table))
+            .collect();
+
+        match lookup(column, &all) {
+            Lookup::Found(_) => {}
+            Lookup::Corrected(name) => {
+                self.fixes.push(correction(column, name));
+                *column = quoted(name);
+            }
+            Lookup::Unknown if sources.opaque || sources.sources.is_empty() => {}
+            Lookup::Unknown => {
+                let visible: Vec<&str> = sources
+                    .sources
+                    .iter()
+                    .flat_map(|s| visible(s.table))
+                    .collect();
+                let tables: Vec<String> = sources
+                    .sources
+                    .iter()
+                    .map(|s| s.table.qualified_name())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect();
+                self.problems.push(format!(
+                    "column {} does not exist in {}{}",
+                    column,
+                    tables.join(" or "),
+                    suggestions(column, &visible)
+                ));
+            }
+        }
+    }
+
+    /// The table a statement changes, named by its already corrected name.
+    fn target(&self, name: &ObjectName) -> Option<&'a Table> {
+        let catalog = self.sources.catalog;
+        match names(name).as_slice() {
+            [table] => catalog.tables().find(|t| &t.name == table),
+            [.., schema, table] => catalog.table(schema, table),
+            [] => None,
+        }
+    }
+}
+
+impl VisitorMut for Columns<'_, '_> {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(column) => self.unqualified(column),
+            Expr::CompoundIdentifier(idents) if idents.len() <= 3 => {
+                let Some((column, qualifier)) = idents.split_last_mut() else {
+                    return ControlFlow::Continue(());
+                };
+                let Some(source) = self.sources.source(qualifier) else {
+                    return ControlFlow::Continue(());
+                };
+                let table = source.table;
+
+                if let [name] = qualifier {
+                    if !source.aliased && normalize(name) != table.name {
+                        self.fixes.push(correction(name, &table.name));
+                        *name = quoted(&table.name);
+                    }
+                }
+                self.qualified(column, table);
+            }
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &mut Statement) -> ControlFlow<()> {
+        match statement {
+            Statement::Insert(insert) => {
+                if let Some(table) = self.target(&insert.table_name) {
+                    for column in &mut insert.columns {
+                        self.qualified(column, table);
+                    }
+                }
+            }
+            Statement::Update {
+                table, assignments, ..
+            } => {
+                let TableFactor::Table { name, .. } = &table.relation else {
+                    return ControlFlow::Continue(());
+                };
+                let Some(table) = self.target(name) else {
+                    return ControlFlow::Continue(());
+                };
+                for assignment in assignments {
+                    let targets = match &mut assignment.target {
+                        AssignmentTarget::ColumnName(name) => vec![name],
+                        AssignmentTarget::Tuple(names) => names.iter_mut().collect(),
+                    };
+                    for target in targets {
+                        if let Some(column) = target.0.last_mut() {
+                            self.qualified(column, table);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+enum Lookup<'n> {
+    Found(&'n str),
+    /// The only name equal to the identifier but for case.
+    Corrected(&'n str),
+    Unknown,
+}
+
+fn lookup<'n>(ident: &Ident, candidates: &[&'n str]) -> Lookup<'n> {
+    let name = normalize(ident);
+    let mut matches = BTreeSet::new();
+
+    for &candidate in candidates {
+        if candidate == name {
+            return Lookup::Found(candidate);
+        }
+        if candidate.eq_ignore_ascii_case(&ident.value) {
+            matches.insert(candidate);
+        }
+    }
+
+    match matches.len() {
+        1 => Lookup::Corrected(matches.pop_first().unwrap()),
+        _ => Lookup::Unknown,
+    }
+}
+
+/// Columns of a table, hidden ones included so the access rules rather
+/// than validation reject them.
+fn columns(table: &Table) -> Vec<&str> {
+    visible(table)
+        .into_iter()
+        .chain(table.hidden.iter().map(String::as_str))
+        .collect()
+}
+
+/// Columns of a table that may be suggested.
+fn visible(table: &Table) -> Vec<&str> {
+    table.columns.iter().map(|c| c.name.as_str()).collect()
+}
+
+/// The nearest candidates to an unknown name, as the end of its error.
+fn suggestions(ident: &Ident, candidates: &[&str]) -> String {
+    let name = ident.value.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut nearest: Vec<(usize, &str)> = candidates
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|candidate| (distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    nearest.sort();
+
+    if nearest.is_empty() {
+        return String::new();
+    }
+
+    let names: Vec<String> = nearest
+        .iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| quote_ident(name))
+        .collect();
+
+    format!(" (did you mean {}?)", names.join(", "))
+}
+
+/// Edit distance between two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn correction(ident: &Ident, name: &str) -> String {
+    format!("{} → {}", ident, quote_ident(name))
+}
+
+/// An identifier that reads back as `name`.
+fn quoted(name: &str) -> Ident {
+    if quote_ident(name) == name {
+        Ident::new(name)
+    } else {
+        Ident::with_quote('"', name)
+    }
+}
+
+fn is_default(ident: &Ident) -> bool {
+    ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("default")
+}
+
+fn alias(factor: &TableFactor) -> Option<&TableAlias> {
+    match factor {
+        TableFactor::Table { alias, .. }
+        | TableFactor::Derived { alias, .. }
+        | TableFactor::TableFunction { alias, .. }
+        | TableFactor::Function { alias, .. }
+        | TableFactor::UNNEST { alias, .. }
+        | TableFactor::NestedJoin { alias, .. } => alias.as_ref(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::catalog;
+
+    fn users() -> Catalog {
+        let mut catalog = catalog(&[
+            ("public.users", &["id", "name", "lastActive"]),
+            ("public.orders", &["id", "user_id", "total"]),
+        ]);
+        catalog.schemas[0].tables[0].hidden = vec!["ssn".to_string()];
+        catalog
+    }
+
+    #[test]
+    fn corrects_case_and_quoting() {
+        let validated = validate("SELECT lastactive FROM Users", &users()).unwrap();
+
+        assert_eq!(validated.sql, "SELECT \"lastActive\" FROM Users");
+        assert_eq!(validated.fixes, ["lastactive → \"lastActive\""]);
+    }
+
+    #[test]
+    fn reports_unknown_columns_of_aliases() {
+        let error = validate(
+            "SELECT o.totl FROM orders o JOIN users u ON u.id = o.user_id",
+            &users(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            "column totl does not exist in public.orders (did you mean total?)"
+        );
+    }
+
+    #[test]
+    fn leaves_hidden_columns_out_of_suggestions() {
+        let error = validate("SELECT u.ssm FROM users u", &users()).unwrap_err();
+        assert!(!error.contains("ssn"), "{}", error);
+
+        let error = validate("SELECT ssm FROM users", &users()).unwrap_err();
+        assert!(!error.contains("ssn"), "{}", error);
+    }
+
+    #[test]
+    fn leaves_hidden_columns_to_the_access_rules() {
+        assert!(validate("SELECT ssn FROM users", &users()).is_ok());
+    }
+
+    #[test]
+    fn ignores_output_aliases_and_opaque_sources() {
+        let catalog = users();
+
+        assert!(validate("SELECT id AS n FROM users ORDER BY n", &catalog).is_ok());
+        assert!(validate(
+            "WITH t AS (SELECT 1 AS anything) SELECT anything FROM t",
+            &catalog
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,243 @@
+use std::cmp::Reverse;
+
+use async_openai::types::ChatChoice;
+use sqlparser::ast::{SetExpr, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+
+use crate::results::{ResultSet, Value};
+
+/// A query the model wrote for a question and what running it gave.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub query: String,
+    pub outcome: Result<ResultSet, String>,
+}
+
+/// Candidate queries that returned the same result.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub queries: Vec<String>,
+    /// The result of the first query of the group.
+    pub result: ResultSet,
+}
+
+/// Candidate queries grouped by their result, largest group first.
+#[derive(Debug, Clone)]
+pub struct Vote {
+    pub groups: Vec<Group>,
+    /// Candidates that were rejected or failed to run.
+    pub failed: Vec<Candidate>,
+}
+
+/// The queries of the choices that call `run_sql_query` with a single
+/// query, in the order of the choices. Anything else would change data or
+/// fail read-only, so it is not voted on.
+pub fn candidates(choices: &[ChatChoice]) -> Vec<String> {
+    choices
+        .iter()
+        .filter_map(|choice| choice.message.function_call.as_ref())
+        .filter(|call| call.name == "run_sql_query")
+        .filter_map(|call| {
+            let arguments: serde_json::Value = call.arguments.parse().ok()?;
+            arguments["query"].as_str().map(str::to_string)
+        })
+        .filter(|query| reads(query))
+        .collect()
+}
+
+/// Whether `sql` is a single query that only reads.
+fn reads(sql: &str) -> bool {
+    match Parser::parse_sql(&PostgreSqlDialect {}, sql).as_deref() {
+        Ok([Statement::Query(query)]) => {
+            !matches!(*query.body, SetExpr::Insert(_) | SetExpr::Update(_))
+        }
+        _ => false,
+    }
+}
+
+impl Vote {
+    /// Groups candidates by result. Results are the same when they have the
+    /// same rows in any order, whatever their columns are named and however
+    /// their numbers are typed. Ties go to the group of the earlier choice.
+    pub fn tally(candidates: Vec<Candidate>) -> Self {
+        let mut groups: Vec<(Vec<Vec<String>>, Group)> = vec![];
+        let mut failed = vec![];
+
+        for candidate in candidates {
+            let result = match &candidate.outcome {
+                Ok(result) => result,
+                Err(_) => {
+                    failed.push(candidate);
+                    continue;
+                }
+            };
+
+            let key = key(result);
+            match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, group)) => group.queries.push(candidate.query),
+                None => groups.push((
+                    key,
+                    Group {
+                        queries: vec![candidate.query],
+                        result: result.clone(),
+                    },
+                )),
+            }
+        }
+
+        // A stable sort keeps the order of the choices among equal groups.
+        groups.sort_by_key(|(_, group)| Reverse(group.queries.len()));
+
+        Self {
+            groups: groups.into_iter().map(|(_, group)| group).collect(),
+            failed,
+        }
+    }
+
+    /// The result most candidates agree on.
+    pub fn majority(&self) -> Option<&Group> {
+        self.groups.first()
+    }
+
+    /// The groups that disagree with the majority.
+    pub fn alternatives(&self) -> &[Group] {
+        self.groups.get(1..).unwrap_or_default()
+    }
+
+    pub fn total(&self) -> usize {
+        self.groups.iter().map(|g| g.queries.len()).sum::<usize>() + self.failed.len()
+    }
+
+    /// How the candidates agreed, as told to the model and the user.
+    pub fn summary(&self) -> String {
+        let agreeing = self.majority().map_or(0, |g| g.queries.len());
+        let mut summary = format!(
+            "{} of {} candidate queries returned this result",
+            agreeing,
+            self.total()
+        );
+
+        let others: usize = self.alternatives().iter().map(|g| g.queries.len()).sum();
+        match self.alternatives().len() {
+            0 => {}
+            1 => summary += &format!(", {} returned another result", others),
+            n => summary += &format!(", {} returned {} other results", others, n),
+        }
+        if !self.failed.is_empty() {
+            summary += &format!(", {} failed", self.failed.len());
+        }
+
+        summary
+    }
+}
+
+/// The rows of a result, sorted, with values in a form that does not depend
+/// on their type.
+fn key(result: &ResultSet) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(canonical).collect())
+        .collect();
+    rows.sort();
+    rows
+}
+
+/// Numbers to nine significant digits, so `12.5`, `12.50` and an average
+/// computed another way compare equal; other values by their text.
+fn canonical(value: &Value) -> String {
+    match value.as_f64() {
+        Some(number) => format!("{:.8e}", number),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn choice(name: &str, query: &str) -> ChatChoice {
+        serde_json::from_value(json!({
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": null,
+                "function_call": {
+                    "name": name,
+                    "arguments": json!({ "query": query }).to_string()
+                }
+            },
+            "finish_reason": "function_call"
+        }))
+        .unwrap()
+    }
+
+    fn candidate(query: &str, rows: Vec<Vec<Value>>) -> Candidate {
+        Candidate {
+            query: query.into(),
+            outcome: Ok(ResultSet {
+                rows,
+                ..ResultSet::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn only_single_reading_queries_are_candidates() {
+        let choices = [
+            choice("run_sql_query", "SELECT 1"),
+            choice("run_sql_query", "DELETE FROM orders"),
+            choice("run_sql_query", "SELECT 1; SELECT 2"),
+            choice(
+                "run_sql_query",
+                "WITH x AS (SELECT 1) INSERT INTO t SELECT * FROM x",
+            ),
+            choice("get_column_stats", "SELECT 2"),
+            choice("run_sql_query", "SELECT 3"),
+        ];
+
+        assert_eq!(candidates(&choices), ["SELECT 1", "SELECT 3"]);
+    }
+
+    #[test]
+    fn groups_equal_results_whatever_their_order_and_types() {
+        let vote = Vote::tally(vec![
+            candidate("a", vec![vec![Value::Int(1)], vec![Value::Int(2)]]),
+            candidate("b", vec![vec![Value::Int(3)]]),
+            candidate(
+                "c",
+                vec![vec![Value::Numeric("2.00".into())], vec![Value::Float(1.0)]],
+            ),
+            Candidate {
+                query: "d".into(),
+                outcome: Err("syntax error".into()),
+            },
+        ]);
+
+        assert_eq!(vote.majority().unwrap().queries, ["a", "c"]);
+        assert_eq!(vote.alternatives().len(), 1);
+        assert_eq!(vote.total(), 4);
+        assert_eq!(
+            vote.summary(),
+            "2 of 4 candidate queries returned this result, 1 returned another result, 1 failed"
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_earlier_choice() {
+        let vote = Vote::tally(vec![
+            candidate("a", vec![vec![Value::Int(1)]]),
+            candidate("b", vec![vec![Value::Int(2)]]),
+            candidate("c", vec![vec![Value::Int(3)]]),
+        ]);
+
+        assert_eq!(vote.majority().unwrap().queries, ["a"]);
+        assert_eq!(
+            vote.summary(),
+            "1 of 3 candidate queries returned this result, 2 returned 2 other results"
+        );
+    }
+}
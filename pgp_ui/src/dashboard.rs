@@ -1,13 +1,15 @@
 mod sidebar;
 mod viewport;
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use iced::theme::palette::Danger;
 use iced::widget::{self, button, column, container, row, text, Column, PaneGrid};
-use iced::{Alignment, Application, Color, Command, Element, Length, Settings, Theme};
+use iced::{Alignment, Application, Color, Command, Element, Length, Settings, Subscription, Theme};
 use pgp_core::config::Config;
 use pgp_core::connection::{self, Connection};
 use pgp_core::errors::Error;
+use pgp_core::snapshot::Refresh;
 use pgp_core::Session;
 use viewport::Viewport;
 
@@ -17,6 +19,7 @@ pub struct Dashboard {
     viewport: Viewport,
     connections_state: BTreeMap<u8, bool>,
     config: Config,
+    refreshing: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +27,9 @@ pub enum Message {
     Connect(u8),
     Disconnect(u8),
     Connected(Result<Session, Error>),
+    Expand(sidebar::Node),
+    RefreshSchema,
+    SchemaRefreshed(Result<Option<Refresh>, Error>),
     Viewppoort(viewport::Message),
 }
 
@@ -35,6 +41,7 @@ impl Dashboard {
             connections_state: config.default_state(),
             // session: None,
             config,
+            refreshing: false,
         }
     }
 
@@ -63,21 +70,63 @@ impl Dashboard {
             }
             Message::Connected(Ok(session)) => {
                 self.connections_state.insert(session.connection_id, true);
+                let stale = session.stale;
                 self.viewport = Viewport::new(session);
 
+                if stale {
+                    return self.update(Message::RefreshSchema);
+                }
                 Command::none()
             }
             Message::Connected(Err(error)) => {
                 self.viewport = Viewport::Errored { error };
                 Command::none()
             }
+            Message::Expand(node) => {
+                self.sidebar.expand(node);
+                Command::none()
+            }
+            Message::RefreshSchema => match self.viewport.session() {
+                Some(session) if !self.refreshing => {
+                    self.refreshing = true;
+                    Command::perform(
+                        pgp_core::refresh_schema(self.config.clone(), session.clone()),
+                        Message::SchemaRefreshed,
+                    )
+                }
+                _ => Command::none(),
+            },
+            Message::SchemaRefreshed(result) => {
+                self.refreshing = false;
+                match result {
+                    Ok(refresh) => self.viewport.schema_refreshed(refresh),
+                    Err(error) => println!("schema refresh error: {:?}", error),
+                }
+                Command::none()
+            }
             Message::Viewppoort(message) => self.viewport.update(message).map(Message::Viewppoort),
         }
     }
 
+    pub fn subscription(&self) -> Subscription<Message> {
+        let viewport = self.viewport.subscription().map(Message::Viewppoort);
+
+        match self.viewport.session() {
+            Some(_) => Subscription::batch(vec![
+                viewport,
+                iced::time::every(Duration::from_secs(300)).map(|_| Message::RefreshSchema),
+            ]),
+            None => viewport,
+        }
+    }
+
     pub fn view(&self) -> Element<Message> {
         let height_margin = if cfg!(target_os = "macos") { 20 } else { 0 };
-        let sidebar = self.sidebar.view(&self.config, &self.connections_state);
+        let sidebar = self.sidebar.view(
+            &self.config,
+            &self.connections_state,
+            self.viewport.catalog(),
+        );
         let viewport = self.viewport.view(&self.config).map(Message::Viewppoort);
         let base = row![].push(sidebar).push(viewport);
 
@@ -1,26 +1,57 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use iced::widget::{self, button, column, container, row, scrollable, text, Column, PaneGrid};
+use iced::widget::{
+    self, button, column, container, horizontal_rule, horizontal_space, row, scrollable, text,
+    tooltip, Column, PaneGrid,
+};
 use iced::{theme, Alignment, Application, Color, Command, Element, Length, Settings, Theme};
 
+use super::viewport;
 use super::Message;
 use pgp_core::config::Config;
+use pgp_core::schema::{quote_ident, Catalog, Schema, Table, TableKind};
+
+const INDENT: f32 = 12.0;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+/// A collapsible entry of the schema tree.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Node {
+    Schema(String),
+    Table(String, String),
+    Functions(String),
+}
 
 #[derive(Debug)]
 pub struct Sidebar {
     hidden: bool,
+    expanded: BTreeSet<Node>,
 }
 
 impl Sidebar {
     pub fn new() -> Self {
-        Self { hidden: false }
+        Self {
+            hidden: false,
+            expanded: BTreeSet::new(),
+        }
     }
 
     pub fn toggle(&mut self) {
         self.hidden = !self.hidden;
     }
 
-    pub fn view(&self, config: &Config, connections_state: &BTreeMap<u8, bool>) -> Element<Message> {
+    pub fn expand(&mut self, node: Node) {
+        if !self.expanded.remove(&node) {
+            self.expanded.insert(node);
+        }
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        config: &Config,
+        connections_state: &BTreeMap<u8, bool>,
+        catalog: Option<&'a Catalog>,
+    ) -> Element<'a, Message> {
         let mut column = column![].spacing(1);
         let state: Vec<_> = connections_state.into_iter().collect();
 
@@ -41,6 +72,18 @@ impl Sidebar {
             column = column.push(button.width(Length::Fill));
         }
 
+        let max_width = match catalog {
+            Some(catalog) => {
+                column = column.push(horizontal_rule(12));
+                column = catalog
+                    .schemas
+                    .iter()
+                    .fold(column, |column, schema| self.schema(column, schema));
+                260
+            }
+            None => 150,
+        };
+
         container(
             scrollable(column).direction(scrollable::Direction::Vertical(
                 iced::widget::scrollable::Properties::default()
@@ -50,7 +93,7 @@ impl Sidebar {
         )
         .padding([8, 8, 6, 6])
         .center_x()
-        .max_width(150)
+        .max_width(max_width)
         .height(Length::Fill)
         .style(theme::Container::Box)
         .into()
@@ -61,4 +104,189 @@ impl Sidebar {
         //     sidebar.width(Length::Units(200)).into()
         // }
     }
+
+    fn schema<'a>(&self, column: Column<'a, Message>, schema: &'a Schema) -> Column<'a, Message> {
+        let node = Node::Schema(schema.name.clone());
+        let expanded = self.expanded.contains(&node);
+        let column = column.push(self.node(0, node, &schema.name, None));
+
+        if !expanded {
+            return column;
+        }
+
+        let column = schema
+            .tables
+            .iter()
+            .fold(column, |column, table| self.table(column, table));
+
+        if schema.functions.is_empty() {
+            return column;
+        }
+
+        let node = Node::Functions(schema.name.clone());
+        let expanded = self.expanded.contains(&node);
+        let count = schema.functions.len().to_string();
+        let column = column.push(self.node(1, node, "functions", Some(count)));
+
+        if !expanded {
+            return column;
+        }
+
+        schema.functions.iter().fold(column, |column, function| {
+            let name = format!(
+                "{}.{}",
+                quote_ident(&function.schema),
+                quote_ident(&function.name)
+            );
+            let label = button(text(function.signature()).size(13))
+                .style(theme::Button::Text)
+                .width(Length::Fill)
+                .on_press(insert(name));
+
+            let hint = match &function.comment {
+                Some(comment) => format!("returns {}\n{}", function.result, comment),
+                None => format!("returns {}", function.result),
+            };
+
+            let label = tooltip(label, hint, tooltip::Position::Right).style(theme::Container::Box);
+
+            column.push(indented(2, label))
+        })
+    }
+
+    fn table<'a>(&self, column: Column<'a, Message>, table: &'a Table) -> Column<'a, Message> {
+        let node = Node::Table(table.schema.clone(), table.name.clone());
+        let expanded = self.expanded.contains(&node);
+        let kind = match table.kind {
+            TableKind::Table => None,
+            kind => Some(kind.to_string()),
+        };
+
+        let column = column.push(self.node(1, node, &table.name, kind));
+
+        if !expanded {
+            return column;
+        }
+
+        let names = (table.schema.clone(), table.name.clone());
+        let action = |label, message: viewport::Message| {
+            button(text(label).size(12))
+                .padding([2, 6])
+                .style(theme::Button::Secondary)
+                .on_press(Message::Viewppoort(message))
+        };
+
+        let actions = row![
+            action("Preview", viewport::Message::Preview(names.clone())),
+            action("Describe", viewport::Message::Describe(names.clone())),
+            action("Ask", viewport::Message::Ask(names)),
+            action(
+                "Insert",
+                viewport::Message::InsertName(table.qualified_name())
+            ),
+        ]
+        .spacing(4);
+
+        let column = column.push(indented(2, actions));
+
+        let column = match &table.comment {
+            Some(comment) => column.push(indented(2, text(comment).size(12).style(MUTED))),
+            None => column,
+        };
+
+        table.columns.iter().fold(column, |column, c| {
+            let key = if table.is_key(&c.name) {
+                "PK"
+            } else if table.foreign_key(&c.name).is_some() {
+                "FK"
+            } else {
+                ""
+            };
+
+            let label = button(
+                row![
+                    text(&c.name).size(13),
+                    text(&c.type_name).size(11).style(MUTED),
+                    horizontal_space(Length::Fill),
+                    text(key).size(11).style(MUTED),
+                ]
+                .spacing(6)
+                .align_items(Alignment::Center),
+            )
+            .padding([2, 4])
+            .style(theme::Button::Text)
+            .width(Length::Fill)
+            .on_press(insert(quote_ident(&c.name)));
+
+            let mut hint = vec![];
+            if !c.nullable {
+                hint.push("not null".to_string());
+            }
+            if let Some(fk) = table.foreign_key(&c.name) {
+                hint.push(format!(
+                    "references {}.{} ({})",
+                    fk.schema,
+                    fk.table,
+                    fk.referenced.join(", ")
+                ));
+            }
+            if let Some(comment) = &c.comment {
+                hint.push(comment.clone());
+            }
+
+            let label: Element<Message> = if hint.is_empty() {
+                label.into()
+            } else {
+                tooltip(label, hint.join("\n"), tooltip::Position::Right)
+                    .style(theme::Container::Box)
+                    .into()
+            };
+
+            column.push(indented(2, label))
+        })
+    }
+
+    fn node<'a>(
+        &self,
+        depth: u16,
+        node: Node,
+        label: &str,
+        detail: Option<String>,
+    ) -> Element<'a, Message> {
+        let arrow = if self.expanded.contains(&node) {
+            "▾"
+        } else {
+            "▸"
+        };
+
+        let content = row![text(format!("{} {}", arrow, label)).size(14)]
+            .spacing(6)
+            .align_items(Alignment::Center);
+
+        let content = match detail {
+            Some(detail) => content.push(text(detail).size(11).style(MUTED)),
+            None => content,
+        };
+
+        indented(
+            depth,
+            button(content)
+                .padding([2, 4])
+                .style(theme::Button::Text)
+                .width(Length::Fill)
+                .on_press(Message::Expand(node)),
+        )
+    }
+}
+
+fn insert(name: String) -> Message {
+    Message::Viewppoort(viewport::Message::InsertName(name))
+}
+
+fn indented<'a>(depth: u16, content: impl Into<Element<'a, Message>>) -> Element<'a, Message> {
+    row![
+        horizontal_space(Length::Fixed(depth as f32 * INDENT)),
+        content.into()
+    ]
+    .into()
 }
@@ -1,3 +1,13 @@
+mod approval;
+mod chart;
+mod editor;
+mod grid;
+mod plan;
+mod sources;
+mod votes;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
 use std::vec;
 
 use iced::gradient::Linear;
@@ -5,11 +15,26 @@ use iced::widget::{
     self, button, column, container, horizontal_rule, row, scrollable, text, text_input,
     vertical_space, Column, Container, PaneGrid, Text,
 };
-use iced::{theme, Alignment, Application, Color, Command, Element, Length, Settings, Theme};
+use iced::futures::SinkExt;
+use iced::{
+    subscription, theme, Alignment, Application, Color, Command, Element, Length, Settings,
+    Subscription, Theme,
+};
 
 use super::Error;
 // use super::Message;
-use pgp_core::config::{self, Config};
+use pgp_core::config::{self, Config, SchemaFormat};
+use chart::Chart;
+use editor::Editor;
+use grid::Grid;
+use plan::Plan;
+use pgp_core::export::{self, Format};
+use pgp_core::injection;
+use pgp_core::plan::QueryPlan;
+use pgp_core::render;
+use pgp_core::retrieval::Reason;
+use pgp_core::schema::{self, Catalog};
+use pgp_core::snapshot::{self, Refresh};
 use pgp_core::Session;
 
 #[derive(Debug)]
@@ -25,14 +50,58 @@ pub enum Viewport {
     Ready {
         input: String,
         session: Session,
+        running: bool,
+        grids: BTreeMap<usize, Grid>,
+        charts: BTreeMap<usize, Chart>,
+        /// Plans of result queries, shown under their grids.
+        plans: BTreeMap<usize, Plan>,
+        /// Voted results whose alternatives are shown.
+        alternatives: BTreeSet<usize>,
+        editor: Editor,
+        streaming: Option<Streaming>,
+        notice: Option<String>,
+        /// Shows which tables the last question sent to the model.
+        context: bool,
+        /// Token cost of the whole schema in each format, once measured.
+        formats: Vec<(SchemaFormat, usize)>,
     },
 }
 
+/// A full export running through `COPY` in the background.
+#[derive(Debug, Clone)]
+pub struct Streaming {
+    query: String,
+    format: Format,
+    path: PathBuf,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     InputChanged(String),
     Query,
     QueryComplete(Result<Session, Error>),
+    Cancel,
+    Cancelled(Result<(), Error>),
+    Approve,
+    Reject,
+    Grid(usize, grid::Message),
+    Chart(usize, chart::Message),
+    Plan(usize, plan::Message),
+    Explained(usize, Result<QueryPlan, Error>),
+    Editor(editor::Message),
+    Preview((String, String)),
+    Describe((String, String)),
+    Ask((String, String)),
+    InsertName(String),
+    ToggleContext,
+    ToggleVote,
+    ToggleAlternatives(usize),
+    OpenSql(String),
+    MeasureFormats,
+    FormatsMeasured(Vec<(SchemaFormat, usize)>),
+    GridEvent(grid::Message),
+    StreamProgress(u64),
+    StreamFinished(Result<u64, Error>),
 }
 
 impl Viewport {
@@ -41,9 +110,83 @@ impl Viewport {
     }
 
     pub fn new(session: Session) -> Self {
+        let mut grids = BTreeMap::new();
+        let mut charts = BTreeMap::new();
+        sync_results(&mut grids, &mut charts, &session);
+
         Self::Ready {
             input: String::new(),
             session,
+            running: false,
+            grids,
+            charts,
+            plans: BTreeMap::new(),
+            alternatives: BTreeSet::new(),
+            editor: Editor::new(),
+            streaming: None,
+            notice: None,
+            context: false,
+            formats: vec![],
+        }
+    }
+
+    pub fn catalog(&self) -> Option<&Catalog> {
+        self.session().map(|session| &session.catalog)
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        match self {
+            Viewport::Ready { session, .. } => Some(session),
+            _ => None,
+        }
+    }
+
+    /// Takes in a catalog read again in the background and says what changed.
+    pub fn schema_refreshed(&mut self, refresh: Option<Refresh>) {
+        if let Viewport::Ready {
+            session, notice, ..
+        } = self
+        {
+            session.stale = false;
+
+            if let Some(refresh) = refresh {
+                *notice = Some(snapshot::describe(&refresh.changes));
+                session.catalog = refresh.catalog;
+                session.namespace = refresh.namespace;
+                session.fingerprint = refresh.fingerprint;
+            }
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        match self {
+            Viewport::Ready {
+                session,
+                grids,
+                editor,
+                streaming,
+                ..
+            } => {
+                let resize = if grids.values().any(Grid::is_resizing) {
+                    Grid::subscription().map(Message::GridEvent)
+                } else {
+                    Subscription::none()
+                };
+
+                let keys = if editor.is_focused() {
+                    Editor::subscription().map(Message::Editor)
+                } else {
+                    Subscription::none()
+                };
+
+                let stream = match streaming {
+                    Some(streaming) => stream(session.clone(), streaming.clone()),
+                    None => Subscription::none(),
+                };
+
+                Subscription::batch(vec![resize, keys, stream])
+            }
+            _ => Subscription::none(),
         }
     }
 
@@ -56,7 +199,14 @@ impl Viewport {
                 Command::none()
             }
             Message::Query => {
-                if let Viewport::Ready { input, session, .. } = self {
+                if let Viewport::Ready {
+                    input,
+                    session,
+                    running,
+                    ..
+                } = self
+                {
+                    *running = true;
                     return Command::perform(
                         pgp_core::exec(input.clone(), session.clone()),
                         Message::QueryComplete,
@@ -64,15 +214,373 @@ impl Viewport {
                 }
                 Command::none()
             }
-            Message::QueryComplete(Ok(session)) => {
-                *self = Viewport::new(session);
+            Message::QueryComplete(Ok(new_session)) => {
+                if let Viewport::Ready {
+                    input,
+                    session,
+                    running,
+                    grids,
+                    charts,
+                    ..
+                } = self
+                {
+                    // The catalog may have been refreshed while this ran.
+                    let mut new_session = new_session;
+                    new_session.catalog = std::mem::take(&mut session.catalog);
+                    new_session.fingerprint = std::mem::take(&mut session.fingerprint);
+                    new_session.stale = session.stale;
+
+                    sync_results(grids, charts, &new_session);
+                    *session = new_session;
+                    *running = false;
+                    input.clear();
+                } else {
+                    *self = Viewport::new(new_session);
+                }
+                Command::none()
+            }
+            Message::QueryComplete(Err(Error::Cancelled)) => {
+                if let Viewport::Ready { running, .. } = self {
+                    *running = false;
+                }
                 Command::none()
             }
             Message::QueryComplete(Err(error)) => {
                 *self = Viewport::Errored { error };
                 Command::none()
             }
+            Message::Approve | Message::Reject => {
+                if let Viewport::Ready {
+                    session, running, ..
+                } = self
+                {
+                    if *running {
+                        return Command::none();
+                    }
+                    *running = true;
+                    let session = session.clone();
+                    return Command::perform(
+                        async move {
+                            match message {
+                                Message::Approve => pgp_core::approve(session).await,
+                                _ => pgp_core::reject(session).await,
+                            }
+                        },
+                        Message::QueryComplete,
+                    );
+                }
+                Command::none()
+            }
+            Message::Cancel => {
+                if let Viewport::Ready { session, .. } = self {
+                    return Command::perform(
+                        pgp_core::cancel(session.clone()),
+                        Message::Cancelled,
+                    );
+                }
+                Command::none()
+            }
+            Message::Grid(index, grid::Message::Stream(format)) => {
+                if let Viewport::Ready {
+                    session,
+                    running,
+                    streaming,
+                    notice,
+                    ..
+                } = self
+                {
+                    if let (Some(result), false) = (session.results.get(&index), *running) {
+                        let path = export::default_path("result", format.extension());
+                        *notice = Some(format!("Exporting to {}...", path.display()));
+                        *streaming = Some(Streaming {
+                            query: result.query.clone(),
+                            format,
+                            path,
+                        });
+                        session.canceller.reset();
+                        *running = true;
+                    }
+                }
+                Command::none()
+            }
+            Message::StreamProgress(bytes) => {
+                if let Viewport::Ready {
+                    streaming: Some(streaming),
+                    notice,
+                    ..
+                } = self
+                {
+                    *notice = Some(format!(
+                        "Exporting to {}: {}",
+                        streaming.path.display(),
+                        human_bytes(bytes)
+                    ));
+                }
+                Command::none()
+            }
+            Message::StreamFinished(result) => {
+                if let Viewport::Ready {
+                    running,
+                    streaming,
+                    notice,
+                    ..
+                } = self
+                {
+                    if let Some(streaming) = streaming.take() {
+                        *notice = Some(match result {
+                            Ok(bytes) => format!(
+                                "Saved {} to {}",
+                                human_bytes(bytes),
+                                streaming.path.display()
+                            ),
+                            Err(Error::Cancelled) => "Export cancelled".to_string(),
+                            Err(error) => format!("Export failed: {}", error),
+                        });
+                    }
+                    *running = false;
+                }
+                Command::none()
+            }
+            Message::Grid(index, grid::Message::Explain) => {
+                if let Viewport::Ready {
+                    session, running, ..
+                } = self
+                {
+                    if let (Some(result), false) = (session.results.get(&index), *running) {
+                        session.canceller.reset();
+                        *running = true;
+                        return Command::perform(
+                            pgp_core::plan::explain(session.clone(), result.query.clone()),
+                            move |plan| Message::Explained(index, plan),
+                        );
+                    }
+                }
+                Command::none()
+            }
+            Message::Explained(index, result) => {
+                if let Viewport::Ready {
+                    running,
+                    plans,
+                    notice,
+                    ..
+                } = self
+                {
+                    match result {
+                        Ok(plan) => {
+                            plans.insert(index, Plan::new(plan));
+                        }
+                        Err(error) => *notice = Some(format!("Explain failed: {}", error)),
+                    }
+                    *running = false;
+                }
+                Command::none()
+            }
+            Message::Plan(index, plan::Message::Close) => {
+                if let Viewport::Ready { plans, .. } = self {
+                    plans.remove(&index);
+                }
+                Command::none()
+            }
+            Message::Plan(index, message) => {
+                if let Viewport::Ready {
+                    session,
+                    plans,
+                    running,
+                    ..
+                } = self
+                {
+                    let describe = matches!(message, plan::Message::Describe);
+                    if describe && *running {
+                        return Command::none();
+                    }
+                    if let plan::Message::Described(_) = message {
+                        *running = false;
+                    }
+
+                    if let Some(plan) = plans.get_mut(&index) {
+                        let command = plan
+                            .update(message)
+                            .map(move |message| Message::Plan(index, message));
+
+                        if describe {
+                            session.canceller.reset();
+                            *running = true;
+                            return Command::perform(
+                                pgp_core::plan::describe(session.clone(), plan.plan().clone()),
+                                move |described| {
+                                    Message::Plan(index, plan::Message::Described(described))
+                                },
+                            );
+                        }
+
+                        return command;
+                    }
+                }
+                Command::none()
+            }
+            Message::Grid(index, message) => {
+                if let Viewport::Ready { session, grids, .. } = self {
+                    if let (Some(grid), Some(result)) =
+                        (grids.get_mut(&index), session.results.get(&index))
+                    {
+                        return grid
+                            .update(result, message)
+                            .map(move |message| Message::Grid(index, message));
+                    }
+                }
+                Command::none()
+            }
+            Message::Chart(index, message) => {
+                if let Viewport::Ready {
+                    session, charts, ..
+                } = self
+                {
+                    if let (Some(chart), Some(result)) =
+                        (charts.get_mut(&index), session.results.get(&index))
+                    {
+                        return chart
+                            .update(result, message)
+                            .map(move |message| Message::Chart(index, message));
+                    }
+                }
+                Command::none()
+            }
+            Message::Editor(editor::Message::Run(target)) => {
+                if let Viewport::Ready {
+                    session,
+                    running,
+                    editor,
+                    ..
+                } = self
+                {
+                    let query = editor.text(target);
+
+                    if !*running && !query.trim().is_empty() {
+                        *running = true;
+                        return Command::perform(
+                            pgp_core::exec_sql(query, session.clone()),
+                            Message::QueryComplete,
+                        );
+                    }
+                }
+                Command::none()
+            }
+            Message::Editor(message) => {
+                if let Viewport::Ready { editor, .. } = self {
+                    return editor.update(message).map(Message::Editor);
+                }
+                Command::none()
+            }
+            Message::Preview((schema_name, table)) => {
+                self.query_table(&schema_name, &table, schema::preview_query)
+            }
+            Message::Describe((schema_name, table)) => {
+                self.query_table(&schema_name, &table, schema::describe_query)
+            }
+            Message::Ask((schema_name, table)) => {
+                if let Viewport::Ready { input, session, .. } = self {
+                    if let Some(table) = session.catalog.table(&schema_name, &table) {
+                        *input = format!("Tell me about the {} table", table.qualified_name());
+                    }
+                }
+                Command::none()
+            }
+            Message::InsertName(name) => {
+                if let Viewport::Ready { input, editor, .. } = self {
+                    if editor.is_focused() {
+                        editor.insert_text(&name);
+                    } else {
+                        if !input.is_empty() && !input.ends_with(' ') {
+                            input.push(' ');
+                        }
+                        input.push_str(&name);
+                    }
+                }
+                Command::none()
+            }
+            Message::ToggleContext => {
+                if let Viewport::Ready { context, .. } = self {
+                    *context = !*context;
+                }
+                Command::none()
+            }
+            Message::ToggleVote => {
+                if let Viewport::Ready { session, .. } = self {
+                    session.vote = !session.vote;
+                }
+                Command::none()
+            }
+            Message::ToggleAlternatives(index) => {
+                if let Viewport::Ready { alternatives, .. } = self {
+                    if !alternatives.remove(&index) {
+                        alternatives.insert(index);
+                    }
+                }
+                Command::none()
+            }
+            Message::OpenSql(query) => {
+                if let Viewport::Ready { editor, .. } = self {
+                    editor.open(&query);
+                }
+                Command::none()
+            }
+            Message::MeasureFormats => {
+                if let Viewport::Ready { session, .. } = self {
+                    let catalog = session.catalog.clone();
+                    let options = session.schema_options.clone();
+                    let model = session.model.clone();
+
+                    return Command::perform(
+                        async move { render::measure(&catalog, &options, &model) },
+                        Message::FormatsMeasured,
+                    );
+                }
+                Command::none()
+            }
+            Message::FormatsMeasured(measured) => {
+                if let Viewport::Ready { formats, .. } = self {
+                    *formats = measured;
+                }
+                Command::none()
+            }
+            Message::GridEvent(message) => {
+                if let Viewport::Ready { session, grids, .. } = self {
+                    for (index, grid) in grids.iter_mut().filter(|(_, g)| g.is_resizing()) {
+                        if let Some(result) = session.results.get(index) {
+                            let _ = grid.update(result, message.clone());
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::Cancelled(Ok(())) => Command::none(),
+            Message::Cancelled(Err(error)) => {
+                println!("cancel error: {:?}", error);
+                Command::none()
+            }
+        }
+    }
+
+    /// Runs a query built from a catalog table, as if typed in the editor.
+    fn query_table(
+        &mut self,
+        schema_name: &str,
+        table: &str,
+        query: fn(&schema::Table) -> String,
+    ) -> Command<Message> {
+        if let Viewport::Ready {
+            session, running, ..
+        } = self
+        {
+            if let (Some(table), false) = (session.catalog.table(schema_name, table), *running) {
+                *running = true;
+                return Command::perform(
+                    pgp_core::exec_sql(query(table), session.clone()),
+                    Message::QueryComplete,
+                );
+            }
         }
+        Command::none()
     }
 
     pub fn view(&self, config: &Config) -> Element<Message> {
@@ -140,7 +648,20 @@ impl Viewport {
                 .into()
             }
 
-            Viewport::Ready { input, session, .. } => {
+            Viewport::Ready {
+                input,
+                session,
+                running,
+                grids,
+                charts,
+                plans,
+                alternatives,
+                editor,
+                notice,
+                context,
+                formats,
+                ..
+            } => {
                 // let mut column = column![].spacing(1);
 
                 let text_input = text_input("Type something...", &input)
@@ -149,19 +670,120 @@ impl Viewport {
                     .size(18)
                     .width(Length::Fill);
 
-                let button = button("Submit").padding(10).on_press(Message::Query);
+                let sql_button = button("SQL")
+                    .padding(10)
+                    .style(if editor.is_visible() {
+                        theme::Button::Primary
+                    } else {
+                        theme::Button::Secondary
+                    })
+                    .on_press(Message::Editor(editor::Message::Toggle));
+
+                let context_button = button("Context")
+                    .padding(10)
+                    .style(if *context {
+                        theme::Button::Primary
+                    } else {
+                        theme::Button::Secondary
+                    })
+                    .on_press(Message::ToggleContext);
+
+                let vote_button = button("Vote")
+                    .padding(10)
+                    .style(if session.vote {
+                        theme::Button::Primary
+                    } else {
+                        theme::Button::Secondary
+                    })
+                    .on_press(Message::ToggleVote);
+
+                let button = if *running {
+                    button("Cancel")
+                        .padding(10)
+                        .style(theme::Button::Destructive)
+                        .on_press(Message::Cancel)
+                } else {
+                    button("Submit").padding(10).on_press(Message::Query)
+                };
 
                 let chat = column![].width(Length::Fill).spacing(1);
 
                 let chat = session
                     .messages
                     .iter()
-                    .fold(chat, |chat, msg| match msg.content {
-                        Some(ref content) => {
-                            let msg_content = msg.role.to_string() + ": " + content;
-                            chat.push(text(msg_content).size(18).width(Length::Fill))
+                    .enumerate()
+                    .fold(chat, |chat, (i, msg)| {
+                        match (session.results.get(&i), grids.get(&i), &msg.content) {
+                            (Some(result), Some(grid), content) => {
+                                let chat = chat.push(
+                                    grid.view(result)
+                                        .map(move |message| Message::Grid(i, message)),
+                                );
+
+                                let chat = match &result.truncated {
+                                    Some(truncation) => chat.push(
+                                        text(format!(
+                                            "Result {}; more rows may exist.",
+                                            truncation
+                                        ))
+                                        .size(14),
+                                    ),
+                                    None => chat,
+                                };
+
+                                let withheld = content.as_deref().map_or(0, injection::withheld);
+                                let chat = if withheld > 0 {
+                                    chat.push(
+                                        text(format!(
+                                            "{} values were withheld from the model as they read \
+                                             like instructions.",
+                                            withheld
+                                        ))
+                                        .size(14),
+                                    )
+                                } else {
+                                    chat
+                                };
+
+                                let chat = match session.votes.get(&i) {
+                                    Some(vote) => chat.push(votes::view(
+                                        vote,
+                                        alternatives.contains(&i),
+                                        Message::ToggleAlternatives(i),
+                                    )),
+                                    None => chat,
+                                };
+
+                                let chat = match plans.get(&i) {
+                                    Some(plan) => chat.push(
+                                        plan.view().map(move |message| Message::Plan(i, message)),
+                                    ),
+                                    None => chat,
+                                };
+
+                                match charts.get(&i) {
+                                    Some(chart) => chat.push(
+                                        chart
+                                            .view(result)
+                                            .map(move |message| Message::Chart(i, message)),
+                                    ),
+                                    None => chat,
+                                }
+                            }
+                            (_, _, Some(content)) => {
+                                let msg_content = msg.role.to_string() + ": " + content;
+                                let chat =
+                                    chat.push(text(msg_content).size(18).width(Length::Fill));
+
+                                match session.groundings.get(&i) {
+                                    Some(grounding) => {
+                                        chat.push(sources::view(grounding, Message::OpenSql))
+                                    }
+                                    None => chat,
+                                }
+                            }
+                            _ => chat,
                         }
-                        None => chat,
                     });
 
                 let scrollable = scrollable(chat)
@@ -174,13 +796,42 @@ impl Viewport {
                     .width(Length::Fill)
                     .height(Length::Fill);
 
-                let content = column![
-                    scrollable,
-                    // horizontal_rule(38),
-                    row![text_input, button]
-                        .spacing(10)
-                        .align_items(Alignment::Center),
-                ]
+                let content = column![scrollable];
+
+                let content = match pgp_core::pending(session) {
+                    Some(call) => content.push(approval::view(
+                        call,
+                        *running,
+                        Message::Approve,
+                        Message::Reject,
+                    )),
+                    None => content,
+                };
+
+                let content = if editor.is_visible() {
+                    content.push(editor.view(*running).map(Message::Editor))
+                } else {
+                    content
+                };
+
+                let content = if *context {
+                    content.push(context_view(session, formats))
+                } else {
+                    content
+                };
+
+                let content = match notice {
+                    Some(notice) => content.push(text(notice).size(14)),
+                    None => content,
+                };
+
+                let content = content
+                    .push(
+                        // horizontal_rule(38),
+                        row![text_input, sql_button, context_button, vote_button, button]
+                            .spacing(10)
+                            .align_items(Alignment::Center),
+                    )
                 .spacing(20)
                 .padding(20)
                 .width(Length::Fill);
@@ -196,3 +847,147 @@ impl Viewport {
         }
     }
 }
+
+fn sync_results(
+    grids: &mut BTreeMap<usize, Grid>,
+    charts: &mut BTreeMap<usize, Chart>,
+    session: &Session,
+) {
+    for (index, result) in &session.results {
+        grids.entry(*index).or_insert_with(|| Grid::new(result));
+        charts.entry(*index).or_insert_with(|| Chart::new(result));
+    }
+}
+
+/// The tables picked for the system message and why, best match first,
+/// with how the last request used the context window and what the schema
+/// costs in each format.
+fn context_view<'a>(
+    session: &'a Session,
+    formats: &[(SchemaFormat, usize)],
+) -> Element<'a, Message> {
+    let selection = &session.selection;
+    let usage = &session.usage;
+
+    let mut summary = if selection.is_partial() {
+        format!(
+            "{} of {} tables in the prompt",
+            selection.tables.len(),
+            selection.total
+        )
+    } else {
+        format!("All {} tables in the prompt", selection.total)
+    };
+    if session.schema_only {
+        summary += ", schema only: no row data is sent";
+    }
+
+    let list = selection
+        .tables
+        .iter()
+        .fold(column![].spacing(2), |list, selected| {
+            let reason = match &selected.reason {
+                Reason::All => String::new(),
+                Reason::Match(score) => format!("score {:.2}", score),
+                Reason::Neighbour(table) => format!("joins {}", table),
+            };
+
+            list.push(
+                row![
+                    text(format!("{}.{}", selected.schema, selected.table)).size(14),
+                    text(reason).size(12).style(Color::from_rgb(0.5, 0.5, 0.5)),
+                ]
+                .spacing(8)
+                .align_items(Alignment::Center),
+            )
+        });
+
+    let mut tokens = format!(
+        "{} of {} tokens: schema {}, history {} (results {})",
+        usage.total(),
+        usage.window,
+        usage.schema,
+        usage.history,
+        usage.results
+    );
+    if usage.dropped > 0 {
+        tokens += &format!(", {} older messages summarised", usage.dropped);
+    }
+    if usage.truncated > 0 {
+        tokens += &format!(", {} results truncated", usage.truncated);
+    }
+
+    let costs = formats
+        .iter()
+        .map(|(format, tokens)| {
+            let current = if *format == session.schema_options.format {
+                " (current)"
+            } else {
+                ""
+            };
+            format!("{} {}{}", format, tokens, current)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let measure = row![
+        button(text("Measure formats").size(12))
+            .padding([2, 6])
+            .style(theme::Button::Secondary)
+            .on_press(Message::MeasureFormats),
+        text(costs).size(14),
+    ]
+    .spacing(8)
+    .align_items(Alignment::Center);
+
+    container(
+        column![
+            text(summary).size(14),
+            text(tokens).size(14),
+            measure,
+            scrollable(list).height(Length::Shrink)
+        ]
+        .spacing(6),
+    )
+    .padding(10)
+    .width(Length::Fill)
+    .max_height(200)
+    .style(theme::Container::Box)
+    .into()
+}
+
+fn stream(session: Session, streaming: Streaming) -> Subscription<Message> {
+    let id = streaming.path.clone();
+
+    subscription::channel(id, 16, move |mut output| async move {
+        let mut progress = output.clone();
+        let result = export::copy_to_file(
+            session,
+            streaming.query,
+            streaming.format,
+            streaming.path,
+            move |bytes| {
+                let _ = progress.try_send(Message::StreamProgress(bytes));
+            },
+        )
+        .await;
+
+        let _ = output.send(Message::StreamFinished(result)).await;
+
+        std::future::pending().await
+    })
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,38 @@
+use iced::widget::{button, column, container, row, text};
+use iced::{theme, Color, Element, Length};
+
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+/// A call the model made after reading values that read like instructions,
+/// with buttons to run it or not that are off while `running`.
+pub fn view<'a, M: Clone + 'a>(
+    call: String,
+    running: bool,
+    on_approve: M,
+    on_reject: M,
+) -> Element<'a, M> {
+    let approve = button(text("Run it").size(14)).style(theme::Button::Primary);
+    let reject = button(text("Don't run").size(14)).style(theme::Button::Secondary);
+    let (approve, reject) = if running {
+        (approve, reject)
+    } else {
+        (approve.on_press(on_approve), reject.on_press(on_reject))
+    };
+
+    container(
+        column![
+            text(
+                "Some values the model read look like instructions and were withheld from it. \
+                 It now wants to run:"
+            )
+            .size(14),
+            text(call).size(12).style(MUTED),
+            row![approve, reject].spacing(8),
+        ]
+        .spacing(6),
+    )
+    .padding(10)
+    .width(Length::Fill)
+    .style(theme::Container::Box)
+    .into()
+}
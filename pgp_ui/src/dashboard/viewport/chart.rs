@@ -0,0 +1,269 @@
+use iced::alignment;
+use iced::mouse;
+use iced::widget::canvas::{self, path::Arc, Cache, Canvas, Frame, Geometry, Path, Stroke};
+use iced::widget::{button, column, pick_list, row, text};
+use iced::{
+    theme, Alignment, Color, Command, Element, Length, Point, Rectangle, Renderer, Size, Theme,
+};
+
+use pgp_core::chart::{self, Anchor, ChartFormat, ChartKind, ChartSpec, Shape};
+use pgp_core::errors::Error;
+use pgp_core::export;
+use pgp_core::results::ResultSet;
+use std::fmt;
+use std::path::PathBuf;
+
+const CHART_HEIGHT: f32 = 280.0;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+/// Chart shown under a result grid. The spec starts out as the suggestion
+/// for the column types and is changed from the controls above the canvas.
+#[derive(Debug)]
+pub struct Chart {
+    spec: Option<ChartSpec>,
+    visible: bool,
+    cache: Cache,
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnOption {
+    index: usize,
+    name: String,
+}
+
+impl fmt::Display for ColumnOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Toggle,
+    Kind(ChartKind),
+    X(ColumnOption),
+    Y(ColumnOption),
+    Save(ChartFormat),
+    Saved(Result<PathBuf, Error>),
+}
+
+impl Chart {
+    pub fn new(result: &ResultSet) -> Self {
+        let spec = chart::suggest(result);
+
+        Self {
+            spec,
+            visible: spec.is_some(),
+            cache: Cache::new(),
+            status: None,
+        }
+    }
+
+    pub fn update(&mut self, result: &ResultSet, message: Message) -> Command<Message> {
+        match message {
+            Message::Toggle => {
+                self.visible = !self.visible;
+
+                if self.spec.is_none() && !result.columns.is_empty() {
+                    self.spec = Some(ChartSpec {
+                        kind: ChartKind::Bar,
+                        x: 0,
+                        y: result.columns.len() - 1,
+                    });
+                }
+            }
+            Message::Kind(kind) => self.change(|spec| spec.kind = kind),
+            Message::X(column) => self.change(|spec| spec.x = column.index),
+            Message::Y(column) => self.change(|spec| spec.y = column.index),
+            Message::Save(format) => {
+                if let Some(spec) = self.spec {
+                    self.status = Some(format!("Saving {}...", format));
+                    return Command::perform(
+                        chart::save(
+                            result.clone(),
+                            spec,
+                            format,
+                            export::default_path("chart", format.extension()),
+                        ),
+                        Message::Saved,
+                    );
+                }
+            }
+            Message::Saved(Ok(path)) => {
+                self.status = Some(format!("Saved to {}", path.display()));
+            }
+            Message::Saved(Err(error)) => {
+                self.status = Some(error.to_string());
+            }
+        }
+
+        Command::none()
+    }
+
+    pub fn view<'a>(&'a self, result: &'a ResultSet) -> Element<'a, Message> {
+        let toggle = button(text(if self.visible { "Hide chart" } else { "Chart" }).size(14))
+            .style(theme::Button::Secondary)
+            .on_press(Message::Toggle);
+
+        let spec = match (self.spec, self.visible) {
+            (Some(spec), true) => spec,
+            _ => return row![toggle].into(),
+        };
+
+        let columns: Vec<_> = result
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, c)| ColumnOption {
+                index,
+                name: c.name.clone(),
+            })
+            .collect();
+
+        let selected = |index: usize| columns.get(index).cloned();
+
+        let controls = row![
+            toggle,
+            pick_list(&ChartKind::ALL[..], Some(spec.kind), Message::Kind).text_size(14),
+            text("x").size(14).style(MUTED),
+            pick_list(columns.clone(), selected(spec.x), Message::X).text_size(14),
+            text("y").size(14).style(MUTED),
+            pick_list(columns.clone(), selected(spec.y), Message::Y).text_size(14),
+            pick_list(&ChartFormat::ALL[..], None, Message::Save)
+                .placeholder("Save chart")
+                .text_size(14),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let controls = match &self.status {
+            Some(status) => controls.push(text(status).size(14).style(MUTED)),
+            None => controls,
+        };
+
+        let plot = Canvas::new(Plot {
+            result,
+            spec,
+            cache: &self.cache,
+        })
+        .width(Length::Fill)
+        .height(Length::Fixed(CHART_HEIGHT));
+
+        column![controls, plot].spacing(6).into()
+    }
+
+    fn change(&mut self, f: impl FnOnce(&mut ChartSpec)) {
+        if let Some(spec) = self.spec.as_mut() {
+            f(spec);
+            self.cache.clear();
+        }
+    }
+}
+
+struct Plot<'a> {
+    result: &'a ResultSet,
+    spec: ChartSpec,
+    cache: &'a Cache,
+}
+
+impl<'a> canvas::Program<Message> for Plot<'a> {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let geometry = self.cache.draw(renderer, bounds.size(), |frame| {
+            let size = frame.size();
+
+            for shape in chart::render(self.result, self.spec, size.width, size.height) {
+                draw(frame, shape);
+            }
+        });
+
+        vec![geometry]
+    }
+}
+
+fn draw(frame: &mut Frame, shape: Shape) {
+    let color = |c: [u8; 3]| Color::from_rgb8(c[0], c[1], c[2]);
+    let point = |(x, y): (f32, f32)| Point::new(x, y);
+
+    match shape {
+        Shape::Line { from, to, color: c } => frame.stroke(
+            &Path::line(point(from), point(to)),
+            Stroke::default().with_color(color(c)),
+        ),
+        Shape::Polyline { points, color: c } => {
+            let path = Path::new(|builder| {
+                for (i, p) in points.into_iter().enumerate() {
+                    if i == 0 {
+                        builder.move_to(point(p));
+                    } else {
+                        builder.line_to(point(p));
+                    }
+                }
+            });
+
+            frame.stroke(
+                &path,
+                Stroke::default().with_color(color(c)).with_width(2.0),
+            );
+        }
+        Shape::Rect {
+            x,
+            y,
+            width,
+            height,
+            color: c,
+        } => frame.fill_rectangle(Point::new(x, y), Size::new(width, height), color(c)),
+        Shape::Circle {
+            center,
+            radius,
+            color: c,
+        } => frame.fill(&Path::circle(point(center), radius), color(c)),
+        Shape::Wedge {
+            center,
+            radius,
+            start,
+            end,
+            color: c,
+        } => {
+            let path = Path::new(|builder| {
+                builder.move_to(point(center));
+                builder.arc(Arc {
+                    center: point(center),
+                    radius,
+                    start_angle: start,
+                    end_angle: end,
+                });
+                builder.close();
+            });
+
+            frame.fill(&path, color(c));
+        }
+        Shape::Text {
+            position,
+            content,
+            anchor,
+            color: c,
+        } => frame.fill_text(canvas::Text {
+            content,
+            position: point(position),
+            color: color(c),
+            size: 11.0,
+            horizontal_alignment: match anchor {
+                Anchor::Start => alignment::Horizontal::Left,
+                Anchor::Middle => alignment::Horizontal::Center,
+                Anchor::End => alignment::Horizontal::Right,
+            },
+            vertical_alignment: alignment::Vertical::Center,
+            ..canvas::Text::default()
+        }),
+    }
+}
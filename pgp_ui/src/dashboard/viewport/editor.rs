@@ -0,0 +1,595 @@
+use iced::event::{self, Event};
+use iced::keyboard::{self, KeyCode, Modifiers};
+use iced::widget::{
+    button, column, container, mouse_area, row, scrollable, text, Column, Container, Row,
+};
+use iced::{
+    clipboard, subscription, theme, Alignment, Background, Color, Command, Element, Font, Length,
+    Subscription, Theme,
+};
+
+const EDITOR_HEIGHT: f32 = 180.0;
+const LINE_HEIGHT: f32 = 20.0;
+const GUTTER_WIDTH: f32 = 36.0;
+const TAB: &str = "    ";
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+const KEYWORDS: &[&str] = &[
+    "ALL",
+    "ALTER",
+    "AND",
+    "ANY",
+    "AS",
+    "ASC",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "CAST",
+    "COMMIT",
+    "CREATE",
+    "CROSS",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "ELSE",
+    "END",
+    "EXCEPT",
+    "EXISTS",
+    "EXPLAIN",
+    "FALSE",
+    "FETCH",
+    "FILTER",
+    "FIRST",
+    "FOR",
+    "FROM",
+    "FULL",
+    "GROUP",
+    "HAVING",
+    "ILIKE",
+    "IN",
+    "INNER",
+    "INSERT",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "JOIN",
+    "LATERAL",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "THEN",
+    "TRUE",
+    "TRUNCATE",
+    "UNION",
+    "UPDATE",
+    "USING",
+    "VALUES",
+    "VIEW",
+    "WHEN",
+    "WHERE",
+    "WITH",
+];
+
+/// A line and column, counted in characters.
+type Position = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Selection,
+    All,
+}
+
+/// Multi-line SQL editor shown above the prompt. Keys reach it through a
+/// subscription while it has focus, so it only sees events that no other
+/// widget captured.
+#[derive(Debug)]
+pub struct Editor {
+    lines: Vec<String>,
+    cursor: Position,
+    anchor: Option<Position>,
+    focused: bool,
+    visible: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Toggle,
+    Focus(usize),
+    Insert(char),
+    Key(KeyCode, Modifiers),
+    Paste(Option<String>),
+    Run(Target),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Plain,
+    Keyword,
+    Literal,
+    Number,
+    Comment,
+    Quoted,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            lines: vec![String::new()],
+            cursor: (0, 0),
+            anchor: None,
+            focused: false,
+            visible: false,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.visible && self.focused
+    }
+
+    /// The selected SQL, or the whole buffer when asked for everything or
+    /// when nothing is selected.
+    pub fn text(&self, target: Target) -> String {
+        match (target, self.selection()) {
+            (Target::Selection, Some((start, end))) => self.slice(start, end),
+            _ => self.lines.join("\n"),
+        }
+    }
+
+    /// Inserts text at the cursor, replacing the selection.
+    pub fn insert_text(&mut self, contents: &str) {
+        self.insert(contents);
+    }
+
+    /// Shows the editor with `contents` in place of the buffer.
+    pub fn open(&mut self, contents: &str) {
+        self.lines = vec![String::new()];
+        self.cursor = (0, 0);
+        self.anchor = None;
+        self.insert(contents);
+        self.visible = true;
+        self.focused = true;
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<Message> {
+        match message {
+            Message::Toggle => {
+                self.visible = !self.visible;
+                self.focused = self.visible;
+            }
+            Message::Focus(line) => {
+                self.focused = true;
+                self.anchor = None;
+                self.cursor = (line, self.len(line));
+            }
+            Message::Insert(c) => self.insert(&c.to_string()),
+            Message::Paste(Some(contents)) => {
+                self.insert(&contents.replace("\r\n", "\n").replace('\r', "\n"))
+            }
+            Message::Paste(None) => {}
+            Message::Key(key_code, modifiers) => return self.key(key_code, modifiers),
+            // Handled by the viewport, which owns the session.
+            Message::Run(_) => {}
+        }
+
+        Command::none()
+    }
+
+    pub fn subscription() -> Subscription<Message> {
+        subscription::events_with(key_event)
+    }
+
+    pub fn view(&self, running: bool) -> Element<'_, Message> {
+        let mut comment = false;
+
+        let lines = self
+            .lines
+            .iter()
+            .enumerate()
+            .fold(Column::new(), |lines, (i, line)| {
+                let tokens = highlight(line, &mut comment);
+
+                let content = row![
+                    text(i + 1)
+                        .font(Font::MONOSPACE)
+                        .size(12)
+                        .style(MUTED)
+                        .width(Length::Fixed(GUTTER_WIDTH)),
+                    self.line(i, line, &tokens),
+                ]
+                .height(Length::Fixed(LINE_HEIGHT))
+                .align_items(Alignment::Center);
+
+                lines.push(mouse_area(content).on_press(Message::Focus(i)))
+            });
+
+        let buffer = container(scrollable(lines).height(Length::Fixed(EDITOR_HEIGHT)))
+            .padding(6)
+            .width(Length::Fill)
+            .style(theme::Container::Box);
+
+        let run_button = |label, target| {
+            let run = button(text(label).size(14)).style(theme::Button::Secondary);
+            if running {
+                run
+            } else {
+                run.on_press(Message::Run(target))
+            }
+        };
+
+        let toolbar = row![
+            run_button("Run selection", Target::Selection),
+            run_button("Run all", Target::All),
+            text("Ctrl+Enter runs the selection, or everything when nothing is selected")
+                .size(12)
+                .style(MUTED),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        column![buffer, toolbar].spacing(6).into()
+    }
+
+    /// Renders one line as runs of equally styled characters, with the
+    /// selection highlighted and the cursor drawn as a thin bar.
+    fn line<'a>(&self, index: usize, line: &str, tokens: &[Token]) -> Row<'a, Message> {
+        let selected = |col: usize| match self.selection() {
+            Some((start, end)) => (index, col) >= start && (index, col) < end,
+            None => false,
+        };
+        let cursor = (self.focused && self.cursor.0 == index).then_some(self.cursor.1);
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut spans = Row::new();
+        let mut start = 0;
+
+        for col in 0..=chars.len() {
+            let boundary = col == chars.len()
+                || cursor == Some(col)
+                || (col > start
+                    && (tokens[col] != tokens[start] || selected(col) != selected(start)));
+
+            if !boundary {
+                continue;
+            }
+
+            if col > start {
+                let span: String = chars[start..col].iter().collect();
+                spans = spans.push(span_view(span, tokens[start], selected(start)));
+            }
+
+            if cursor == Some(col) {
+                spans = spans.push(
+                    container(text(""))
+                        .width(Length::Fixed(2.0))
+                        .height(Length::Fixed(LINE_HEIGHT - 4.0))
+                        .style(caret as fn(&Theme) -> container::Appearance),
+                );
+            }
+
+            start = col;
+        }
+
+        spans
+    }
+
+    fn key(&mut self, key_code: KeyCode, modifiers: Modifiers) -> Command<Message> {
+        let shift = modifiers.shift();
+        let command = modifiers.command();
+
+        match key_code {
+            KeyCode::Enter => self.insert("\n"),
+            KeyCode::Tab => self.insert(TAB),
+            KeyCode::Backspace | KeyCode::Delete => {
+                // Without a selection the character next to the cursor is
+                // selected first, so both keys go through the same path.
+                if self.selection().is_none() {
+                    let towards = match key_code {
+                        KeyCode::Backspace => KeyCode::Left,
+                        _ => KeyCode::Right,
+                    };
+                    self.anchor = Some(self.motion(towards, false));
+                }
+
+                if !self.delete_selection() {
+                    self.anchor = None;
+                }
+            }
+            KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Home
+            | KeyCode::End => {
+                match (shift, self.anchor) {
+                    (true, None) => self.anchor = Some(self.cursor),
+                    (false, _) => self.anchor = None,
+                    _ => {}
+                }
+                self.cursor = self.motion(key_code, command);
+            }
+            KeyCode::A if command => {
+                let last = self.lines.len() - 1;
+                self.anchor = Some((0, 0));
+                self.cursor = (last, self.len(last));
+            }
+            KeyCode::C if command => {
+                if let Some((start, end)) = self.selection() {
+                    return clipboard::write(self.slice(start, end));
+                }
+            }
+            KeyCode::X if command => {
+                if let Some((start, end)) = self.selection() {
+                    let contents = self.slice(start, end);
+                    self.delete_range(start, end);
+                    return clipboard::write(contents);
+                }
+            }
+            KeyCode::V if command => return clipboard::read(Message::Paste),
+            KeyCode::Escape => self.focused = false,
+            _ => {}
+        }
+
+        Command::none()
+    }
+
+    fn motion(&self, key_code: KeyCode, command: bool) -> Position {
+        let (line, col) = self.cursor;
+        let last = self.lines.len() - 1;
+
+        match key_code {
+            KeyCode::Left if col > 0 => (line, col - 1),
+            KeyCode::Left if line > 0 => (line - 1, self.len(line - 1)),
+            KeyCode::Right if col < self.len(line) => (line, col + 1),
+            KeyCode::Right if line < last => (line + 1, 0),
+            KeyCode::Up if line > 0 => (line - 1, col.min(self.len(line - 1))),
+            KeyCode::Down if line < last => (line + 1, col.min(self.len(line + 1))),
+            KeyCode::Home if command => (0, 0),
+            KeyCode::Home => (line, 0),
+            KeyCode::End if command => (last, self.len(last)),
+            KeyCode::End => (line, self.len(line)),
+            _ => (line, col),
+        }
+    }
+
+    fn len(&self, line: usize) -> usize {
+        self.lines[line].chars().count()
+    }
+
+    /// The selected range in document order, if it is not empty.
+    fn selection(&self) -> Option<(Position, Position)> {
+        match self.anchor {
+            Some(anchor) if anchor < self.cursor => Some((anchor, self.cursor)),
+            Some(anchor) if anchor > self.cursor => Some((self.cursor, anchor)),
+            _ => None,
+        }
+    }
+
+    fn slice(&self, start: Position, end: Position) -> String {
+        if start.0 == end.0 {
+            let line = &self.lines[start.0];
+            return line[byte(line, start.1)..byte(line, end.1)].to_string();
+        }
+
+        let first = &self.lines[start.0];
+        let last = &self.lines[end.0];
+
+        std::iter::once(&first[byte(first, start.1)..])
+            .chain(self.lines[start.0 + 1..end.0].iter().map(String::as_str))
+            .chain(std::iter::once(&last[..byte(last, end.1)]))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        match self.selection() {
+            Some((start, end)) => {
+                self.delete_range(start, end);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn delete_range(&mut self, start: Position, end: Position) {
+        let tail = self.lines[end.0][byte(&self.lines[end.0], end.1)..].to_string();
+        let line = &mut self.lines[start.0];
+
+        line.truncate(byte(line, start.1));
+        line.push_str(&tail);
+        self.lines.drain(start.0 + 1..=end.0);
+
+        self.cursor = start;
+        self.anchor = None;
+    }
+
+    fn insert(&mut self, contents: &str) {
+        self.delete_selection();
+
+        let (line, col) = self.cursor;
+        let split = byte(&self.lines[line], col);
+        let tail = self.lines[line].split_off(split);
+        let mut parts = contents.split('\n');
+
+        self.lines[line].push_str(parts.next().unwrap_or_default());
+
+        let inserted: Vec<String> = parts.map(str::to_string).collect();
+
+        match inserted.last() {
+            Some(last) => {
+                self.cursor = (line + inserted.len(), last.chars().count());
+                self.lines.splice(line + 1..line + 1, inserted);
+            }
+            None => self.cursor = (line, self.len(line)),
+        }
+
+        self.lines[self.cursor.0].push_str(&tail);
+    }
+}
+
+fn key_event(event: Event, status: event::Status) -> Option<Message> {
+    if status == event::Status::Captured {
+        return None;
+    }
+
+    match event {
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code: KeyCode::Enter,
+            modifiers,
+        }) if modifiers.command() => Some(Message::Run(Target::Selection)),
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code,
+            modifiers,
+        }) => Some(Message::Key(key_code, modifiers)),
+        // Enter, Tab and Backspace arrive as control characters as well and
+        // are handled as key presses.
+        Event::Keyboard(keyboard::Event::CharacterReceived(c)) if !c.is_control() => {
+            Some(Message::Insert(c))
+        }
+        _ => None,
+    }
+}
+
+fn span_view<'a>(span: String, token: Token, selected: bool) -> Container<'a, Message> {
+    let content = text(span).font(Font::MONOSPACE).size(14);
+    let content = match token {
+        Token::Plain => content,
+        Token::Keyword => content.style(Color::from_rgb(0.1, 0.3, 0.8)),
+        Token::Literal => content.style(Color::from_rgb(0.1, 0.5, 0.2)),
+        Token::Number => content.style(Color::from_rgb(0.7, 0.35, 0.0)),
+        Token::Comment => content.style(MUTED),
+        Token::Quoted => content.style(Color::from_rgb(0.55, 0.2, 0.6)),
+    };
+
+    if selected {
+        container(content).style(selection as fn(&Theme) -> container::Appearance)
+    } else {
+        container(content)
+    }
+}
+
+fn selection(theme: &Theme) -> container::Appearance {
+    container::Appearance {
+        background: Some(Background::Color(Color {
+            a: 0.3,
+            ..theme.palette().primary
+        })),
+        ..Default::default()
+    }
+}
+
+fn caret(theme: &Theme) -> container::Appearance {
+    container::Appearance {
+        background: Some(Background::Color(theme.palette().text)),
+        ..Default::default()
+    }
+}
+
+/// Byte offset of a character column, clamped to the end of the line.
+fn byte(line: &str, col: usize) -> usize {
+    line.char_indices()
+        .nth(col)
+        .map(|(i, _)| i)
+        .unwrap_or(line.len())
+}
+
+/// Token kind of every character in a line. `comment` carries an open
+/// block comment over to the next line.
+fn highlight(line: &str, comment: &mut bool) -> Vec<Token> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = vec![Token::Plain; chars.len()];
+    let mut i = 0;
+
+    let until = |from: usize, quote: char| {
+        chars[from + 1..]
+            .iter()
+            .position(|c| *c == quote)
+            .map(|p| from + p + 2)
+            .unwrap_or(chars.len())
+    };
+
+    while i < chars.len() {
+        let start = i;
+        let next = chars.get(i + 1).copied();
+
+        let token = match chars[i] {
+            _ if *comment => {
+                match (i..chars.len() - 1).find(|&j| chars[j] == '*' && chars[j + 1] == '/') {
+                    Some(j) => {
+                        *comment = false;
+                        i = j + 2;
+                    }
+                    None => i = chars.len(),
+                }
+                Token::Comment
+            }
+            '-' if next == Some('-') => {
+                i = chars.len();
+                Token::Comment
+            }
+            '/' if next == Some('*') => {
+                *comment = true;
+                i += 2;
+                Token::Comment
+            }
+            '\'' => {
+                i = until(i, '\'');
+                Token::Literal
+            }
+            '"' => {
+                i = until(i, '"');
+                Token::Quoted
+            }
+            c if c.is_ascii_digit() => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                Token::Number
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+                {
+                    i += 1;
+                }
+
+                let word: String = chars[start..i].iter().collect();
+                if KEYWORDS.contains(&word.to_uppercase().as_str()) {
+                    Token::Keyword
+                } else {
+                    Token::Plain
+                }
+            }
+            _ => {
+                i += 1;
+                Token::Plain
+            }
+        };
+
+        tokens[start..i].fill(token);
+    }
+
+    tokens
+}
@@ -0,0 +1,355 @@
+use iced::event::{self, Event};
+use iced::mouse;
+use iced::widget::{
+    button, column, container, mouse_area, pick_list, row, scrollable, text, vertical_space,
+    Column, Row,
+};
+use iced::{
+    clipboard, subscription, theme, Alignment, Color, Command, Element, Length, Subscription,
+};
+
+use pgp_core::errors::Error;
+use pgp_core::export::{self, Format};
+use pgp_core::results::{ResultSet, Value};
+use std::path::PathBuf;
+
+const ROW_HEIGHT: f32 = 26.0;
+const HEADER_HEIGHT: f32 = 40.0;
+const VISIBLE_ROWS: usize = 12;
+const OVERSCAN_ROWS: usize = 2;
+const DEFAULT_COLUMN_WIDTH: f32 = 140.0;
+const MIN_COLUMN_WIDTH: f32 = 48.0;
+const HANDLE_WIDTH: f32 = 4.0;
+const CHAR_WIDTH: f32 = 8.0;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyTarget {
+    Cell,
+    Row,
+    Column,
+}
+
+/// View state of the results table under an answer. Rows are never copied,
+/// sorting only reorders `order`, and only the rows inside the scrolled
+/// window are turned into widgets.
+#[derive(Debug)]
+pub struct Grid {
+    widths: Vec<f32>,
+    order: Vec<usize>,
+    sort: Option<(usize, bool)>,
+    selection: Option<(usize, usize)>,
+    offset: f32,
+    resizing: Option<(usize, Option<f32>)>,
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Sort(usize),
+    Select(usize, usize),
+    Copy(CopyTarget),
+    Scrolled(scrollable::Viewport),
+    ResizeStart(usize),
+    ResizeMove(f32),
+    ResizeEnd,
+    Export(Format),
+    Exported(Result<PathBuf, Error>),
+    CopyAs(Format),
+    Stream(Format),
+    Explain,
+}
+
+impl Grid {
+    pub fn new(result: &ResultSet) -> Self {
+        Self {
+            widths: vec![DEFAULT_COLUMN_WIDTH; result.columns.len()],
+            order: (0..result.rows.len()).collect(),
+            sort: None,
+            selection: None,
+            offset: 0.0,
+            resizing: None,
+            status: None,
+        }
+    }
+
+    pub fn is_resizing(&self) -> bool {
+        self.resizing.is_some()
+    }
+
+    pub fn update(&mut self, result: &ResultSet, message: Message) -> Command<Message> {
+        match message {
+            Message::Sort(column) => {
+                self.sort = match self.sort {
+                    Some((c, true)) if c == column => Some((column, false)),
+                    Some((c, false)) if c == column => None,
+                    _ => Some((column, true)),
+                };
+
+                self.order = (0..result.rows.len()).collect();
+
+                if let Some((column, ascending)) = self.sort {
+                    self.order.sort_by(|a, b| {
+                        let ordering = result.rows[*a][column].compare(&result.rows[*b][column]);
+                        if ascending {
+                            ordering
+                        } else {
+                            ordering.reverse()
+                        }
+                    });
+                }
+
+                Command::none()
+            }
+            Message::Select(row, column) => {
+                self.selection = Some((row, column));
+                Command::none()
+            }
+            Message::Copy(target) => match self.selection {
+                Some((row, column)) => {
+                    clipboard::write(copy(result, &self.order, target, row, column))
+                }
+                None => Command::none(),
+            },
+            Message::Scrolled(viewport) => {
+                self.offset = viewport.absolute_offset().y;
+                Command::none()
+            }
+            Message::ResizeStart(column) => {
+                self.resizing = Some((column, None));
+                Command::none()
+            }
+            Message::ResizeMove(x) => {
+                if let Some((column, last)) = self.resizing {
+                    if let Some(last) = last {
+                        let width = self.widths[column] + x - last;
+                        self.widths[column] = width.max(MIN_COLUMN_WIDTH);
+                    }
+                    self.resizing = Some((column, Some(x)));
+                }
+                Command::none()
+            }
+            Message::ResizeEnd => {
+                self.resizing = None;
+                Command::none()
+            }
+            Message::Export(format) => {
+                self.status = Some(format!("Exporting {}...", format));
+                Command::perform(
+                    export::save(
+                        result.clone(),
+                        format,
+                        export::default_path("result", format.extension()),
+                    ),
+                    Message::Exported,
+                )
+            }
+            Message::Exported(Ok(path)) => {
+                self.status = Some(format!("Saved to {}", path.display()));
+                Command::none()
+            }
+            Message::Exported(Err(error)) => {
+                self.status = Some(error.to_string());
+                Command::none()
+            }
+            // Handled by the viewport, which owns the session.
+            Message::Stream(_) | Message::Explain => Command::none(),
+            Message::CopyAs(format) => match export::to_string(result, format) {
+                Ok(contents) => {
+                    self.status = Some(format!("Copied as {}", format));
+                    clipboard::write(contents)
+                }
+                Err(error) => {
+                    self.status = Some(error.to_string());
+                    Command::none()
+                }
+            },
+        }
+    }
+
+    pub fn subscription() -> Subscription<Message> {
+        subscription::events_with(resize_event)
+    }
+
+    pub fn view<'a>(&'a self, result: &'a ResultSet) -> Element<'a, Message> {
+        let header = result
+            .columns
+            .iter()
+            .enumerate()
+            .fold(Row::new(), |header, (i, c)| {
+                let arrow = match self.sort {
+                    Some((column, true)) if column == i => " ▲",
+                    Some((column, false)) if column == i => " ▼",
+                    _ => "",
+                };
+
+                let label = column![
+                    text(fit(&format!("{}{}", c.name, arrow), self.widths[i])).size(14),
+                    text(fit(&c.type_name, self.widths[i]))
+                        .size(11)
+                        .style(MUTED),
+                ];
+
+                let title = button(label)
+                    .width(Length::Fixed(self.widths[i] - HANDLE_WIDTH))
+                    .height(Length::Fixed(HEADER_HEIGHT))
+                    .padding([4, 6])
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::Sort(i));
+
+                let handle = mouse_area(
+                    container(vertical_space(Length::Fixed(HEADER_HEIGHT)))
+                        .width(Length::Fixed(HANDLE_WIDTH))
+                        .style(theme::Container::Box),
+                )
+                .on_press(Message::ResizeStart(i));
+
+                header.push(title).push(handle)
+            });
+
+        let total = self.order.len();
+        let first = ((self.offset / ROW_HEIGHT) as usize).min(total);
+        let last = (first + VISIBLE_ROWS + OVERSCAN_ROWS).min(total);
+
+        let body = self.order[first..last].iter().fold(
+            Column::new().push(vertical_space(Length::Fixed(first as f32 * ROW_HEIGHT))),
+            |body, &r| {
+                let cells =
+                    result.rows[r]
+                        .iter()
+                        .enumerate()
+                        .fold(Row::new(), |cells, (c, value)| {
+                            let selected = self.selection == Some((r, c));
+                            cells.push(
+                                self.cell(value, c, selected)
+                                    .on_press(Message::Select(r, c)),
+                            )
+                        });
+
+                body.push(cells)
+            },
+        );
+
+        let body = body.push(vertical_space(Length::Fixed(
+            (total - last) as f32 * ROW_HEIGHT,
+        )));
+
+        let body = scrollable(body)
+            .height(Length::Fixed(
+                total.clamp(1, VISIBLE_ROWS) as f32 * ROW_HEIGHT,
+            ))
+            .on_scroll(Message::Scrolled);
+
+        let table = scrollable(column![header, body])
+            .direction(scrollable::Direction::Horizontal(
+                scrollable::Properties::default(),
+            ))
+            .width(Length::Fill);
+
+        let copy_button = |label, target| {
+            let copy = button(text(label).size(14)).style(theme::Button::Secondary);
+            match self.selection {
+                Some(_) => copy.on_press(Message::Copy(target)),
+                None => copy,
+            }
+        };
+
+        let toolbar = row![
+            text(format!("{} rows", total)).size(14).style(MUTED),
+            copy_button("Copy cell", CopyTarget::Cell),
+            copy_button("Copy row", CopyTarget::Row),
+            copy_button("Copy column", CopyTarget::Column),
+            pick_list(&Format::TEXT[..], None, Message::CopyAs)
+                .placeholder("Copy as")
+                .text_size(14),
+            pick_list(&Format::ALL[..], None, Message::Export)
+                .placeholder("Export")
+                .text_size(14),
+            pick_list(&Format::STREAM[..], None, Message::Stream)
+                .placeholder("Export all rows")
+                .text_size(14),
+            button(text("Explain").size(14))
+                .style(theme::Button::Secondary)
+                .on_press(Message::Explain),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let toolbar = match &self.status {
+            Some(status) => toolbar.push(text(status).size(14).style(MUTED)),
+            None => toolbar,
+        };
+
+        column![table, toolbar].spacing(6).into()
+    }
+
+    fn cell<'a>(
+        &self,
+        value: &Value,
+        column: usize,
+        selected: bool,
+    ) -> button::Button<'a, Message> {
+        let content = if value.is_null() {
+            text("NULL").style(MUTED)
+        } else {
+            text(fit(&value.to_string(), self.widths[column]))
+        };
+
+        button(content.size(14))
+            .width(Length::Fixed(self.widths[column]))
+            .height(Length::Fixed(ROW_HEIGHT))
+            .padding([4, 6])
+            .style(if selected {
+                theme::Button::Primary
+            } else {
+                theme::Button::Text
+            })
+    }
+}
+
+fn resize_event(event: Event, _status: event::Status) -> Option<Message> {
+    match event {
+        Event::Mouse(mouse::Event::CursorMoved { position }) => {
+            Some(Message::ResizeMove(position.x))
+        }
+        Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => Some(Message::ResizeEnd),
+        _ => None,
+    }
+}
+
+fn copy(
+    result: &ResultSet,
+    order: &[usize],
+    target: CopyTarget,
+    row: usize,
+    column: usize,
+) -> String {
+    match target {
+        CopyTarget::Cell => result.rows[row][column].to_string(),
+        CopyTarget::Row => result.rows[row]
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join("\t"),
+        CopyTarget::Column => order
+            .iter()
+            .map(|&r| result.rows[r][column].to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Cuts a value to a single line that fits into the column width.
+fn fit(value: &str, width: f32) -> String {
+    let max = ((width - 12.0) / CHAR_WIDTH).max(1.0) as usize;
+    let line = value.replace(['\n', '\r'], " ");
+
+    if line.chars().count() > max {
+        line.chars()
+            .take(max.saturating_sub(1))
+            .chain(['…'])
+            .collect()
+    } else {
+        line
+    }
+}
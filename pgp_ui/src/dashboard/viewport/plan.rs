@@ -0,0 +1,163 @@
+use std::collections::BTreeSet;
+
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, Column};
+use iced::{theme, Alignment, Color, Command, Element, Length};
+
+use pgp_core::errors::Error;
+use pgp_core::plan::{PlanNode, QueryPlan};
+
+const INDENT: f32 = 18.0;
+const TREE_HEIGHT: f32 = 320.0;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+const HOT: Color = Color::from_rgb(0.85, 0.25, 0.2);
+
+/// The plan of a result's query, shown under its grid as a tree whose
+/// nodes can be folded. Hotspots are highlighted.
+#[derive(Debug)]
+pub struct Plan {
+    plan: QueryPlan,
+    /// Paths of the folded nodes, as child indexes from the root.
+    collapsed: BTreeSet<Vec<usize>>,
+    explanation: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Toggle(Vec<usize>),
+    Describe,
+    Described(Result<String, Error>),
+    Close,
+}
+
+impl Plan {
+    pub fn new(plan: QueryPlan) -> Self {
+        Self {
+            plan,
+            collapsed: BTreeSet::new(),
+            explanation: None,
+            status: None,
+        }
+    }
+
+    pub fn plan(&self) -> &QueryPlan {
+        &self.plan
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<Message> {
+        match message {
+            Message::Toggle(path) => {
+                if !self.collapsed.remove(&path) {
+                    self.collapsed.insert(path);
+                }
+            }
+            // Handled by the viewport, which owns the session.
+            Message::Describe => self.status = Some("Asking the model...".to_string()),
+            Message::Described(Ok(explanation)) => {
+                self.status = None;
+                self.explanation = Some(explanation);
+            }
+            Message::Described(Err(error)) => self.status = Some(error.to_string()),
+            Message::Close => {}
+        }
+
+        Command::none()
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let title = match (self.plan.analyzed, self.plan.execution_ms) {
+            (true, Some(execution)) => format!(
+                "Plan, analyzed: planning {:.2} ms, execution {:.2} ms",
+                self.plan.planning_ms.unwrap_or_default(),
+                execution
+            ),
+            _ => "Plan, estimated".to_string(),
+        };
+
+        let describe = button(text("Explain in words").size(14)).style(theme::Button::Secondary);
+        let describe = match (&self.explanation, &self.status) {
+            (None, None) => describe.on_press(Message::Describe),
+            _ => describe,
+        };
+
+        let header = row![
+            text(title).size(14),
+            describe,
+            button(text("Close").size(14))
+                .style(theme::Button::Secondary)
+                .on_press(Message::Close),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let header = match &self.status {
+            Some(status) => header.push(text(status).size(14).style(MUTED)),
+            None => header,
+        };
+
+        let tree = self.node(&self.plan.root, vec![], Column::new().spacing(2));
+        let tree = container(scrollable(tree).width(Length::Fill)).max_height(TREE_HEIGHT);
+
+        let content = column![header, tree].spacing(6);
+
+        match &self.explanation {
+            Some(explanation) => content.push(text(explanation).size(14)).into(),
+            None => content.into(),
+        }
+    }
+
+    /// Adds the row of a node and, unless it is folded, its children.
+    fn node<'a>(
+        &'a self,
+        node: &'a PlanNode,
+        path: Vec<usize>,
+        tree: Column<'a, Message>,
+    ) -> Column<'a, Message> {
+        let collapsed = self.collapsed.contains(&path);
+        let indent = horizontal_space(Length::Fixed(path.len() as f32 * INDENT));
+
+        let toggle = if node.children.is_empty() {
+            button(text(" ").size(14)).style(theme::Button::Text)
+        } else {
+            button(text(if collapsed { "▸" } else { "▾" }).size(14))
+                .style(theme::Button::Text)
+                .on_press(Message::Toggle(path.clone()))
+        };
+
+        let summary = text(node.summary()).size(14);
+        let summary = if node.hotspots.is_empty() {
+            summary
+        } else {
+            summary.style(HOT)
+        };
+
+        let mut details = column![summary];
+
+        if let Some(condition) = &node.condition {
+            details = details.push(text(condition).size(12).style(MUTED));
+        }
+        if !node.hotspots.is_empty() {
+            let hotspots: Vec<String> = node.hotspots.iter().map(|h| h.to_string()).collect();
+            details = details.push(
+                text(format!("Hotspot: {}", hotspots.join(", ")))
+                    .size(12)
+                    .style(HOT),
+            );
+        }
+
+        let tree = tree.push(row![indent, toggle, details].align_items(Alignment::Start));
+
+        if collapsed {
+            return tree;
+        }
+
+        node.children
+            .iter()
+            .enumerate()
+            .fold(tree, |tree, (i, child)| {
+                let mut path = path.clone();
+                path.push(i);
+                self.node(child, path, tree)
+            })
+    }
+}
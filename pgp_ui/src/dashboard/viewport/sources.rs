@@ -0,0 +1,73 @@
+use iced::widget::{button, column, row, text};
+use iced::{theme, Alignment, Color, Element};
+
+use pgp_core::grounding::{ClaimKind, Grounding};
+
+/// Longest query shown in full on a source line.
+const QUERY_WIDTH: usize = 120;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+const HOT: Color = Color::from_rgb(0.85, 0.25, 0.2);
+
+/// The results an answer came from, each with its SQL, and the numbers and
+/// names of the answer that none of them contain.
+pub fn view<'a, M: Clone + 'a>(grounding: &'a Grounding, open: fn(String) -> M) -> Element<'a, M> {
+    let sources = grounding
+        .sources
+        .iter()
+        .fold(column![].spacing(2), |sources, source| {
+            sources.push(
+                row![
+                    text(format!("Source: {} rows from", source.rows))
+                        .size(12)
+                        .style(MUTED),
+                    text(shorten(&source.query)).size(12),
+                    button(text("Open SQL").size(12))
+                        .padding([2, 6])
+                        .style(theme::Button::Secondary)
+                        .on_press(open(source.query.clone())),
+                ]
+                .spacing(8)
+                .align_items(Alignment::Center),
+            )
+        });
+
+    let unsupported: Vec<String> = grounding
+        .unsupported()
+        .map(|claim| match claim.kind {
+            ClaimKind::Number => claim.text.clone(),
+            ClaimKind::Entity => format!("\"{}\"", claim.text),
+        })
+        .collect();
+
+    let checked = grounding.claims.len();
+    let summary = if unsupported.is_empty() {
+        text(format!("All {} claims found in the results", checked))
+            .size(12)
+            .style(MUTED)
+    } else {
+        text(format!(
+            "Not found in the results: {}",
+            unsupported.join(", ")
+        ))
+        .size(12)
+        .style(HOT)
+    };
+
+    let sources = if checked > 0 {
+        sources.push(summary)
+    } else {
+        sources
+    };
+
+    sources.into()
+}
+
+/// The query on one line, cut to `QUERY_WIDTH` characters.
+fn shorten(query: &str) -> String {
+    let line = query.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match line.char_indices().nth(QUERY_WIDTH) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line,
+    }
+}
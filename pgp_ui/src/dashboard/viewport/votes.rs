@@ -0,0 +1,94 @@
+use iced::widget::{button, column, container, text, Column};
+use iced::{theme, Color, Element, Length};
+
+use pgp_core::results::ResultSet;
+use pgp_core::voting::Vote;
+
+const PREVIEW_ROWS: usize = 5;
+const MUTED: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+/// How the candidate queries of a result voted, with the results the others
+/// returned when `expanded`.
+pub fn view<'a, M: Clone + 'a>(vote: &'a Vote, expanded: bool, on_toggle: M) -> Element<'a, M> {
+    let disagreeing = vote.alternatives().len() + vote.failed.len();
+
+    let summary = text(format!("{}.", vote.summary())).size(14);
+
+    if disagreeing == 0 {
+        return summary.into();
+    }
+
+    let label = if expanded {
+        "Hide alternatives".to_string()
+    } else {
+        format!("Show alternatives ({})", disagreeing)
+    };
+    let toggle = button(text(label).size(14))
+        .style(theme::Button::Secondary)
+        .on_press(on_toggle);
+
+    let content = column![summary, toggle].spacing(6);
+
+    if !expanded {
+        return content.into();
+    }
+
+    let alternatives = vote
+        .alternatives()
+        .iter()
+        .fold(Column::new().spacing(10), |list, group| {
+            let agreeing = match group.queries.len() {
+                1 => "1 candidate query returned:".to_string(),
+                n => format!("{} candidate queries returned:", n),
+            };
+            let queries = group
+                .queries
+                .iter()
+                .fold(column![text(agreeing).size(14)], |queries, query| {
+                    queries.push(text(query).size(12).style(MUTED))
+                });
+
+            list.push(queries.push(text(preview(&group.result)).size(12)))
+        });
+
+    let failed = vote.failed.iter().fold(alternatives, |list, candidate| {
+        let error = candidate
+            .outcome
+            .as_ref()
+            .err()
+            .cloned()
+            .unwrap_or_default();
+        list.push(column![
+            text(format!("Failed: {}", error)).size(14),
+            text(&candidate.query).size(12).style(MUTED),
+        ])
+    });
+
+    content
+        .push(
+            container(failed)
+                .padding(10)
+                .width(Length::Fill)
+                .style(theme::Container::Box),
+        )
+        .into()
+}
+
+/// The first rows of a result as text, one line per row.
+fn preview(result: &ResultSet) -> String {
+    let header: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+    let mut lines = vec![header.join(" | ")];
+
+    lines.extend(result.rows.iter().take(PREVIEW_ROWS).map(|row| {
+        row.iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }));
+
+    if result.rows.len() > PREVIEW_ROWS {
+        lines.push(format!("... {} rows in all", result.rows.len()));
+    }
+
+    lines.join("\n")
+}
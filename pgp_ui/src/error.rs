@@ -37,6 +37,18 @@ impl ErrorExt for Error {
             Error::QueryError => column![text("Query error").size(18)]
                 .width(Length::Shrink)
                 .into(),
+            Error::Cancelled => column![text("Query cancelled").size(18)]
+                .width(Length::Shrink)
+                .into(),
+            Error::ExportError => column![text("Export error").size(18)]
+                .width(Length::Shrink)
+                .into(),
+            Error::AuditError => column![text("Audit log error").size(18)]
+                .width(Length::Shrink)
+                .into(),
+            Error::Rejected(_) | Error::Failed(_) => column![text(self.to_string()).size(18)]
+                .width(Length::Shrink)
+                .into(),
         }
     }
 }
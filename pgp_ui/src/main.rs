@@ -6,16 +6,64 @@ use error::ErrorExt;
 use iced::executor;
 use iced::widget::{self, button, column, container, row, text};
 use iced::window;
-use iced::{Alignment, Application, Command, Element, Length, Settings, Theme};
+use iced::{Alignment, Application, Command, Element, Length, Settings, Subscription, Theme};
 
+use std::path::PathBuf;
+
+use pgp_core::audit;
 use pgp_core::config::Config;
 use pgp_core::errors::Error;
 
 #[tokio::main]
 async fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("verify-audit") {
+        std::process::exit(verify_audit(args.get(2)).await);
+    }
+
     PgParrot::run(Settings::default())
 }
 
+/// Checks the audit log given, or the configured one, and prints what was
+/// found. The exit code is non-zero when the log was tampered with.
+async fn verify_audit(path: Option<&String>) -> i32 {
+    let settings = Config::new()
+        .await
+        .map(|config| config.audit)
+        .unwrap_or_default();
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => settings.path,
+    };
+
+    match audit::verify(&path, settings.key.as_deref()) {
+        Ok(report) => {
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            println!(
+                "{}: {} entries, last hash {}",
+                path.display(),
+                report.entries,
+                report.last_hash.as_deref().unwrap_or("-")
+            );
+
+            if report.is_valid() {
+                println!("OK");
+                0
+            } else {
+                println!("TAMPERED");
+                1
+            }
+        }
+        Err(_) => {
+            println!("{}: cannot be read", path.display());
+            2
+        }
+    }
+}
+
 #[derive(Debug)]
 enum PgParrot {
     Loading,
@@ -83,6 +131,13 @@ impl Application for PgParrot {
         }
     }
 
+    fn subscription(&self) -> Subscription<Message> {
+        match self {
+            PgParrot::Ready { dashboard } => dashboard.subscription().map(Message::Dashboard),
+            _ => Subscription::none(),
+        }
+    }
+
     fn view(&self) -> Element<Message> {
         let content = match self {
             PgParrot::Loading => column![text("Loading...").size(18),]